@@ -0,0 +1,299 @@
+use bitcoincore_rpc::bitcoin::util::amount::Denomination;
+use bitcoincore_rpc::bitcoin::{Address, Amount, Network};
+use std::fmt;
+
+const SCHEME: &str = "bitcoin:";
+
+/// A parsed BIP21 `bitcoin:` payment URI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentUri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+    pub lightning: Option<String>,
+    /// Optional parameters we don't interpret, kept in URI order.
+    pub extras: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bip21Error {
+    MissingScheme,
+    InvalidAddress(String),
+    InvalidAmount(String),
+    InvalidEncoding(String),
+    DuplicateParam(String),
+    /// A `req-` parameter this client does not understand, which BIP21 says
+    /// makes the whole URI invalid.
+    UnsupportedRequirement(String),
+    WrongNetwork {
+        address: Address,
+        chain: String,
+    },
+    /// A `chain` name from `getblockchaininfo` we don't know the network of.
+    UnknownChain(String),
+}
+
+impl fmt::Display for Bip21Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bip21Error::MissingScheme => write!(f, "URI does not start with `bitcoin:`"),
+            Bip21Error::InvalidAddress(a) => write!(f, "invalid address `{}`", a),
+            Bip21Error::InvalidAmount(a) => write!(f, "invalid amount `{}`", a),
+            Bip21Error::InvalidEncoding(s) => write!(f, "invalid percent-encoding in `{}`", s),
+            Bip21Error::DuplicateParam(k) => write!(f, "parameter `{}` appears more than once", k),
+            Bip21Error::UnsupportedRequirement(k) => {
+                write!(f, "unsupported required parameter `{}`", k)
+            }
+            Bip21Error::WrongNetwork { address, chain } => {
+                write!(
+                    f,
+                    "address {} is not valid on the node's `{}` chain",
+                    address, chain
+                )
+            }
+            Bip21Error::UnknownChain(chain) => write!(f, "unknown chain `{}`", chain),
+        }
+    }
+}
+
+impl std::error::Error for Bip21Error {}
+
+impl PaymentUri {
+    pub fn new(address: Address) -> Self {
+        PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            lightning: None,
+            extras: Vec::new(),
+        }
+    }
+
+    /// Checks the address against the `chain` field of `getblockchaininfo`.
+    pub fn check_chain(&self, chain: &str) -> Result<(), Bip21Error> {
+        let network = match chain {
+            "main" => Network::Bitcoin,
+            "test" | "testnet4" => Network::Testnet,
+            "signet" => Network::Signet,
+            "regtest" => Network::Regtest,
+            _ => return Err(Bip21Error::UnknownChain(chain.to_owned())),
+        };
+        if self.address.is_valid_for_network(network) {
+            Ok(())
+        } else {
+            Err(Bip21Error::WrongNetwork {
+                address: self.address.clone(),
+                chain: chain.to_owned(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address)?;
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!(
+                "amount={}",
+                amount.to_string_in(Denomination::Bitcoin)
+            ));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if let Some(lightning) = &self.lightning {
+            params.push(format!("lightning={}", percent_encode(lightning)));
+        }
+        for (key, value) in &self.extras {
+            params.push(format!("{}={}", key, percent_encode(value)));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `bitcoin:` URI. The scheme is matched case-insensitively and
+/// parameter values are percent-decoded.
+pub fn parse(uri: &str) -> Result<PaymentUri, Bip21Error> {
+    let uri = uri.trim();
+    if !uri
+        .get(..SCHEME.len())
+        .is_some_and(|s| s.eq_ignore_ascii_case(SCHEME))
+    {
+        return Err(Bip21Error::MissingScheme);
+    }
+    let rest = &uri[SCHEME.len()..];
+    let (addr_str, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let address: Address = addr_str
+        .parse()
+        .map_err(|_| Bip21Error::InvalidAddress(addr_str.to_owned()))?;
+    let mut result = PaymentUri::new(address);
+
+    let mut seen: Vec<String> = Vec::new();
+    for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+        let (key, raw_value) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+        let key = key.to_ascii_lowercase();
+        if seen.contains(&key) {
+            return Err(Bip21Error::DuplicateParam(key));
+        }
+        seen.push(key.clone());
+        let value = percent_decode(raw_value)?;
+
+        match key.as_str() {
+            "amount" => {
+                // BIP21 amounts are plain decimal BTC: no exponents, no separators.
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                    return Err(Bip21Error::InvalidAmount(value));
+                }
+                let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                    .map_err(|_| Bip21Error::InvalidAmount(value.clone()))?;
+                result.amount = Some(amount);
+            }
+            "label" => result.label = Some(value),
+            "message" => result.message = Some(value),
+            "lightning" => result.lightning = Some(value),
+            k if k.starts_with("req-") => {
+                return Err(Bip21Error::UnsupportedRequirement(k.to_owned()))
+            }
+            _ => result.extras.push((key, value)),
+        }
+    }
+    Ok(result)
+}
+
+fn percent_decode(s: &str) -> Result<String, Bip21Error> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // from_str_radix would also take a sign, as in `%+1`
+            let hex = s
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .ok_or_else(|| Bip21Error::InvalidEncoding(s.to_owned()))?;
+            let byte = u8::from_str_radix(hex, 16).expect("two hex digits");
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| Bip21Error::InvalidEncoding(s.to_owned()))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Examples from the BIP21 specification, whose own address fails its
+    // checksum; the genesis block's stands in for it.
+    const ADDRESS: &str = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+
+    #[test]
+    fn spec_examples() {
+        let uri = parse(&format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert_eq!(uri, PaymentUri::new(Address::from_str(ADDRESS).unwrap()));
+
+        let uri = parse(&format!("bitcoin:{}?label=Luke-Jr", ADDRESS)).unwrap();
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+
+        let uri = parse(&format!("bitcoin:{}?amount=20.3&label=Luke-Jr", ADDRESS)).unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000)));
+
+        let uri = parse(&format!(
+            "BITCOIN:{}?amount=50&label=Luke-Jr&message=Donation%20for%20project%20xyz",
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(5_000_000_000)));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(parse(&uri.to_string()).unwrap(), uri);
+
+        let uri = parse(&format!(
+            "bitcoin:{}?somethingyoudontunderstand=50&somethingelseyoudontget=999",
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(uri.extras.len(), 2);
+    }
+
+    #[test]
+    fn rejects_required_parameters() {
+        assert_eq!(
+            parse(&format!(
+                "bitcoin:{}?req-somethingyoudontunderstand=50&req-somethingelseyoudontget=999",
+                ADDRESS
+            )),
+            Err(Bip21Error::UnsupportedRequirement(
+                "req-somethingyoudontunderstand".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_uris() {
+        // Byte 8 falls inside the euro sign
+        assert_eq!(parse("bitcoin€x"), Err(Bip21Error::MissingScheme));
+        assert_eq!(parse("bitcoin"), Err(Bip21Error::MissingScheme));
+        assert!(matches!(
+            parse(&format!("bitcoin:{}?label=%+1", ADDRESS)),
+            Err(Bip21Error::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            parse(&format!("bitcoin:{}?label=%4", ADDRESS)),
+            Err(Bip21Error::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            parse(&format!("bitcoin:{}?amount=1e3", ADDRESS)),
+            Err(Bip21Error::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            parse(&format!("bitcoin:{}?label=a&LABEL=b", ADDRESS)),
+            Err(Bip21Error::DuplicateParam(_))
+        ));
+    }
+
+    #[test]
+    fn checks_chain() {
+        let uri = parse(&format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert_eq!(uri.check_chain("main"), Ok(()));
+        assert!(matches!(
+            uri.check_chain("regtest"),
+            Err(Bip21Error::WrongNetwork { .. })
+        ));
+        assert_eq!(
+            uri.check_chain("mainnet"),
+            Err(Bip21Error::UnknownChain("mainnet".to_owned()))
+        );
+    }
+}
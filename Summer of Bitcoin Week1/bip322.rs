@@ -0,0 +1,444 @@
+//! BIP322 generic message signing for segwit v0 and taproot addresses,
+//! which the node's legacy `signmessage` cannot sign for.
+
+use bitcoincore_rpc::bitcoin::blockdata::opcodes;
+use bitcoincore_rpc::bitcoin::blockdata::script::Builder;
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoincore_rpc::bitcoin::secp256k1::{self, ecdsa, schnorr, Message, Secp256k1};
+use bitcoincore_rpc::bitcoin::secp256k1::{KeyPair, XOnlyPublicKey};
+use bitcoincore_rpc::bitcoin::util::address::{Payload, WitnessVersion};
+use bitcoincore_rpc::bitcoin::util::schnorr::TapTweak;
+use bitcoincore_rpc::bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoincore_rpc::bitcoin::{
+    Address, EcdsaSighashType, OutPoint, PrivateKey, PublicKey, SchnorrSighashType, Script,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use std::fmt;
+
+const TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Base64 of the consensus-encoded witness stack.
+    Simple,
+    /// Base64 of the whole signed `to_sign` transaction.
+    Full,
+}
+
+#[derive(Debug)]
+pub enum Bip322Error {
+    UnsupportedAddress(Address),
+    /// The address is P2SH-P2WPKH, whose scriptSig only the full format carries.
+    NeedsFullFormat,
+    KeyMismatch,
+    InvalidEncoding,
+    Malformed(&'static str),
+    InvalidSignature,
+    Sighash(String),
+    Secp(secp256k1::Error),
+}
+
+impl fmt::Display for Bip322Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bip322Error::UnsupportedAddress(a) => write!(f, "unsupported address type: {}", a),
+            Bip322Error::NeedsFullFormat => write!(f, "P2SH-P2WPKH needs the full format"),
+            Bip322Error::KeyMismatch => write!(f, "key does not belong to the address"),
+            Bip322Error::InvalidEncoding => write!(f, "signature is not valid base64"),
+            Bip322Error::Malformed(what) => write!(f, "malformed signature: {}", what),
+            Bip322Error::InvalidSignature => write!(f, "signature does not verify"),
+            Bip322Error::Sighash(e) => write!(f, "sighash error: {}", e),
+            Bip322Error::Secp(e) => write!(f, "secp256k1 error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Bip322Error {}
+
+impl From<secp256k1::Error> for Bip322Error {
+    fn from(e: secp256k1::Error) -> Self {
+        Bip322Error::Secp(e)
+    }
+}
+
+/// The address kinds we can sign and verify for.
+#[derive(PartialEq, Eq)]
+enum Kind {
+    P2wpkh,
+    P2shP2wpkh,
+    P2tr,
+}
+
+fn kind(address: &Address) -> Result<Kind, Bip322Error> {
+    match &address.payload {
+        Payload::WitnessProgram {
+            version: WitnessVersion::V0,
+            program,
+        } if program.len() == 20 => Ok(Kind::P2wpkh),
+        Payload::WitnessProgram {
+            version: WitnessVersion::V1,
+            program,
+        } if program.len() == 32 => Ok(Kind::P2tr),
+        Payload::ScriptHash(_) => Ok(Kind::P2shP2wpkh),
+        _ => Err(Bip322Error::UnsupportedAddress(address.clone())),
+    }
+}
+
+/// `SHA256(SHA256(tag) || SHA256(tag) || message)` with the BIP322 tag.
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual transaction whose only output the signature spends.
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    let script_sig = Builder::new()
+        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
+        .push_slice(&message_hash(message)[..])
+        .into_script();
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::default(), 0xFFFF_FFFF),
+            script_sig,
+            sequence: 0,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The unsigned virtual transaction carrying the signature.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: 0,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+fn p2wpkh_sighash(
+    to_sign: &Transaction,
+    pubkey: &PublicKey,
+    sighash_type: EcdsaSighashType,
+) -> Result<Message, Bip322Error> {
+    let wpkh = pubkey
+        .wpubkey_hash()
+        .ok_or(Bip322Error::Malformed("uncompressed key"))?;
+    let script_code = Script::new_p2pkh(&wpkh.as_hash().into());
+    let sighash = SighashCache::new(to_sign)
+        .segwit_signature_hash(0, &script_code, 0, sighash_type)
+        .map_err(|e| Bip322Error::Sighash(e.to_string()))?;
+    Ok(Message::from_slice(&sighash[..])?)
+}
+
+fn taproot_sighash(
+    to_sign: &Transaction,
+    prevout: &TxOut,
+    sighash_type: SchnorrSighashType,
+) -> Result<Message, Bip322Error> {
+    let prevouts = [prevout.clone()];
+    let sighash = SighashCache::new(to_sign)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), sighash_type)
+        .map_err(|e| Bip322Error::Sighash(e.to_string()))?;
+    Ok(Message::from_slice(&sighash[..])?)
+}
+
+fn p2sh_p2wpkh_redeem_script(pubkey: &PublicKey) -> Result<Script, Bip322Error> {
+    let wpkh = pubkey
+        .wpubkey_hash()
+        .ok_or(Bip322Error::Malformed("uncompressed key"))?;
+    Ok(Script::new_v0_p2wpkh(&wpkh))
+}
+
+/// Signs `message` for `address` with `key` and returns the base64 signature.
+pub fn sign(
+    key: &PrivateKey,
+    address: &Address,
+    message: &[u8],
+    format: Format,
+) -> Result<String, Bip322Error> {
+    let secp = Secp256k1::new();
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let mut to_sign = to_sign(&to_spend);
+    let pubkey = key.public_key(&secp);
+
+    let kind = kind(address)?;
+    let witness = match kind {
+        Kind::P2wpkh | Kind::P2shP2wpkh => {
+            if kind == Kind::P2shP2wpkh {
+                if format == Format::Simple {
+                    return Err(Bip322Error::NeedsFullFormat);
+                }
+                let redeem_script = p2sh_p2wpkh_redeem_script(&pubkey)?;
+                if Script::new_p2sh(&redeem_script.script_hash()) != address.script_pubkey() {
+                    return Err(Bip322Error::KeyMismatch);
+                }
+                to_sign.input[0].script_sig = Builder::new()
+                    .push_slice(redeem_script.as_bytes())
+                    .into_script();
+            } else if !address.is_related_to_pubkey(&pubkey) {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let msg = p2wpkh_sighash(&to_sign, &pubkey, EcdsaSighashType::All)?;
+            let mut sig = secp
+                .sign_ecdsa_low_r(&msg, &key.inner)
+                .serialize_der()
+                .to_vec();
+            sig.push(EcdsaSighashType::All as u8);
+            Witness::from_vec(vec![sig, pubkey.to_bytes()])
+        }
+        Kind::P2tr => {
+            let keypair = KeyPair::from_secret_key(&secp, key.inner);
+            let tweaked = keypair.tap_tweak(&secp, None).into_inner();
+            let output_key = XOnlyPublicKey::from_keypair(&tweaked);
+            if Script::new_v1_p2tr_tweaked(output_key.dangerous_assume_tweaked())
+                != address.script_pubkey()
+            {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let msg = taproot_sighash(&to_sign, &to_spend.output[0], SchnorrSighashType::Default)?;
+            let sig = secp.sign_schnorr_no_aux_rand(&msg, &tweaked);
+            Witness::from_vec(vec![sig.as_ref().to_vec()])
+        }
+    };
+    to_sign.input[0].witness = witness;
+
+    let bytes = match format {
+        Format::Simple => serialize(&to_sign.input[0].witness),
+        Format::Full => serialize(&to_sign),
+    };
+    Ok(base64::encode(bytes))
+}
+
+/// Checks a simple or full BIP322 signature of `message` by `address`.
+pub fn verify(address: &Address, message: &[u8], signature: &str) -> Result<(), Bip322Error> {
+    let bytes = base64::decode(signature.trim()).map_err(|_| Bip322Error::InvalidEncoding)?;
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let expected = to_sign(&to_spend);
+
+    let to_sign = match deserialize::<Transaction>(&bytes) {
+        Ok(tx) => {
+            if tx.input.len() != 1
+                || tx.input[0].previous_output != expected.input[0].previous_output
+            {
+                return Err(Bip322Error::Malformed("to_sign must spend to_spend:0 only"));
+            }
+            if tx.output != expected.output {
+                return Err(Bip322Error::Malformed(
+                    "to_sign must have a single empty OP_RETURN",
+                ));
+            }
+            tx
+        }
+        Err(_) => {
+            let witness: Witness =
+                deserialize(&bytes).map_err(|_| Bip322Error::Malformed("not a witness stack"))?;
+            let mut tx = expected;
+            tx.input[0].witness = witness;
+            tx
+        }
+    };
+    let witness: Vec<Vec<u8>> = to_sign.input[0].witness.to_vec();
+
+    let kind = kind(address)?;
+    match kind {
+        Kind::P2wpkh | Kind::P2shP2wpkh => {
+            if witness.len() != 2 || witness[0].is_empty() {
+                return Err(Bip322Error::Malformed("expected [signature, pubkey]"));
+            }
+            let pubkey = PublicKey::from_slice(&witness[1])
+                .map_err(|_| Bip322Error::Malformed("invalid pubkey"))?;
+            if kind == Kind::P2shP2wpkh {
+                let redeem_script = p2sh_p2wpkh_redeem_script(&pubkey)?;
+                let script_sig = Builder::new()
+                    .push_slice(redeem_script.as_bytes())
+                    .into_script();
+                if to_sign.input[0].script_sig != script_sig
+                    || Script::new_p2sh(&redeem_script.script_hash()) != address.script_pubkey()
+                {
+                    return Err(Bip322Error::KeyMismatch);
+                }
+            } else if !to_sign.input[0].script_sig.is_empty() {
+                return Err(Bip322Error::Malformed("scriptSig must be empty"));
+            } else if !address.is_related_to_pubkey(&pubkey) {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let (der, hash_byte) = witness[0].split_at(witness[0].len() - 1);
+            let sighash_type = EcdsaSighashType::from_standard(hash_byte[0] as u32)
+                .map_err(|_| Bip322Error::Malformed("non-standard sighash type"))?;
+            let sig = ecdsa::Signature::from_der(der)
+                .map_err(|_| Bip322Error::Malformed("invalid DER signature"))?;
+            let msg = p2wpkh_sighash(&to_sign, &pubkey, sighash_type)?;
+            Secp256k1::verification_only()
+                .verify_ecdsa(&msg, &sig, &pubkey.inner)
+                .map_err(|_| Bip322Error::InvalidSignature)
+        }
+        Kind::P2tr => {
+            if !to_sign.input[0].script_sig.is_empty() {
+                return Err(Bip322Error::Malformed("scriptSig must be empty"));
+            }
+            if witness.len() != 1 {
+                return Err(Bip322Error::Malformed(
+                    "expected a single key-path signature",
+                ));
+            }
+            let sig_bytes = &witness[0];
+            let sighash_type = match sig_bytes.len() {
+                64 => SchnorrSighashType::Default,
+                65 if sig_bytes[64] != 0 => SchnorrSighashType::from_u8(sig_bytes[64])
+                    .map_err(|_| Bip322Error::Malformed("invalid sighash type"))?,
+                _ => return Err(Bip322Error::Malformed("invalid schnorr signature length")),
+            };
+            let sig = schnorr::Signature::from_slice(&sig_bytes[..64])
+                .map_err(|_| Bip322Error::Malformed("invalid schnorr signature"))?;
+            let output_key = match &address.payload {
+                Payload::WitnessProgram { program, .. } => XOnlyPublicKey::from_slice(program)
+                    .map_err(|_| Bip322Error::Malformed("invalid output key"))?,
+                _ => unreachable!("kind() only returns P2tr for witness v1 programs"),
+            };
+            let msg = taproot_sighash(&to_sign, &to_spend.output[0], sighash_type)?;
+            Secp256k1::verification_only()
+                .verify_schnorr(&sig, &msg, &output_key)
+                .map_err(|_| Bip322Error::InvalidSignature)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::Network;
+    use std::str::FromStr;
+
+    // Vectors from the BIP322 specification.
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    #[test]
+    fn message_hashes() {
+        assert_eq!(
+            message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn to_spend_and_to_sign_txids() {
+        let address = Address::from_str(P2WPKH).unwrap();
+        let spend = to_spend(&address.script_pubkey(), b"");
+        assert_eq!(
+            spend.txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(&spend).txid().to_string(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+
+        let spend = to_spend(&address.script_pubkey(), b"Hello World");
+        assert_eq!(
+            spend.txid().to_string(),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+        assert_eq!(
+            to_sign(&spend).txid().to_string(),
+            "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf"
+        );
+    }
+
+    #[test]
+    fn p2wpkh_vectors_verify_and_sign_byte_for_byte() {
+        let address = Address::from_str(P2WPKH).unwrap();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let vectors = [
+            ("", "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+            ("Hello World", "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+        ];
+        for (message, signature) in vectors {
+            verify(&address, message.as_bytes(), signature).unwrap();
+            assert_eq!(
+                sign(&key, &address, message.as_bytes(), Format::Simple).unwrap(),
+                signature
+            );
+        }
+        let signature = vectors[1].1;
+        assert!(verify(&address, b"Hello World!", signature).is_err());
+    }
+
+    #[test]
+    fn p2tr_vector_verifies() {
+        let address = Address::from_str(P2TR).unwrap();
+        let signature = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        verify(&address, b"Hello World", signature).unwrap();
+        assert!(verify(&address, b"", signature).is_err());
+    }
+
+    #[test]
+    fn round_trips_in_both_formats() {
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        for addr in [P2WPKH, P2TR] {
+            let address = Address::from_str(addr).unwrap();
+            for format in [Format::Simple, Format::Full] {
+                let signature = sign(&key, &address, b"proof of ownership", format).unwrap();
+                verify(&address, b"proof of ownership", &signature).unwrap();
+            }
+        }
+
+        let secp = Secp256k1::new();
+        let wrapped = Address::p2shwpkh(&key.public_key(&secp), Network::Bitcoin).unwrap();
+        assert!(matches!(
+            sign(&key, &wrapped, b"m", Format::Simple),
+            Err(Bip322Error::NeedsFullFormat)
+        ));
+        let signature = sign(&key, &wrapped, b"m", Format::Full).unwrap();
+        verify(&wrapped, b"m", &signature).unwrap();
+    }
+
+    #[test]
+    fn native_segwit_rejects_a_script_sig() {
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        for addr in [P2WPKH, P2TR] {
+            let address = Address::from_str(addr).unwrap();
+            let signature = sign(&key, &address, b"m", Format::Full).unwrap();
+            let mut tx: Transaction = deserialize(&base64::decode(&signature).unwrap()).unwrap();
+            // Neither sighash commits to the scriptSig, so only this check
+            // stops it being stuffed
+            tx.input[0].script_sig = Builder::new().push_int(1).into_script();
+            let stuffed = base64::encode(serialize(&tx));
+            assert!(
+                matches!(
+                    verify(&address, b"m", &stuffed),
+                    Err(Bip322Error::Malformed("scriptSig must be empty"))
+                ),
+                "{}",
+                addr
+            );
+        }
+    }
+}
@@ -1,17 +1,40 @@
 #![allow(unused)]
+mod bip21;
+mod bip322;
+mod http;
+mod message;
+mod regtest;
+mod rest;
+mod webhook;
+
+use bitcoincore_rpc::bitcoin::util::amount::Denomination;
+use bitcoincore_rpc::bitcoin::Amount;
 use bitcoincore_rpc::{Auth, Client, RpcApi};
 use serde::Deserialize;
 use serde_json::json;
+use std::error::Error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufRead, Write};
 
 const RPC_URL: &str = "http://127.0.0.1:18443";
 const RPC_USER: &str = "alice";
 const RPC_PASS: &str = "password";
+const WALLET_NAME: &str = "testwallet";
+
+fn connect(url: &str) -> bitcoincore_rpc::Result<Client> {
+    Client::new(
+        url,
+        Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
+    )
+}
 
-fn send(rpc: &Client, addr: &str) -> bitcoincore_rpc::Result<String> {
+fn wallet_client(name: &str) -> bitcoincore_rpc::Result<Client> {
+    connect(&format!("{}/wallet/{}", RPC_URL, name))
+}
+
+fn send(rpc: &Client, addr: &str, amount: Amount) -> bitcoincore_rpc::Result<String> {
     let args = [
-        json!([{ addr: 100 }]),
+        json!([{ addr: amount.as_btc() }]),
         json!(null),
         json!(null),
         json!(null),
@@ -40,38 +63,120 @@ fn list_wallet_dir(client: &Client) -> bitcoincore_rpc::Result<Vec<String>> {
     Ok(result.wallets.into_iter().map(|n| n.name).collect())
 }
 
-fn main() -> bitcoincore_rpc::Result<()> {
-    let rpc = Client::new(
-        RPC_URL,
-        Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
-    )?;
+/// Pays a BIP21 URI from the test wallet after asking for confirmation.
+/// An amount given on the command line is used when the URI carries none.
+fn pay_uri(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let uri = args
+        .first()
+        .ok_or("usage: pay <bitcoin-uri> [amount-btc]")?;
+    let payment = bip21::parse(uri)?;
+
+    let rpc = connect(RPC_URL)?;
+    let chain = rpc.get_blockchain_info()?.chain;
+    payment.check_chain(&chain)?;
+
+    let amount = match (payment.amount, args.get(1)) {
+        (Some(amount), _) => amount,
+        (None, Some(a)) => Amount::from_str_in(a, Denomination::Bitcoin)?,
+        (None, None) => return Err("URI has no amount; pass one after the URI".into()),
+    };
+
+    println!("Pay {} to {}", amount, payment.address);
+    if let Some(label) = &payment.label {
+        println!("  label:   {}", label);
+    }
+    if let Some(message) = &payment.message {
+        println!("  message: {}", message);
+    }
+    if let Some(lightning) = &payment.lightning {
+        println!("  lightning invoice ignored: {}", lightning);
+    }
+    print!("Send? [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    let wallet_rpc = wallet_client(WALLET_NAME)?;
+    let txid = send(&wallet_rpc, &payment.address.to_string(), amount)?;
+    println!("Txid: {}", txid);
+    Ok(())
+}
+
+/// Prints a BIP21 URI for a fresh address from the test wallet.
+fn request_uri(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut amount = None;
+    let mut label = None;
+    let mut message = None;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--amount" => amount = Some(Amount::from_str_in(value, Denomination::Bitcoin)?),
+            "--label" => label = Some(value.clone()),
+            "--message" => message = Some(value.clone()),
+            _ => return Err(format!("unknown flag {}", flag).into()),
+        }
+    }
+
+    let wallet_rpc = wallet_client(WALLET_NAME)?;
+    let address = wallet_rpc.get_new_address(label.as_deref(), None)?;
+    let mut payment = bip21::PaymentUri::new(address);
+    payment.amount = amount;
+    payment.label = label;
+    payment.message = message;
+    println!("{}", payment);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("pay") => return pay_uri(&args[1..]),
+        Some("request") => return request_uri(&args[1..]),
+        Some("watch") => return webhook::run(&args[1..]),
+        Some("message") => return message::run(&args[1..]),
+        Some("chain") => return rest::run(&args[1..]),
+        _ => {}
+    }
+
+    let rpc = connect(RPC_URL)?;
     let info = rpc.get_blockchain_info()?;
     println!("{:?}", info);
 
     //Create or load the wallet
     let wallets = list_wallet_dir(&rpc)?;
-    if !wallets.contains(&"testwallet".to_string()) {
-        let create_wallet: serde_json::Value =
-            rpc.call("createwallet", &[json!("testwallet")])?;
+    if !wallets.contains(&WALLET_NAME.to_string()) {
+        let create_wallet: serde_json::Value = rpc.call("createwallet", &[json!(WALLET_NAME)])?;
         println!("{:?}", create_wallet);
     } else {
         println!("Wallet exists");
     }
-    let wallet_url = format!("{}/wallet/{}", RPC_URL, "testwallet");
-    let wallet_rpc = Client::new(
-        &wallet_url,
-        Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
-    )?;
+    let wallet_rpc = wallet_client(WALLET_NAME)?;
 
     //Generate a new address
     let new_addr = wallet_rpc.get_new_address(None, None)?;
     println!("{:?}", new_addr);
-    let checked_addr: bitcoincore_rpc::bitcoin::Address =
-        new_addr.to_string().parse().expect("Failed to parse address");
+    let checked_addr: bitcoincore_rpc::bitcoin::Address = new_addr
+        .to_string()
+        .parse()
+        .expect("Failed to parse address");
 
     //Mine 103 blocks to the new address
-    let blocks = wallet_rpc.generate_to_address(103, &checked_addr)?;
-    println!("Mined {} blocks", blocks.len());
+    let blocks = regtest::generate_to_address(&wallet_rpc, 103, &checked_addr)?;
+    if let Some(last) = blocks.last() {
+        println!(
+            "Mined {} blocks, tip {} at height {}",
+            blocks.len(),
+            last.hash,
+            last.height
+        );
+    }
 
     //Prepare a transaction to send 100 BTC with an OP_RETURN output
     let op_return_hex = "57652061726520616c6c205361746f7368692121";
@@ -79,8 +184,7 @@ fn main() -> bitcoincore_rpc::Result<()> {
         "bcrt1qq2yshcmzdlznnpxx258xswqlmqcxjs4dssfxt2": 100.0,
         "data": op_return_hex
     });
-    let raw_tx: String =
-        wallet_rpc.call("createrawtransaction", &[json!([]), outputs])?;
+    let raw_tx: String = wallet_rpc.call("createrawtransaction", &[json!([]), outputs])?;
     println!("Raw tx: {}", raw_tx);
 
     #[derive(Deserialize)]
@@ -105,8 +209,7 @@ fn main() -> bitcoincore_rpc::Result<()> {
     println!("Signed tx: {}", sign_result.hex);
 
     //Send the transaction
-    let txid: String =
-        wallet_rpc.call("sendrawtransaction", &[json!(sign_result.hex)])?;
+    let txid: String = wallet_rpc.call("sendrawtransaction", &[json!(sign_result.hex)])?;
     println!("Txid: {}", txid);
 
     //Write the txid to out.txt
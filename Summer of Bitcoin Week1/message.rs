@@ -0,0 +1,246 @@
+use crate::bip322::{self, Format};
+use bitcoincore_rpc::bitcoin::util::address::Payload;
+use bitcoincore_rpc::bitcoin::{Address, PrivateKey};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde_json::json;
+use std::error::Error;
+
+const USAGE: &str = "usage:
+  message sign <address> <message>
+  message sign <address> <message> --wif <key> [--full]
+  message verify <address> <signature> <message>";
+
+/// Legacy `signmessage` through the wallet. Only works for P2PKH addresses.
+pub fn sign_message(
+    wallet_rpc: &Client,
+    address: &Address,
+    message: &str,
+) -> bitcoincore_rpc::Result<String> {
+    wallet_rpc.call("signmessage", &[json!(address.to_string()), json!(message)])
+}
+
+/// Legacy `verifymessage` through the node.
+pub fn verify_message(
+    rpc: &Client,
+    address: &Address,
+    signature: &str,
+    message: &str,
+) -> bitcoincore_rpc::Result<bool> {
+    rpc.call(
+        "verifymessage",
+        &[json!(address.to_string()), json!(signature), json!(message)],
+    )
+}
+
+fn is_p2pkh(address: &Address) -> bool {
+    matches!(address.payload, Payload::PubkeyHash(_))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Sign {
+        address: Address,
+        message: String,
+        /// Signs locally with BIP322 instead of through the wallet.
+        wif: Option<String>,
+        format: Format,
+    },
+    Verify {
+        address: Address,
+        signature: String,
+        message: String,
+    },
+}
+
+/// How a message is signed or verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// The node's `signmessage`/`verifymessage`, for P2PKH only.
+    Legacy,
+    Bip322,
+}
+
+impl Command {
+    pub fn from_args(args: &[String]) -> Result<Command, Box<dyn Error>> {
+        let (command, rest) = args.split_first().ok_or(USAGE)?;
+        match command.as_str() {
+            "sign" => {
+                let address = rest.first().ok_or(USAGE)?.parse()?;
+                let message = rest.get(1).ok_or(USAGE)?.clone();
+                let mut wif = None;
+                let mut format = Format::Simple;
+                let mut iter = rest[2..].iter();
+                while let Some(flag) = iter.next() {
+                    match flag.as_str() {
+                        "--wif" => wif = Some(iter.next().ok_or(USAGE)?.clone()),
+                        "--full" => format = Format::Full,
+                        _ => return Err(USAGE.into()),
+                    }
+                }
+                Ok(Command::Sign {
+                    address,
+                    message,
+                    wif,
+                    format,
+                })
+            }
+            "verify" => {
+                if rest.len() != 3 {
+                    return Err(USAGE.into());
+                }
+                Ok(Command::Verify {
+                    address: rest[0].parse()?,
+                    signature: rest[1].clone(),
+                    message: rest[2].clone(),
+                })
+            }
+            _ => Err(USAGE.into()),
+        }
+    }
+
+    /// The node handles P2PKH, unless signing with a key given here;
+    /// everything else is BIP322.
+    pub fn scheme(&self) -> Scheme {
+        match self {
+            Command::Sign {
+                address, wif: None, ..
+            }
+            | Command::Verify { address, .. }
+                if is_p2pkh(address) =>
+            {
+                Scheme::Legacy
+            }
+            _ => Scheme::Bip322,
+        }
+    }
+}
+
+/// Signs with the node for P2PKH addresses and locally with BIP322 for
+/// everything else, which needs the key as WIF.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let command = Command::from_args(args)?;
+    let scheme = command.scheme();
+    match command {
+        Command::Sign {
+            address,
+            message,
+            wif,
+            format,
+        } => {
+            let signature = match (scheme, wif) {
+                (Scheme::Legacy, _) => sign_message(
+                    &crate::wallet_client(crate::WALLET_NAME)?,
+                    &address,
+                    &message,
+                )?,
+                (Scheme::Bip322, Some(wif)) => {
+                    let key = PrivateKey::from_wif(&wif)?;
+                    bip322::sign(&key, &address, message.as_bytes(), format)?
+                }
+                (Scheme::Bip322, None) => return Err("BIP322 signing needs --wif <key>".into()),
+            };
+            println!("{}", signature);
+        }
+        Command::Verify {
+            address,
+            signature,
+            message,
+        } => {
+            let valid = match scheme {
+                Scheme::Legacy => verify_message(
+                    &crate::connect(crate::RPC_URL)?,
+                    &address,
+                    &signature,
+                    &message,
+                )?,
+                Scheme::Bip322 => match bip322::verify(&address, message.as_bytes(), &signature) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("{}", e);
+                        false
+                    }
+                },
+            };
+            println!("{}", if valid { "valid" } else { "invalid" });
+            if !valid {
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P2PKH: &str = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+
+    fn parse(args: &[&str]) -> Result<Command, Box<dyn Error>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::from_args(&args)
+    }
+
+    #[test]
+    fn parses_sign_and_verify() {
+        assert_eq!(
+            parse(&["sign", P2WPKH, "hi", "--wif", WIF, "--full"]).unwrap(),
+            Command::Sign {
+                address: P2WPKH.parse().unwrap(),
+                message: "hi".to_owned(),
+                wif: Some(WIF.to_owned()),
+                format: Format::Full,
+            }
+        );
+        assert_eq!(
+            parse(&["sign", P2PKH, "hi"]).unwrap(),
+            Command::Sign {
+                address: P2PKH.parse().unwrap(),
+                message: "hi".to_owned(),
+                wif: None,
+                format: Format::Simple,
+            }
+        );
+        assert_eq!(
+            parse(&["verify", P2TR, "c2ln", "hi"]).unwrap(),
+            Command::Verify {
+                address: P2TR.parse().unwrap(),
+                signature: "c2ln".to_owned(),
+                message: "hi".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for args in [
+            &[][..],
+            &["sign"],
+            &["sign", P2WPKH],
+            &["sign", P2WPKH, "hi", "--wif"],
+            &["sign", P2WPKH, "hi", "--simple"],
+            &["sign", "not-an-address", "hi"],
+            &["verify", P2WPKH, "c2ln"],
+            &["verify", P2WPKH, "c2ln", "hi", "extra"],
+            &["recover", P2WPKH, "hi"],
+        ] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn p2pkh_goes_to_the_node_and_the_rest_to_bip322() {
+        let scheme = |args: &[&str]| parse(args).unwrap().scheme();
+        assert_eq!(scheme(&["sign", P2PKH, "hi"]), Scheme::Legacy);
+        assert_eq!(scheme(&["verify", P2PKH, "c2ln", "hi"]), Scheme::Legacy);
+        // A key given here is used directly rather than the wallet's
+        assert_eq!(scheme(&["sign", P2PKH, "hi", "--wif", WIF]), Scheme::Bip322);
+        for address in [P2WPKH, P2TR] {
+            assert_eq!(scheme(&["sign", address, "hi"]), Scheme::Bip322);
+            assert_eq!(scheme(&["verify", address, "c2ln", "hi"]), Scheme::Bip322);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use bitcoincore_rpc::bitcoin::{Address, BlockHash};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use serde_json::json;
+use std::io;
+
+/// A block identified by hash together with its height.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRef {
+    pub hash: BlockHash,
+    pub height: u64,
+}
+
+/// The blocks a simulated reorg took off and put on the active chain.
+#[derive(Debug, Clone)]
+pub struct Reorg {
+    pub disconnected: Vec<BlockRef>,
+    pub connected: Vec<BlockRef>,
+}
+
+fn block_ref(rpc: &Client, hash: BlockHash) -> bitcoincore_rpc::Result<BlockRef> {
+    let header = rpc.get_block_header_info(&hash)?;
+    Ok(BlockRef {
+        hash,
+        height: header.height as u64,
+    })
+}
+
+fn block_refs(rpc: &Client, hashes: Vec<BlockHash>) -> bitcoincore_rpc::Result<Vec<BlockRef>> {
+    hashes.into_iter().map(|h| block_ref(rpc, h)).collect()
+}
+
+pub fn tip(rpc: &Client) -> bitcoincore_rpc::Result<BlockRef> {
+    let hash = rpc.get_best_block_hash()?;
+    block_ref(rpc, hash)
+}
+
+pub fn generate_to_address(
+    rpc: &Client,
+    count: u64,
+    address: &Address,
+) -> bitcoincore_rpc::Result<Vec<BlockRef>> {
+    let hashes = rpc.generate_to_address(count, address)?;
+    block_refs(rpc, hashes)
+}
+
+/// Mines `count` blocks paying the coinbase to `descriptor`, which must be a
+/// non-ranged descriptor with its checksum, e.g. from `getdescriptorinfo`.
+pub fn generate_to_descriptor(
+    rpc: &Client,
+    count: u64,
+    descriptor: &str,
+) -> bitcoincore_rpc::Result<Vec<BlockRef>> {
+    let hashes: Vec<BlockHash> =
+        rpc.call("generatetodescriptor", &[json!(count), json!(descriptor)])?;
+    block_refs(rpc, hashes)
+}
+
+/// Mines one block containing exactly `transactions`, in that order, and
+/// nothing from the mempool. Each entry is a txid already in the mempool or
+/// a raw transaction hex. `output` is an address or a descriptor.
+pub fn generate_block(
+    rpc: &Client,
+    output: &str,
+    transactions: &[String],
+) -> bitcoincore_rpc::Result<BlockRef> {
+    #[derive(Deserialize)]
+    struct GenerateBlockResult {
+        hash: BlockHash,
+    }
+    let result: GenerateBlockResult =
+        rpc.call("generateblock", &[json!(output), json!(transactions)])?;
+    block_ref(rpc, result.hash)
+}
+
+/// Marks `hash` and its descendants invalid and returns the new tip.
+pub fn invalidate_block(rpc: &Client, hash: &BlockHash) -> bitcoincore_rpc::Result<BlockRef> {
+    rpc.invalidate_block(hash)?;
+    tip(rpc)
+}
+
+/// Undoes `invalidate_block` and returns the tip after the node re-evaluates.
+pub fn reconsider_block(rpc: &Client, hash: &BlockHash) -> bitcoincore_rpc::Result<BlockRef> {
+    rpc.reconsider_block(hash)?;
+    tip(rpc)
+}
+
+/// Replaces the top `depth` blocks with `depth + 1` new ones paying to
+/// `address`, so the new branch wins. Use a different address (or move the
+/// mock time) from the original chain, otherwise the node may rebuild an
+/// identical block it already marked invalid.
+pub fn reorg(rpc: &Client, depth: u64, address: &Address) -> bitcoincore_rpc::Result<Reorg> {
+    let old_tip = tip(rpc)?;
+    let fork_height = fork_height(old_tip.height, depth)?;
+    let mut disconnected = Vec::new();
+    for height in fork_height..=old_tip.height {
+        disconnected.push(BlockRef {
+            hash: rpc.get_block_hash(height)?,
+            height,
+        });
+    }
+    if let Some(first) = disconnected.first() {
+        rpc.invalidate_block(&first.hash)?;
+    }
+    let connected = generate_to_address(rpc, depth + 1, address)?;
+    Ok(Reorg {
+        disconnected,
+        connected,
+    })
+}
+
+/// The height of the first block a reorg of `depth` takes off a chain
+/// whose tip is at `tip_height`. The genesis block can't be replaced.
+fn fork_height(tip_height: u64, depth: u64) -> bitcoincore_rpc::Result<u64> {
+    if depth == 0 || depth > tip_height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot reorg {} block(s) off a chain of height {}",
+                depth, tip_height
+            ),
+        )
+        .into());
+    }
+    Ok(tip_height + 1 - depth)
+}
+
+/// Controls the node clock through `setmocktime`. Regtest only.
+pub struct MockClock<'a> {
+    rpc: &'a Client,
+    now: u64,
+}
+
+impl<'a> MockClock<'a> {
+    pub fn start(rpc: &'a Client, now: u64) -> bitcoincore_rpc::Result<Self> {
+        let clock = MockClock { rpc, now };
+        clock.apply()?;
+        Ok(clock)
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn set(&mut self, now: u64) -> bitcoincore_rpc::Result<()> {
+        self.now = now;
+        self.apply()
+    }
+
+    pub fn advance(&mut self, secs: u64) -> bitcoincore_rpc::Result<()> {
+        self.set(self.now + secs)
+    }
+
+    /// Hands the clock back to the system time.
+    pub fn reset(self) -> bitcoincore_rpc::Result<()> {
+        self.rpc
+            .call::<serde_json::Value>("setmocktime", &[json!(0)])?;
+        Ok(())
+    }
+
+    fn apply(&self) -> bitcoincore_rpc::Result<()> {
+        self.rpc
+            .call::<serde_json::Value>("setmocktime", &[json!(self.now)])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reorg_depth_is_validated() {
+        assert_eq!(fork_height(10, 1).unwrap(), 10);
+        assert_eq!(fork_height(10, 10).unwrap(), 1);
+        assert!(fork_height(10, 0).is_err());
+        assert!(fork_height(10, 11).is_err());
+        assert!(fork_height(0, 1).is_err());
+        assert!(fork_height(5, u64::MAX).is_err());
+    }
+}
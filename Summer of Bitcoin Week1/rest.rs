@@ -0,0 +1,422 @@
+//! Client for the node's unauthenticated REST interface (`-rest=1`), and a
+//! `ChainSource` trait so read-only commands can run over REST or RPC.
+
+use crate::http;
+use bitcoincore_rpc::bitcoin::consensus::encode::{self, deserialize, Decodable};
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, BlockHeader, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+const HEADER_SIZE: usize = 80;
+
+#[derive(Debug)]
+pub enum RestError {
+    Io(std::io::Error),
+    /// Non-2xx reply; the node puts the reason in the body as plain text.
+    Status(u16, String),
+    Decode(encode::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for RestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestError::Io(e) => write!(f, "REST request failed: {}", e),
+            RestError::Status(code, body) => write!(f, "REST returned {}: {}", code, body.trim()),
+            RestError::Decode(e) => write!(f, "invalid binary REST response: {}", e),
+            RestError::Json(e) => write!(f, "invalid JSON REST response: {}", e),
+        }
+    }
+}
+
+impl Error for RestError {}
+
+impl From<std::io::Error> for RestError {
+    fn from(e: std::io::Error) -> Self {
+        RestError::Io(e)
+    }
+}
+
+impl From<encode::Error> for RestError {
+    fn from(e: encode::Error) -> Self {
+        RestError::Decode(e)
+    }
+}
+
+impl From<serde_json::Error> for RestError {
+    fn from(e: serde_json::Error) -> Self {
+        RestError::Json(e)
+    }
+}
+
+/// A script as reported by `getutxos`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptPubKey {
+    pub hex: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Utxo {
+    pub height: u32,
+    /// Amount in BTC.
+    pub value: f64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: ScriptPubKey,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetUtxosResult {
+    pub chain_height: u64,
+    pub chaintip_hash: BlockHash,
+    /// One `0`/`1` per requested outpoint, `1` when it is unspent.
+    pub bitmap: String,
+    pub utxos: Vec<Utxo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainInfo {
+    blocks: u64,
+    bestblockhash: BlockHash,
+}
+
+pub struct RestClient {
+    base_url: String,
+}
+
+impl RestClient {
+    /// `base_url` is the node's RPC address, e.g. `http://127.0.0.1:18443`.
+    pub fn new(base_url: &str) -> Self {
+        RestClient {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    fn fetch(&self, path: &str) -> Result<Vec<u8>, RestError> {
+        let response = http::get(&format!("{}/rest/{}", self.base_url, path))?;
+        if !response.is_success() {
+            let body = String::from_utf8_lossy(&response.body).into_owned();
+            return Err(RestError::Status(response.status, body));
+        }
+        Ok(response.body)
+    }
+
+    fn fetch_bin<T: Decodable>(&self, path: &str) -> Result<T, RestError> {
+        Ok(deserialize(&self.fetch(&format!("{}.bin", path))?)?)
+    }
+
+    fn fetch_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, RestError> {
+        Ok(serde_json::from_slice(
+            &self.fetch(&format!("{}.json", path))?,
+        )?)
+    }
+
+    pub fn block(&self, hash: &BlockHash) -> Result<Block, RestError> {
+        self.fetch_bin(&format!("block/{}", hash))
+    }
+
+    /// The node's verbose JSON rendering of a block, with decoded transactions.
+    pub fn block_json(&self, hash: &BlockHash) -> Result<serde_json::Value, RestError> {
+        self.fetch_json(&format!("block/{}", hash))
+    }
+
+    /// Needs `-txindex` unless the transaction is in the mempool.
+    pub fn transaction(&self, txid: &Txid) -> Result<Transaction, RestError> {
+        self.fetch_bin(&format!("tx/{}", txid))
+    }
+
+    pub fn transaction_json(&self, txid: &Txid) -> Result<serde_json::Value, RestError> {
+        self.fetch_json(&format!("tx/{}", txid))
+    }
+
+    /// Up to `count` headers starting at `start` and walking towards the tip.
+    pub fn headers(&self, start: &BlockHash, count: u32) -> Result<Vec<BlockHeader>, RestError> {
+        let bytes = self.fetch(&format!("headers/{}.bin?count={}", start, count))?;
+        if bytes.len() % HEADER_SIZE != 0 {
+            return Err(RestError::Decode(encode::Error::ParseFailed(
+                "header response is not a multiple of 80 bytes",
+            )));
+        }
+        bytes
+            .chunks(HEADER_SIZE)
+            .map(|chunk| Ok(deserialize(chunk)?))
+            .collect()
+    }
+
+    pub fn utxos(
+        &self,
+        outpoints: &[OutPoint],
+        check_mempool: bool,
+    ) -> Result<GetUtxosResult, RestError> {
+        let mut path = String::from("getutxos");
+        if check_mempool {
+            path.push_str("/checkmempool");
+        }
+        for outpoint in outpoints {
+            path.push_str(&format!("/{}-{}", outpoint.txid, outpoint.vout));
+        }
+        self.fetch_json(&path)
+    }
+
+    pub fn mempool_info(&self) -> Result<serde_json::Value, RestError> {
+        self.fetch_json("mempool/info")
+    }
+
+    pub fn mempool_txids(&self) -> Result<Vec<Txid>, RestError> {
+        let contents: BTreeMap<Txid, serde_json::Value> = self.fetch_json("mempool/contents")?;
+        Ok(contents.into_keys().collect())
+    }
+
+    pub fn tip(&self) -> Result<(BlockHash, u64), RestError> {
+        let info: ChainInfo = self.fetch_json("chaininfo")?;
+        Ok((info.bestblockhash, info.blocks))
+    }
+}
+
+/// Read-only chain queries that both transports can answer.
+pub trait ChainSource {
+    fn tip(&self) -> Result<(BlockHash, u64), Box<dyn Error>>;
+    fn block(&self, hash: &BlockHash) -> Result<Block, Box<dyn Error>>;
+    fn transaction(&self, txid: &Txid) -> Result<Transaction, Box<dyn Error>>;
+    fn headers(&self, start: &BlockHash, count: u32) -> Result<Vec<BlockHeader>, Box<dyn Error>>;
+    fn mempool_txids(&self) -> Result<Vec<Txid>, Box<dyn Error>>;
+}
+
+impl ChainSource for RestClient {
+    fn tip(&self) -> Result<(BlockHash, u64), Box<dyn Error>> {
+        Ok(RestClient::tip(self)?)
+    }
+
+    fn block(&self, hash: &BlockHash) -> Result<Block, Box<dyn Error>> {
+        Ok(RestClient::block(self, hash)?)
+    }
+
+    fn transaction(&self, txid: &Txid) -> Result<Transaction, Box<dyn Error>> {
+        Ok(RestClient::transaction(self, txid)?)
+    }
+
+    fn headers(&self, start: &BlockHash, count: u32) -> Result<Vec<BlockHeader>, Box<dyn Error>> {
+        Ok(RestClient::headers(self, start, count)?)
+    }
+
+    fn mempool_txids(&self) -> Result<Vec<Txid>, Box<dyn Error>> {
+        Ok(RestClient::mempool_txids(self)?)
+    }
+}
+
+impl ChainSource for Client {
+    fn tip(&self) -> Result<(BlockHash, u64), Box<dyn Error>> {
+        let info = self.get_blockchain_info()?;
+        Ok((info.best_block_hash, info.blocks))
+    }
+
+    fn block(&self, hash: &BlockHash) -> Result<Block, Box<dyn Error>> {
+        Ok(self.get_block(hash)?)
+    }
+
+    fn transaction(&self, txid: &Txid) -> Result<Transaction, Box<dyn Error>> {
+        Ok(self.get_raw_transaction(txid, None)?)
+    }
+
+    fn headers(&self, start: &BlockHash, count: u32) -> Result<Vec<BlockHeader>, Box<dyn Error>> {
+        let height = self.get_block_header_info(start)?.height as u64;
+        let tip = self.get_block_count()?;
+        let mut headers = Vec::new();
+        for h in height..(height + count as u64).min(tip + 1) {
+            let hash = self.get_block_hash(h)?;
+            headers.push(self.get_block_header(&hash)?);
+        }
+        Ok(headers)
+    }
+
+    fn mempool_txids(&self) -> Result<Vec<Txid>, Box<dyn Error>> {
+        Ok(self.get_raw_mempool()?)
+    }
+}
+
+const USAGE: &str =
+    "usage: chain [--rest] <tip | block <hash> | tx <txid> | headers <hash> <count> | mempool>";
+
+/// Read-only queries, over RPC by default or REST with `--rest`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let use_rest = args.first().map(String::as_str) == Some("--rest");
+    let args = if use_rest { &args[1..] } else { args };
+    let source: Box<dyn ChainSource> = if use_rest {
+        Box::new(RestClient::new(crate::RPC_URL))
+    } else {
+        Box::new(crate::connect(crate::RPC_URL)?)
+    };
+
+    match args.first().map(String::as_str) {
+        Some("tip") => {
+            let (hash, height) = source.tip()?;
+            println!("{} {}", height, hash);
+        }
+        Some("block") => {
+            let hash: BlockHash = args.get(1).ok_or(USAGE)?.parse()?;
+            let block = source.block(&hash)?;
+            println!(
+                "{} ({} transactions)",
+                block.block_hash(),
+                block.txdata.len()
+            );
+            for tx in &block.txdata {
+                println!("  {}", tx.txid());
+            }
+        }
+        Some("tx") => {
+            let txid: Txid = args.get(1).ok_or(USAGE)?.parse()?;
+            let tx = source.transaction(&txid)?;
+            println!("{:#?}", tx);
+        }
+        Some("headers") => {
+            let hash: BlockHash = args.get(1).ok_or(USAGE)?.parse()?;
+            let count: u32 = args.get(2).ok_or(USAGE)?.parse()?;
+            for header in source.headers(&hash, count)? {
+                println!("{} prev {}", header.block_hash(), header.prev_blockhash);
+            }
+        }
+        Some("mempool") => {
+            for txid in source.mempool_txids()? {
+                println!("{}", txid);
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+    use bitcoincore_rpc::bitcoin::consensus::encode::serialize;
+    use bitcoincore_rpc::bitcoin::Network;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves fixed `(path, status, body)` routes until the test exits.
+    fn stand_in(routes: Vec<(String, u16, Vec<u8>)>) -> RestClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or("");
+                let (status, body) = routes
+                    .iter()
+                    .find(|(p, _, _)| p == path)
+                    .map(|(_, s, b)| (*s, b.clone()))
+                    .unwrap_or((404, b"Not found".to_vec()));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        RestClient::new(&base)
+    }
+
+    #[test]
+    fn decodes_binary_block_tx_and_headers() {
+        let genesis = genesis_block(Network::Regtest);
+        let hash = genesis.block_hash();
+        let coinbase = genesis.txdata[0].clone();
+        let mut two_headers = serialize(&genesis.header);
+        two_headers.extend(serialize(&genesis.header));
+        let client = stand_in(vec![
+            (
+                format!("/rest/block/{}.bin", hash),
+                200,
+                serialize(&genesis),
+            ),
+            (
+                format!("/rest/tx/{}.bin", coinbase.txid()),
+                200,
+                serialize(&coinbase),
+            ),
+            (
+                format!("/rest/headers/{}.bin?count=2", hash),
+                200,
+                two_headers,
+            ),
+        ]);
+
+        assert_eq!(client.block(&hash).unwrap(), genesis);
+        assert_eq!(client.transaction(&coinbase.txid()).unwrap(), coinbase);
+        let headers = client.headers(&hash, 2).unwrap();
+        assert_eq!(headers, vec![genesis.header, genesis.header]);
+    }
+
+    #[test]
+    fn decodes_json_endpoints() {
+        let hash = genesis_block(Network::Regtest).block_hash();
+        let txid = genesis_block(Network::Regtest).txdata[0].txid();
+        let utxos = format!(
+            r#"{{"chainHeight":101,"chaintipHash":"{}","bitmap":"10","utxos":[{{"height":1,"value":50.0,"scriptPubKey":{{"asm":"","hex":"51","type":"nonstandard"}}}}]}}"#,
+            hash
+        );
+        let client = stand_in(vec![
+            (
+                format!("/rest/getutxos/checkmempool/{}-0/{}-1.json", txid, txid),
+                200,
+                utxos.into_bytes(),
+            ),
+            (
+                "/rest/mempool/contents.json".to_owned(),
+                200,
+                format!(r#"{{"{}":{{"vsize":100}}}}"#, txid).into_bytes(),
+            ),
+            (
+                "/rest/chaininfo.json".to_owned(),
+                200,
+                format!(
+                    r#"{{"chain":"regtest","blocks":101,"bestblockhash":"{}"}}"#,
+                    hash
+                )
+                .into_bytes(),
+            ),
+        ]);
+
+        let result = client
+            .utxos(&[OutPoint::new(txid, 0), OutPoint::new(txid, 1)], true)
+            .unwrap();
+        assert_eq!(result.chain_height, 101);
+        assert_eq!(result.bitmap, "10");
+        assert_eq!(result.utxos[0].script_pubkey.hex, "51");
+        assert_eq!(client.mempool_txids().unwrap(), vec![txid]);
+        assert_eq!(RestClient::tip(&client).unwrap(), (hash, 101));
+    }
+
+    #[test]
+    fn surfaces_http_errors() {
+        let client = stand_in(vec![]);
+        let hash = genesis_block(Network::Regtest).block_hash();
+        match client.block(&hash) {
+            Err(RestError::Status(404, body)) => assert_eq!(body, "Not found"),
+            other => panic!("expected 404, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use crate::http;
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoincore_rpc::{Client, RpcApi};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_MILESTONES: [i64; 3] = [1, 3, 6];
+const DEFAULT_STATE_FILE: &str = "webhook-state.json";
+const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+const ID_HEADER: &str = "X-Webhook-Id";
+const BASE_RETRY_SECS: u64 = 5;
+const MAX_RETRY_SECS: u64 = 3600;
+
+pub struct Config {
+    pub endpoint: String,
+    pub secret: String,
+    pub wallets: Vec<String>,
+    /// Confirmation counts that trigger a `confirmed` notification, ascending.
+    pub milestones: Vec<i64>,
+    pub state_path: PathBuf,
+    pub interval: Duration,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config, Box<dyn Error>> {
+        let mut endpoint = None;
+        let mut secret = None;
+        let mut wallets = Vec::new();
+        let mut milestones = DEFAULT_MILESTONES.to_vec();
+        let mut state_path = PathBuf::from(DEFAULT_STATE_FILE);
+        let mut interval = Duration::from_secs(10);
+
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+            match flag.as_str() {
+                "--endpoint" => endpoint = Some(value.clone()),
+                "--secret" => secret = Some(value.clone()),
+                "--wallet" => wallets.push(value.clone()),
+                "--milestones" => {
+                    milestones = value
+                        .split(',')
+                        .map(|m| m.trim().parse())
+                        .collect::<Result<_, _>>()?;
+                }
+                "--state" => state_path = PathBuf::from(value),
+                "--interval" => interval = Duration::from_secs(value.parse()?),
+                _ => return Err(format!("unknown flag {}", flag).into()),
+            }
+        }
+        milestones.sort_unstable();
+        milestones.dedup();
+        if milestones.is_empty() || milestones[0] < 1 {
+            return Err("milestones must be positive confirmation counts".into());
+        }
+        if wallets.is_empty() {
+            wallets.push(crate::WALLET_NAME.to_owned());
+        }
+        Ok(Config {
+            endpoint: endpoint.ok_or("--endpoint is required")?,
+            secret: secret.ok_or("--secret is required")?,
+            wallets,
+            milestones,
+            state_path,
+            interval,
+        })
+    }
+}
+
+/// The JSON body POSTed to the endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// One of `seen`, `confirmed`, `reorg` or `conflict`.
+    pub event: String,
+    pub wallet: String,
+    pub txid: String,
+    pub vout: u32,
+    pub address: Option<String>,
+    pub amount: f64,
+    pub confirmations: i64,
+    pub blockhash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub attempts: u32,
+    /// Unix time before which the delivery is not retried.
+    pub next_attempt: u64,
+    pub notification: Notification,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Tracked {
+    /// Highest milestone already notified, 0 when only `seen` was sent.
+    reported: i64,
+    conflicted: bool,
+}
+
+/// Everything the daemon needs to survive a restart: the `listsinceblock`
+/// cursor per wallet, what was already reported per output, and the
+/// deliveries still waiting for a 2xx.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    cursors: BTreeMap<String, String>,
+    tracked: BTreeMap<String, Tracked>,
+    pub queue: Vec<Delivery>,
+    next_id: u64,
+}
+
+impl State {
+    pub fn load(path: &Path) -> Result<State, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a torn state file.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn enqueue(&mut self, notification: Notification, now: u64) {
+        self.next_id += 1;
+        self.queue.push(Delivery {
+            id: self.next_id,
+            attempts: 0,
+            next_attempt: now,
+            notification,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WalletTx {
+    pub txid: String,
+    pub category: String,
+    pub amount: f64,
+    pub confirmations: i64,
+    #[serde(default)]
+    pub vout: u32,
+    pub address: Option<String>,
+    pub blockhash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SinceBlock {
+    pub transactions: Vec<WalletTx>,
+    #[serde(default)]
+    pub removed: Vec<WalletTx>,
+    pub lastblock: String,
+}
+
+fn notification(event: &str, wallet: &str, tx: &WalletTx) -> Notification {
+    Notification {
+        event: event.to_owned(),
+        wallet: wallet.to_owned(),
+        txid: tx.txid.clone(),
+        vout: tx.vout,
+        address: tx.address.clone(),
+        amount: tx.amount,
+        confirmations: tx.confirmations,
+        blockhash: tx.blockhash.clone(),
+    }
+}
+
+/// Turns one `listsinceblock` result into notifications, updating what has
+/// been reported so each event fires once per output.
+pub fn collect_events(
+    state: &mut State,
+    wallet: &str,
+    since: &SinceBlock,
+    milestones: &[i64],
+) -> Vec<Notification> {
+    let mut events = Vec::new();
+
+    for tx in &since.removed {
+        let key = format!("{}:{}:{}", wallet, tx.txid, tx.vout);
+        if let Some(tracked) = state.tracked.get_mut(&key) {
+            if tracked.reported > 0 {
+                tracked.reported = 0;
+                events.push(notification("reorg", wallet, tx));
+            }
+        }
+    }
+
+    for tx in since
+        .transactions
+        .iter()
+        .filter(|t| t.category == "receive")
+    {
+        let key = format!("{}:{}:{}", wallet, tx.txid, tx.vout);
+        let tracked = match state.tracked.get_mut(&key) {
+            Some(tracked) => tracked,
+            None => {
+                events.push(notification("seen", wallet, tx));
+                state.tracked.entry(key).or_default()
+            }
+        };
+
+        // Negative confirmations mean the wallet saw a conflicting spend confirm.
+        if tx.confirmations < 0 {
+            if !tracked.conflicted {
+                tracked.conflicted = true;
+                events.push(notification("conflict", wallet, tx));
+            }
+            continue;
+        }
+        tracked.conflicted = false;
+        if tx.confirmations < tracked.reported {
+            tracked.reported = 0;
+            events.push(notification("reorg", wallet, tx));
+        }
+        for &milestone in milestones {
+            if tx.confirmations >= milestone && tracked.reported < milestone {
+                tracked.reported = milestone;
+                let mut confirmed = notification("confirmed", wallet, tx);
+                confirmed.confirmations = milestone;
+                events.push(confirmed);
+            }
+        }
+    }
+    events
+}
+
+/// Hex HMAC-SHA256 of the body, sent as `sha256=<hex>` so receivers can
+/// authenticate the notification with the shared secret.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut engine = HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(body);
+    format!(
+        "sha256={}",
+        Hmac::<sha256::Hash>::from_engine(engine).to_hex()
+    )
+}
+
+fn backoff(attempts: u32) -> u64 {
+    BASE_RETRY_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_RETRY_SECS)
+}
+
+/// Attempts every due delivery once. Failures stay queued with a growing delay.
+pub fn flush(endpoint: &str, secret: &str, state: &mut State, now: u64) {
+    for delivery in state.queue.iter_mut().filter(|d| d.next_attempt <= now) {
+        let body = serde_json::to_vec(&delivery.notification).expect("notification serializes");
+        let headers = [
+            (SIGNATURE_HEADER, sign(secret, &body)),
+            (ID_HEADER, delivery.id.to_string()),
+        ];
+        delivery.attempts += 1;
+        match http::post_json(endpoint, &headers, &body) {
+            Ok(response) if response.is_success() => {
+                info!(
+                    "delivered {} {} for {}",
+                    delivery.id, delivery.notification.event, delivery.notification.txid
+                );
+                delivery.next_attempt = u64::MAX;
+            }
+            Ok(response) => {
+                warn!(
+                    "delivery {} rejected with status {}",
+                    delivery.id, response.status
+                );
+                delivery.next_attempt = now + backoff(delivery.attempts);
+            }
+            Err(e) => {
+                warn!("delivery {} failed: {}", delivery.id, e);
+                delivery.next_attempt = now + backoff(delivery.attempts);
+            }
+        }
+    }
+    state.queue.retain(|d| d.next_attempt != u64::MAX);
+}
+
+fn poll(
+    rpc: &Client,
+    wallet: &str,
+    config: &Config,
+    state: &mut State,
+) -> bitcoincore_rpc::Result<Vec<Notification>> {
+    // Asking for the deepest milestone keeps the cursor far enough back that
+    // transactions keep being listed until they have passed every milestone.
+    let target = *config.milestones.last().unwrap();
+    let cursor = state.cursors.get(wallet).cloned();
+    let since: SinceBlock = rpc.call(
+        "listsinceblock",
+        &[json!(cursor), json!(target), json!(true), json!(true)],
+    )?;
+    let events = collect_events(state, wallet, &since, &config.milestones);
+    state.cursors.insert(wallet.to_owned(), since.lastblock);
+    Ok(events)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Runs the notifier until killed.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    let config = Config::from_args(args)?;
+    let mut state = State::load(&config.state_path)?;
+    let clients = config
+        .wallets
+        .iter()
+        .map(|w| Ok((w.clone(), crate::wallet_client(w)?)))
+        .collect::<bitcoincore_rpc::Result<Vec<_>>>()?;
+    info!(
+        "watching {:?}, posting to {}",
+        config.wallets, config.endpoint
+    );
+
+    loop {
+        for (wallet, rpc) in &clients {
+            match poll(rpc, wallet, &config, &mut state) {
+                Ok(events) => {
+                    for event in events {
+                        state.enqueue(event, unix_now());
+                    }
+                }
+                Err(e) => warn!("listsinceblock on {} failed: {}", wallet, e),
+            }
+        }
+        // Persist before sending so queued events survive a crash mid-delivery.
+        state.save(&config.state_path)?;
+        flush(&config.endpoint, &config.secret, &mut state, unix_now());
+        state.save(&config.state_path)?;
+        thread::sleep(config.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn tx(txid: &str, confirmations: i64) -> WalletTx {
+        WalletTx {
+            txid: txid.to_owned(),
+            category: "receive".to_owned(),
+            amount: 0.5,
+            confirmations,
+            vout: 0,
+            address: Some("bcrt1qq2yshcmzdlznnpxx258xswqlmqcxjs4dssfxt2".to_owned()),
+            blockhash: None,
+        }
+    }
+
+    fn since(transactions: Vec<WalletTx>, removed: Vec<WalletTx>) -> SinceBlock {
+        SinceBlock {
+            transactions,
+            removed,
+            lastblock: "00".repeat(32),
+        }
+    }
+
+    /// Accepts `count` requests, answering each with `status`, and forwards
+    /// the signature header and body of every request.
+    fn receiver(status: u16, count: usize) -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut signature = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap_or((&line, ""));
+                    if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                        signature = value.trim().to_owned();
+                    } else if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                sender.send((signature, body)).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn events_fire_once_per_milestone() {
+        let mut state = State::default();
+        let events = collect_events(&mut state, "w", &since(vec![tx("aa", 0)], vec![]), &[1, 3]);
+        assert_eq!(
+            events.iter().map(|e| e.event.as_str()).collect::<Vec<_>>(),
+            ["seen"]
+        );
+
+        let events = collect_events(&mut state, "w", &since(vec![tx("aa", 4)], vec![]), &[1, 3]);
+        let confs: Vec<_> = events
+            .iter()
+            .map(|e| (e.event.as_str(), e.confirmations))
+            .collect();
+        assert_eq!(confs, [("confirmed", 1), ("confirmed", 3)]);
+
+        let events = collect_events(&mut state, "w", &since(vec![tx("aa", 5)], vec![]), &[1, 3]);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn removed_and_conflicted_transactions_are_reported() {
+        let mut state = State::default();
+        collect_events(&mut state, "w", &since(vec![tx("aa", 2)], vec![]), &[1]);
+
+        let events = collect_events(&mut state, "w", &since(vec![], vec![tx("aa", 0)]), &[1]);
+        assert_eq!(events[0].event, "reorg");
+
+        let events = collect_events(&mut state, "w", &since(vec![tx("aa", -1)], vec![]), &[1]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "conflict");
+    }
+
+    #[test]
+    fn delivery_is_signed_and_dequeued_on_success() {
+        let (url, received) = receiver(200, 1);
+        let mut state = State::default();
+        state.enqueue(notification("seen", "w", &tx("aa", 0)), 0);
+
+        flush(&url, "s3cret", &mut state, 0);
+
+        let (signature, body) = received.recv().unwrap();
+        assert_eq!(signature, sign("s3cret", &body));
+        let sent: Notification = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sent.txid, "aa");
+        assert!(state.queue.is_empty());
+    }
+
+    #[test]
+    fn failed_delivery_is_persisted_and_retried() {
+        let (url, received) = receiver(500, 2);
+        let path = std::env::temp_dir().join(format!("webhook-test-{}.json", std::process::id()));
+        let mut state = State::default();
+        state.enqueue(notification("seen", "w", &tx("aa", 0)), 0);
+
+        flush(&url, "s3cret", &mut state, 0);
+        received.recv().unwrap();
+        assert_eq!(state.queue[0].attempts, 1);
+        assert_eq!(state.queue[0].next_attempt, backoff(1));
+        state.save(&path).unwrap();
+
+        let mut state = State::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        flush(&url, "s3cret", &mut state, 1);
+        assert_eq!(state.queue[0].attempts, 1, "not due yet");
+
+        flush(&url, "s3cret", &mut state, backoff(1));
+        received.recv().unwrap();
+        assert_eq!(state.queue[0].attempts, 2);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::keys;
+use crate::multisig::{KeyOrdering, Multisig, MultisigError};
+use crate::psbt::{self, KeyOrigin};
+use bip39::Mnemonic;
+use bitcoin::{
+    network::constants::Network,
+    secp256k1::{Secp256k1, Signing, Verification},
+    util::bip32::{self, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, KeySource},
+    Address, PublicKey,
+};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// BIP48 purpose, the first hardened step of every account path.
+const BIP48_PURPOSE: u32 = 48;
+/// Size of a generated raw seed.
+pub const SEED_SIZE: usize = 32;
+
+/// The multisig script type a BIP48 account is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptType {
+    P2shP2wsh,
+    P2wsh,
+}
+
+impl ScriptType {
+    /// The last hardened step of the account path: `1'` or `2'`.
+    pub fn index(self) -> u32 {
+        match self {
+            ScriptType::P2shP2wsh => 1,
+            ScriptType::P2wsh => 2,
+        }
+    }
+
+    pub fn address(self, multisig: &Multisig, network: Network) -> Address {
+        match self {
+            ScriptType::P2shP2wsh => multisig.p2sh_p2wsh_address(network),
+            ScriptType::P2wsh => multisig.p2wsh_address(network),
+        }
+    }
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptType::P2shP2wsh => f.pad("P2SH-P2WSH"),
+            ScriptType::P2wsh => f.pad("P2WSH"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    Mnemonic(bip39::Error),
+    Bip32(bip32::Error),
+    Multisig(MultisigError),
+    WordCount(usize),
+    /// An export not of the form `[fingerprint/48'/coin'/account'/type']xpub`.
+    Export(String),
+    /// Cosigner accounts for different script types or networks.
+    Mismatch,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountError::Mnemonic(e) => write!(f, "invalid mnemonic: {}", e),
+            AccountError::Bip32(e) => write!(f, "derivation: {}", e),
+            AccountError::Multisig(e) => write!(f, "{}", e),
+            AccountError::WordCount(n) => {
+                write!(
+                    f,
+                    "{} words is not a BIP39 length (12, 15, 18, 21 or 24)",
+                    n
+                )
+            }
+            AccountError::Export(s) => write!(
+                f,
+                "invalid account `{}`, expected [fingerprint/48'/coin'/account'/type']xpub",
+                s
+            ),
+            AccountError::Mismatch => write!(
+                f,
+                "cosigner accounts are for different script types or networks"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl From<bip39::Error> for AccountError {
+    fn from(e: bip39::Error) -> Self {
+        AccountError::Mnemonic(e)
+    }
+}
+
+impl From<bip32::Error> for AccountError {
+    fn from(e: bip32::Error) -> Self {
+        AccountError::Bip32(e)
+    }
+}
+
+impl From<MultisigError> for AccountError {
+    fn from(e: MultisigError) -> Self {
+        AccountError::Multisig(e)
+    }
+}
+
+/// A new English mnemonic of `words` words from the OS random source.
+pub fn generate_mnemonic(words: usize) -> Result<Mnemonic, AccountError> {
+    if !(12..=24).contains(&words) || !words.is_multiple_of(3) {
+        return Err(AccountError::WordCount(words));
+    }
+    let mut entropy = Zeroizing::new(vec![0u8; words * 4 / 3]);
+    OsRng.fill_bytes(&mut entropy);
+    Ok(Mnemonic::from_entropy(&entropy)?)
+}
+
+/// A new raw seed from the OS random source, for cosigners who back up
+/// the hex rather than words.
+pub fn generate_seed() -> Zeroizing<[u8; SEED_SIZE]> {
+    let mut seed = Zeroizing::new([0u8; SEED_SIZE]);
+    OsRng.fill_bytes(&mut seed[..]);
+    seed
+}
+
+/// `m/48'/coin'/account'/type'`, with coin 0 on mainnet and 1 elsewhere.
+pub fn account_path(
+    network: Network,
+    account: u32,
+    script_type: ScriptType,
+) -> Result<DerivationPath, AccountError> {
+    let coin = u32::from(network != Network::Bitcoin);
+    [BIP48_PURPOSE, coin, account, script_type.index()]
+        .into_iter()
+        .map(ChildNumber::from_hardened_idx)
+        .collect::<Result<Vec<_>, _>>()
+        .map(DerivationPath::from)
+        .map_err(AccountError::from)
+}
+
+/// A cosigner's BIP48 account xpub with its origin, exported and imported
+/// as `[fingerprint/48'/coin'/account'/type']xpub`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub source: KeySource,
+    pub xpub: ExtendedPubKey,
+}
+
+impl Account {
+    /// Derives account `account` for `script_type` from a master key.
+    pub fn from_master<C: Signing>(
+        secp: &Secp256k1<C>,
+        master: &ExtendedPrivKey,
+        account: u32,
+        script_type: ScriptType,
+    ) -> Result<Account, AccountError> {
+        let path = account_path(master.network, account, script_type)?;
+        let mut xprv = master.derive_priv(secp, &path)?;
+        let xpub = ExtendedPubKey::from_priv(secp, &xprv);
+        keys::erase_xprv(&mut xprv);
+        Ok(Account {
+            source: (master.fingerprint(secp), path),
+            xpub,
+        })
+    }
+
+    /// The script type named by the last step of the account path.
+    pub fn script_type(&self) -> Option<ScriptType> {
+        let hardened = |index| ChildNumber::Hardened { index };
+        match self.source.1.as_ref() {
+            [purpose, _, _, ty] if *purpose == hardened(BIP48_PURPOSE) => {
+                [ScriptType::P2shP2wsh, ScriptType::P2wsh]
+                    .into_iter()
+                    .find(|script_type| *ty == hardened(script_type.index()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The key at `<change>/<index>` below the account, with its full
+    /// origin for PSBTs.
+    pub fn derive<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        change: bool,
+        index: u32,
+    ) -> Result<KeyOrigin, AccountError> {
+        let steps = [
+            ChildNumber::from_normal_idx(u32::from(change))?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        let child = self.xpub.derive_pub(secp, &steps)?;
+        Ok(KeyOrigin {
+            key: PublicKey::new(child.public_key),
+            source: (self.source.0, self.source.1.extend(steps)),
+        })
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        psbt::fmt_origin(f, &self.source)?;
+        write!(f, "{}", self.xpub)
+    }
+}
+
+impl FromStr for Account {
+    type Err = AccountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AccountError::Export(s.to_owned());
+        let (source, xpub) = psbt::split_origin(s.trim()).ok_or_else(invalid)?;
+        let account = Account {
+            source,
+            xpub: ExtendedPubKey::from_str(xpub).map_err(|_| invalid())?,
+        };
+        if account.script_type().is_none() || account.xpub.depth != 4 {
+            return Err(invalid());
+        }
+        Ok(account)
+    }
+}
+
+/// The child keys at `<change>/<index>` of every cosigner account, in a
+/// sorted m-of-n multisig as BIP48 wallets build it.
+pub struct Quorum {
+    pub multisig: Multisig,
+    pub origins: Vec<KeyOrigin>,
+    pub script_type: ScriptType,
+}
+
+impl Quorum {
+    pub fn derive<C: Verification>(
+        secp: &Secp256k1<C>,
+        accounts: &[Account],
+        threshold: usize,
+        network: Network,
+        change: bool,
+        index: u32,
+    ) -> Result<Quorum, AccountError> {
+        let first = accounts.first().ok_or(MultisigError::NoKeys)?;
+        let script_type = first.script_type().ok_or(AccountError::Mismatch)?;
+        let origins = accounts
+            .iter()
+            .map(|account| {
+                // xpubs only tell mainnet from the test networks
+                let same_network =
+                    (account.xpub.network == Network::Bitcoin) == (network == Network::Bitcoin);
+                if account.script_type() != Some(script_type) || !same_network {
+                    return Err(AccountError::Mismatch);
+                }
+                account.derive(secp, change, index)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys: Vec<PublicKey> = origins.iter().map(|origin| origin.key).collect();
+        let multisig = Multisig::new(&keys, threshold, KeyOrdering::SortedMulti)?;
+        Ok(Quorum {
+            multisig,
+            origins,
+            script_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The master key of BIP32 test vector 1.
+    fn master() -> ExtendedPrivKey {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap()
+    }
+
+    fn account(seed: u8, network: Network, script_type: ScriptType) -> Account {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(network, &[seed; 32]).unwrap();
+        Account::from_master(&secp, &master, 0, script_type).unwrap()
+    }
+
+    #[test]
+    fn bip32_test_vector_1() {
+        let secp = Secp256k1::new();
+        let master = master();
+        assert_eq!(
+            ExtendedPubKey::from_priv(&secp, &master).to_string(),
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+        );
+        let child = master
+            .derive_priv(&secp, &DerivationPath::from_str("m/0'").unwrap())
+            .unwrap();
+        assert_eq!(
+            ExtendedPubKey::from_priv(&secp, &child).to_string(),
+            "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw"
+        );
+
+        let account = Account::from_master(&secp, &master, 0, ScriptType::P2wsh).unwrap();
+        assert_eq!(account.source.0.to_string(), "3442193e");
+        let xprv = master.derive_priv(&secp, &account.source.1).unwrap();
+        assert_eq!(account.xpub, ExtendedPubKey::from_priv(&secp, &xprv));
+        assert_eq!(account.xpub.depth, 4);
+    }
+
+    #[test]
+    fn account_paths() {
+        for (network, account, script_type, expected) in [
+            (Network::Bitcoin, 0, ScriptType::P2shP2wsh, "m/48'/0'/0'/1'"),
+            (Network::Bitcoin, 7, ScriptType::P2wsh, "m/48'/0'/7'/2'"),
+            (Network::Testnet, 0, ScriptType::P2shP2wsh, "m/48'/1'/0'/1'"),
+            (Network::Regtest, 3, ScriptType::P2wsh, "m/48'/1'/3'/2'"),
+        ] {
+            assert_eq!(
+                account_path(network, account, script_type).unwrap(),
+                DerivationPath::from_str(expected).unwrap()
+            );
+        }
+        assert!(matches!(
+            account_path(Network::Bitcoin, 1 << 31, ScriptType::P2wsh),
+            Err(AccountError::Bip32(_))
+        ));
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        for script_type in [ScriptType::P2shP2wsh, ScriptType::P2wsh] {
+            let account = account(1, Network::Bitcoin, script_type);
+            let text = account.to_string();
+            let parsed: Account = text.parse().unwrap();
+            assert_eq!(parsed, account);
+            assert_eq!(parsed.script_type(), Some(script_type));
+        }
+
+        let secp = Secp256k1::new();
+        let master = master();
+        let master_xpub = ExtendedPubKey::from_priv(&secp, &master);
+        let fingerprint = master.fingerprint(&secp);
+        for bad in [
+            // Depth 0 under a BIP48 origin
+            format!("[{}/48'/0'/0'/2']{}", fingerprint, master_xpub),
+            // Not a BIP48 path
+            format!("[{}/44'/0'/0'/2']{}", fingerprint, master_xpub),
+            format!("{}", master_xpub),
+        ] {
+            assert!(
+                matches!(bad.parse::<Account>(), Err(AccountError::Export(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn quorum_derives_sorted_keys_with_origins() {
+        let secp = Secp256k1::verification_only();
+        let accounts: Vec<Account> = (1..=3)
+            .map(|seed| account(seed, Network::Bitcoin, ScriptType::P2wsh))
+            .collect();
+        let quorum = Quorum::derive(&secp, &accounts, 2, Network::Bitcoin, true, 5).unwrap();
+        assert_eq!(quorum.script_type, ScriptType::P2wsh);
+        assert_eq!(quorum.multisig.threshold(), 2);
+        assert!(quorum
+            .multisig
+            .keys()
+            .windows(2)
+            .all(|w| w[0].to_bytes() < w[1].to_bytes()));
+        for (origin, account) in quorum.origins.iter().zip(&accounts) {
+            assert_eq!(*origin, account.derive(&secp, true, 5).unwrap());
+            assert_eq!(origin.source.1.to_string(), "m/48'/0'/0'/2'/1/5");
+        }
+    }
+
+    #[test]
+    fn quorum_rejects_mismatched_accounts() {
+        let secp = Secp256k1::verification_only();
+        let mixed = [
+            account(1, Network::Bitcoin, ScriptType::P2wsh),
+            account(2, Network::Bitcoin, ScriptType::P2shP2wsh),
+        ];
+        assert!(matches!(
+            Quorum::derive(&secp, &mixed, 2, Network::Bitcoin, false, 0),
+            Err(AccountError::Mismatch)
+        ));
+        let testnet = [
+            account(1, Network::Testnet, ScriptType::P2wsh),
+            account(2, Network::Testnet, ScriptType::P2wsh),
+        ];
+        assert!(matches!(
+            Quorum::derive(&secp, &testnet, 2, Network::Bitcoin, false, 0),
+            Err(AccountError::Mismatch)
+        ));
+        assert!(Quorum::derive(&secp, &testnet, 2, Network::Regtest, false, 0).is_ok());
+        assert!(matches!(
+            Quorum::derive(&secp, &[], 1, Network::Bitcoin, false, 0),
+            Err(AccountError::Multisig(MultisigError::NoKeys))
+        ));
+    }
+}
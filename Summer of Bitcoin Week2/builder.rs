@@ -0,0 +1,708 @@
+use crate::account::ScriptType;
+use crate::finalize::{self, FinalizeError};
+use crate::multisig::{Multisig, MultisigError};
+use crate::prevout::{self, Prevout};
+use crate::signature::{self, SIGNATURE_SIZE};
+use bitcoin::{
+    blockdata::{
+        opcodes,
+        script::{Builder, Script},
+        transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut},
+        witness::Witness,
+    },
+    consensus::encode::VarInt,
+    network::constants::Network,
+    secp256k1::{Message, Secp256k1, Signing},
+    util::{
+        amount::Amount,
+        sighash::{self, EcdsaSighashType, SighashCache},
+    },
+    Address, EcdsaSig, PackedLockTime, PrivateKey,
+};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// A `--to` value not of the form `address:amount_sat`.
+    Recipient(String),
+    /// A `--wrapping` value other than `p2sh-p2wsh`, `p2wsh` or `p2sh`.
+    Wrapping(String),
+    Multisig(MultisigError),
+    WrongNetwork(Address),
+    NoInputs,
+    /// Two prevouts with the same outpoint.
+    DuplicateInput(OutPoint),
+    NoRecipients,
+    DustOutput {
+        index: usize,
+        amount: Amount,
+        dust: Amount,
+    },
+    InsufficientFunds {
+        available: Amount,
+        needed: Amount,
+    },
+    Sighash(sighash::Error),
+    Finalize(FinalizeError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Recipient(s) => {
+                write!(f, "invalid recipient `{}`, expected address:amount_sat", s)
+            }
+            BuildError::Wrapping(s) => {
+                write!(
+                    f,
+                    "unknown wrapping `{}`, expected p2sh-p2wsh, p2wsh or p2sh",
+                    s
+                )
+            }
+            BuildError::Multisig(e) => write!(f, "{}", e),
+            BuildError::WrongNetwork(address) => {
+                write!(f, "address {} is for another network", address)
+            }
+            BuildError::NoInputs => write!(f, "at least one input is required"),
+            BuildError::DuplicateInput(outpoint) => {
+                write!(f, "prevout {} is spent twice", outpoint)
+            }
+            BuildError::NoRecipients => write!(f, "at least one recipient is required"),
+            BuildError::DustOutput {
+                index,
+                amount,
+                dust,
+            } => write!(
+                f,
+                "output {} pays {}, below the {} dust threshold",
+                index, amount, dust
+            ),
+            BuildError::InsufficientFunds { available, needed } => write!(
+                f,
+                "inputs hold {}, outputs plus fee need {}",
+                available, needed
+            ),
+            BuildError::Sighash(e) => write!(f, "sighash: {}", e),
+            BuildError::Finalize(e) => write!(f, "witness: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<sighash::Error> for BuildError {
+    fn from(e: sighash::Error) -> Self {
+        BuildError::Sighash(e)
+    }
+}
+
+impl From<MultisigError> for BuildError {
+    fn from(e: MultisigError) -> Self {
+        BuildError::Multisig(e)
+    }
+}
+
+impl From<FinalizeError> for BuildError {
+    fn from(e: FinalizeError) -> Self {
+        BuildError::Finalize(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    pub script_pubkey: Script,
+    pub amount: Amount,
+}
+
+impl Recipient {
+    /// Parses `address:amount_sat`, rejecting addresses for other networks.
+    pub fn parse(s: &str, network: Network) -> Result<Self, BuildError> {
+        let invalid = || BuildError::Recipient(s.to_owned());
+        let (address, amount) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let address = Address::from_str(address).map_err(|_| invalid())?;
+        if !address.is_valid_for_network(network) {
+            return Err(BuildError::WrongNetwork(address));
+        }
+        Ok(Recipient {
+            script_pubkey: address.script_pubkey(),
+            amount: Amount::from_sat(amount.parse().map_err(|_| invalid())?),
+        })
+    }
+}
+
+/// How a multisig script is committed to by the outputs it locks, and so
+/// where a spend puts the signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrapping {
+    /// P2WSH nested in P2SH: the scriptSig pushes the redeem script, the
+    /// signatures go in the witness.
+    P2shP2wsh,
+    /// Native P2WSH: empty scriptSig, everything in the witness.
+    P2wsh,
+    /// Legacy P2SH: signatures and script in the scriptSig, signed with the
+    /// pre-segwit sighash.
+    P2sh,
+}
+
+impl Wrapping {
+    pub const ALL: [Wrapping; 3] = [Wrapping::P2shP2wsh, Wrapping::P2wsh, Wrapping::P2sh];
+
+    pub fn address(self, multisig: &Multisig, network: Network) -> Result<Address, BuildError> {
+        match self {
+            Wrapping::P2shP2wsh => Ok(multisig.p2sh_p2wsh_address(network)),
+            Wrapping::P2wsh => Ok(multisig.p2wsh_address(network)),
+            Wrapping::P2sh => Ok(multisig.p2sh_address(network)?),
+        }
+    }
+
+    pub fn template(self, multisig: &Multisig) -> InputTemplate {
+        match self {
+            Wrapping::P2shP2wsh => InputTemplate::p2sh_p2wsh(multisig),
+            Wrapping::P2wsh => InputTemplate::p2wsh(witness_size(multisig)),
+            Wrapping::P2sh => InputTemplate::p2sh(multisig),
+        }
+    }
+}
+
+impl From<ScriptType> for Wrapping {
+    fn from(script_type: ScriptType) -> Self {
+        match script_type {
+            ScriptType::P2shP2wsh => Wrapping::P2shP2wsh,
+            ScriptType::P2wsh => Wrapping::P2wsh,
+        }
+    }
+}
+
+impl fmt::Display for Wrapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Wrapping::P2shP2wsh => f.pad("P2SH-P2WSH"),
+            Wrapping::P2wsh => f.pad("P2WSH"),
+            Wrapping::P2sh => f.pad("P2SH"),
+        }
+    }
+}
+
+impl FromStr for Wrapping {
+    type Err = BuildError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "p2sh-p2wsh" => Ok(Wrapping::P2shP2wsh),
+            "p2wsh" => Ok(Wrapping::P2wsh),
+            "p2sh" => Ok(Wrapping::P2sh),
+            _ => Err(BuildError::Wrapping(s.to_owned())),
+        }
+    }
+}
+
+/// An unsigned spend of multisig outputs.
+#[derive(Debug)]
+pub struct Unsigned {
+    pub tx: Transaction,
+    pub fee: Amount,
+    /// Index of the change output, if one was worth adding.
+    pub change: Option<usize>,
+}
+
+/// How every input of a spend looks once signed, as far as building and fee
+/// estimation need to know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputTemplate {
+    /// The scriptSig of the unsigned input.
+    pub script_sig: Script,
+    /// Exact size of the signed scriptSig.
+    pub script_sig_size: usize,
+    /// Exact size of the signed witness, zero for a legacy input.
+    pub witness_size: usize,
+    pub sequence: Sequence,
+}
+
+impl InputTemplate {
+    /// A P2SH-P2WSH multisig input: the scriptSig pushes the redeem script.
+    pub fn p2sh_p2wsh(multisig: &Multisig) -> Self {
+        InputTemplate::p2sh_wrapped(multisig.witness_script(), witness_size(multisig))
+    }
+
+    /// Any native P2WSH input, given its signed witness size.
+    pub fn p2wsh(witness_size: usize) -> Self {
+        InputTemplate::witness_only(witness_size)
+    }
+
+    /// Any input with an empty scriptSig, such as P2TR key- and
+    /// script-path spends, given its signed witness size.
+    pub fn witness_only(witness_size: usize) -> Self {
+        InputTemplate {
+            script_sig: Script::new(),
+            script_sig_size: 0,
+            witness_size,
+            sequence: Sequence::MAX,
+        }
+    }
+
+    /// A legacy P2SH multisig input: unsigned it has an empty scriptSig,
+    /// signed `OP_0 <sig>... <redeem script>` and no witness.
+    pub fn p2sh(multisig: &Multisig) -> Self {
+        InputTemplate {
+            script_sig: Script::new(),
+            script_sig_size: script_sig_size(multisig),
+            witness_size: 0,
+            sequence: Sequence::MAX,
+        }
+    }
+
+    /// Any P2SH-P2WSH input, given its witness script and signed witness size.
+    pub fn p2sh_wrapped(witness_script: &Script, witness_size: usize) -> Self {
+        let script_sig = Builder::new()
+            .push_slice(witness_script.to_v0_p2wsh().as_bytes())
+            .into_script();
+        InputTemplate {
+            script_sig_size: script_sig.len(),
+            script_sig,
+            witness_size,
+            sequence: Sequence::MAX,
+        }
+    }
+}
+
+/// Builds a transaction spending every prevout to `recipients`, paying
+/// `fee_rate` sat/vB on the estimated signed size. Whatever is left goes to
+/// `change` unless it would be dust, in which case it adds to the fee.
+pub fn build(
+    template: &InputTemplate,
+    lock_time: PackedLockTime,
+    prevouts: &[Prevout],
+    recipients: &[Recipient],
+    change: &Script,
+    fee_rate: f64,
+) -> Result<Unsigned, BuildError> {
+    if prevouts.is_empty() {
+        return Err(BuildError::NoInputs);
+    }
+    if let Some(outpoint) = prevout::first_duplicate(prevouts) {
+        return Err(BuildError::DuplicateInput(outpoint));
+    }
+    if recipients.is_empty() {
+        return Err(BuildError::NoRecipients);
+    }
+    for (index, recipient) in recipients.iter().enumerate() {
+        let dust = recipient.script_pubkey.dust_value();
+        if recipient.amount < dust {
+            return Err(BuildError::DustOutput {
+                index,
+                amount: recipient.amount,
+                dust,
+            });
+        }
+    }
+
+    let mut tx = Transaction {
+        version: 2,
+        lock_time,
+        input: prevouts
+            .iter()
+            .map(|prevout| TxIn {
+                previous_output: prevout.outpoint,
+                script_sig: template.script_sig.clone(),
+                sequence: template.sequence,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: recipients
+            .iter()
+            .map(|recipient| TxOut {
+                value: recipient.amount.to_sat(),
+                script_pubkey: recipient.script_pubkey.clone(),
+            })
+            .collect(),
+    };
+
+    let available: Amount = prevouts.iter().map(|prevout| prevout.amount).sum();
+    let sent: Amount = recipients.iter().map(|recipient| recipient.amount).sum();
+    let fee_without_change = fee_for(signed_weight(&tx, template), fee_rate);
+    let needed = sent + fee_without_change;
+    if available < needed {
+        return Err(BuildError::InsufficientFunds { available, needed });
+    }
+
+    tx.output.push(TxOut {
+        value: 0,
+        script_pubkey: change.clone(),
+    });
+    let fee_with_change = fee_for(signed_weight(&tx, template), fee_rate);
+    let change_value = available
+        .checked_sub(sent + fee_with_change)
+        .unwrap_or(Amount::ZERO);
+    if change_value >= change.dust_value() {
+        let index = tx.output.len() - 1;
+        tx.output[index].value = change_value.to_sat();
+        Ok(Unsigned {
+            tx,
+            fee: fee_with_change,
+            change: Some(index),
+        })
+    } else {
+        tx.output.pop();
+        Ok(Unsigned {
+            tx,
+            fee: available - sent,
+            change: None,
+        })
+    }
+}
+
+/// Size of the `<> <sig>... <witness script>` witness, in bytes and so in
+/// weight units. Exact, since every signature is `SIGNATURE_SIZE`.
+pub fn witness_size(multisig: &Multisig) -> usize {
+    let script_len = multisig.witness_script().len();
+    let items = multisig.threshold() + 2;
+    VarInt(items as u64).len()
+        + 1
+        + multisig.threshold() * (1 + SIGNATURE_SIZE)
+        + VarInt(script_len as u64).len()
+        + script_len
+}
+
+/// Size of the legacy `OP_0 <sig>... <redeem script>` scriptSig, exact for
+/// the same reason as `witness_size`.
+pub fn script_sig_size(multisig: &Multisig) -> usize {
+    let mut builder = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0);
+    for _ in 0..multisig.threshold() {
+        builder = builder.push_slice(&[0; SIGNATURE_SIZE]);
+    }
+    builder
+        .push_slice(multisig.witness_script().as_bytes())
+        .into_script()
+        .len()
+}
+
+/// Weight of one input signed as `template`: outpoint, scriptSig and
+/// sequence at four units a byte, plus the witness.
+pub fn input_weight(template: &InputTemplate) -> usize {
+    let script_sig_len = template.script_sig_size;
+    let base = 36 + VarInt(script_sig_len as u64).len() + script_sig_len + 4;
+    base * 4 + template.witness_size
+}
+
+/// Weight `tx` will have once every input is signed as `template`: the
+/// stripped size with signed scriptSigs counts four times, then, if the
+/// inputs have witnesses, the segwit marker and flag and each witness once.
+pub fn signed_weight(tx: &Transaction, template: &InputTemplate) -> usize {
+    let mut stripped = tx.clone();
+    for input in &mut stripped.input {
+        input.script_sig = Script::from(vec![0; template.script_sig_size]);
+        input.witness.clear();
+    }
+    let witnesses = if template.witness_size > 0 {
+        2 + tx.input.len() * template.witness_size
+    } else {
+        0
+    };
+    stripped.weight() + witnesses
+}
+
+fn fee_for(weight: usize, fee_rate: f64) -> Amount {
+    let vsize = weight.div_ceil(4);
+    Amount::from_sat((vsize as f64 * fee_rate).ceil() as u64)
+}
+
+/// Splits off the ANYONECANPAY flag, leaving ALL, NONE or SINGLE.
+pub fn split_anyonecanpay(ty: EcdsaSighashType) -> (EcdsaSighashType, bool) {
+    match ty {
+        EcdsaSighashType::AllPlusAnyoneCanPay => (EcdsaSighashType::All, true),
+        EcdsaSighashType::NonePlusAnyoneCanPay => (EcdsaSighashType::None, true),
+        EcdsaSighashType::SinglePlusAnyoneCanPay => (EcdsaSighashType::Single, true),
+        ty => (ty, false),
+    }
+}
+
+/// Whether a signature of type `ty` on input `index` commits to no outputs
+/// at all: NONE, or SINGLE without an output at the same index. BIP143 has
+/// no legacy "sighash of 1" bug here; it hashes 32 zero bytes in place of
+/// the outputs, so such a signature is valid but lets anyone redirect the
+/// funds. A legacy P2SH signature is worse still: SINGLE without an output
+/// signs the constant 1 and so fits any transaction.
+pub fn commits_to_no_outputs(ty: EcdsaSighashType, index: usize, tx: &Transaction) -> bool {
+    match split_anyonecanpay(ty).0 {
+        EcdsaSighashType::None => true,
+        EcdsaSighashType::Single => index >= tx.output.len(),
+        _ => false,
+    }
+}
+
+/// Signs input `i` with `keys` under `sighash_types[i]` and sets its
+/// witness, or for legacy P2SH its scriptSig. All inputs share one
+/// `SighashCache`, so the BIP143 midstate hashes are computed only once.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    tx: &mut Transaction,
+    prevouts: &[Prevout],
+    wrapping: Wrapping,
+    witness_script: &Script,
+    keys: &[PrivateKey],
+    sighash_types: &[EcdsaSighashType],
+) -> Result<(), BuildError> {
+    let mut cache = SighashCache::new(&*tx);
+    let mut signed = Vec::with_capacity(prevouts.len());
+    for (index, (prevout, &sighash_type)) in prevouts.iter().zip(sighash_types).enumerate() {
+        let amount = prevout.amount.to_sat();
+        let mut sighash = |hash_ty| {
+            signature_message(
+                &mut cache,
+                tx,
+                wrapping,
+                index,
+                witness_script,
+                amount,
+                hash_ty,
+            )
+        };
+        let message = sighash(sighash_type)?;
+        let signatures: Vec<EcdsaSig> = keys
+            .iter()
+            .map(|key| EcdsaSig {
+                sig: signature::sign(secp, &message, &key.inner),
+                hash_ty: sighash_type,
+            })
+            .collect();
+
+        let by_type = |hash_ty| sighash(hash_ty).expect("sighash for an existing input");
+        if wrapping == Wrapping::P2sh {
+            let script_sig = finalize::multisig_script_sig(witness_script, &signatures, by_type)?;
+            signed.push((script_sig, Witness::new()));
+        } else {
+            let witness = finalize::multisig_witness(witness_script, &signatures, by_type)?;
+            signed.push((tx.input[index].script_sig.clone(), witness));
+        }
+    }
+    for (input, (script_sig, witness)) in tx.input.iter_mut().zip(signed) {
+        input.script_sig = script_sig;
+        input.witness = witness;
+    }
+    Ok(())
+}
+
+/// The message a signature on input `index` commits to: the BIP143 sighash
+/// for segwit inputs, the original one over the redeem script for legacy
+/// P2SH. Legacy SINGLE without an output at `index` is refused rather than
+/// signing the constant 1, which would authorise any transaction. `tx` is
+/// the transaction `cache` was made from.
+pub fn signature_message(
+    cache: &mut SighashCache<&Transaction>,
+    tx: &Transaction,
+    wrapping: Wrapping,
+    index: usize,
+    script: &Script,
+    amount: u64,
+    hash_ty: EcdsaSighashType,
+) -> Result<Message, sighash::Error> {
+    let sighash = match wrapping {
+        Wrapping::P2sh => {
+            if split_anyonecanpay(hash_ty).0 == EcdsaSighashType::Single && index >= tx.output.len()
+            {
+                return Err(sighash::Error::SingleWithoutCorrespondingOutput {
+                    index,
+                    outputs_size: tx.output.len(),
+                });
+            }
+            cache.legacy_signature_hash(index, script, hash_ty.to_u32())?
+        }
+        Wrapping::P2shP2wsh | Wrapping::P2wsh => {
+            cache.segwit_signature_hash(index, script, amount, hash_ty)?
+        }
+    };
+    Ok(Message::from_slice(&sighash[..]).expect("sighash is 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{keys, prevouts, recipient};
+    use crate::multisig::KeyOrdering;
+
+    fn multisig_2_of_3() -> Multisig {
+        let secp = Secp256k1::new();
+        let public_keys: Vec<_> = keys(3).iter().map(|key| key.public_key(&secp)).collect();
+        Multisig::new(&public_keys, 2, KeyOrdering::SortedMulti).unwrap()
+    }
+
+    /// Spends `amounts` of a 2-of-3 P2WSH to one 50000 sat recipient, with
+    /// change back to the multisig.
+    fn build_2_of_3(amounts: &[u64], fee_rate: f64) -> Result<Unsigned, BuildError> {
+        let multisig = multisig_2_of_3();
+        let script_pubkey = multisig.p2wsh_address(Network::Bitcoin).script_pubkey();
+        build(
+            &Wrapping::P2wsh.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts(&script_pubkey, amounts),
+            &[recipient(50_000)],
+            &script_pubkey,
+            fee_rate,
+        )
+    }
+
+    fn output_total(tx: &Transaction) -> u64 {
+        tx.output.iter().map(|output| output.value).sum()
+    }
+
+    #[test]
+    fn adds_change_above_dust() {
+        let unsigned = build_2_of_3(&[60_000, 40_000], 2.0).unwrap();
+        assert_eq!(unsigned.change, Some(1));
+        assert_eq!(unsigned.tx.output.len(), 2);
+        assert_eq!(output_total(&unsigned.tx) + unsigned.fee.to_sat(), 100_000);
+        // The fee pays for the change output too
+        let template = Wrapping::P2wsh.template(&multisig_2_of_3());
+        let weight = signed_weight(&unsigned.tx, &template);
+        assert_eq!(unsigned.fee, fee_for(weight, 2.0));
+    }
+
+    #[test]
+    fn leaves_dust_change_to_the_fee() {
+        let fee_with_change = build_2_of_3(&[100_000], 1.0).unwrap().fee.to_sat();
+        // 100 sats left after that fee is under the 330 sat P2WSH dust limit
+        let available = 50_000 + fee_with_change + 100;
+        let unsigned = build_2_of_3(&[available], 1.0).unwrap();
+        assert_eq!(unsigned.change, None);
+        assert_eq!(unsigned.tx.output.len(), 1);
+        assert_eq!(unsigned.fee.to_sat(), fee_with_change + 100);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        match build_2_of_3(&[30_000, 20_000], 1.0) {
+            Err(BuildError::InsufficientFunds { available, needed }) => {
+                assert_eq!(available, Amount::from_sat(50_000));
+                assert!(needed > available);
+            }
+            other => panic!("{:?}", other.map(|unsigned| unsigned.tx)),
+        }
+    }
+
+    #[test]
+    fn zero_fee_rate() {
+        let unsigned = build_2_of_3(&[50_000], 0.0).unwrap();
+        assert_eq!(unsigned.fee, Amount::ZERO);
+        assert_eq!(unsigned.change, None);
+
+        let unsigned = build_2_of_3(&[50_000, 1_000], 0.0).unwrap();
+        assert_eq!(unsigned.fee, Amount::ZERO);
+        assert_eq!(unsigned.change, Some(1));
+        assert_eq!(unsigned.tx.output[1].value, 1_000);
+    }
+
+    #[test]
+    fn rejects_duplicate_inputs() {
+        let multisig = multisig_2_of_3();
+        let script_pubkey = multisig.p2wsh_address(Network::Bitcoin).script_pubkey();
+        let mut prevouts = prevouts(&script_pubkey, &[30_000, 40_000]);
+        prevouts.push(prevouts[0].clone());
+        match build(
+            &Wrapping::P2wsh.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts,
+            &[recipient(50_000)],
+            &script_pubkey,
+            1.0,
+        ) {
+            Err(BuildError::DuplicateInput(outpoint)) => assert_eq!(outpoint, prevouts[0].outpoint),
+            other => panic!("{:?}", other.map(|unsigned| unsigned.tx)),
+        }
+    }
+
+    #[test]
+    fn refuses_legacy_single_without_output() {
+        let secp = Secp256k1::new();
+        let keys = keys(3);
+        let multisig = multisig_2_of_3();
+        let script_pubkey = Wrapping::P2sh
+            .address(&multisig, Network::Bitcoin)
+            .unwrap()
+            .script_pubkey();
+        let prevouts = prevouts(&script_pubkey, &[30_000, 20_000]);
+        let unsigned = build(
+            &Wrapping::P2sh.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts,
+            &[recipient(50_000)],
+            &script_pubkey,
+            0.0,
+        )
+        .unwrap();
+        assert_eq!(unsigned.tx.output.len(), 1);
+
+        let sign_with = |sighash_types: &[EcdsaSighashType]| {
+            let mut tx = unsigned.tx.clone();
+            sign(
+                &secp,
+                &mut tx,
+                &prevouts,
+                Wrapping::P2sh,
+                multisig.witness_script(),
+                &keys[..2],
+                sighash_types,
+            )
+        };
+        // Input 0 has an output to commit to, input 1 does not
+        sign_with(&[EcdsaSighashType::Single, EcdsaSighashType::All]).unwrap();
+        for ty in [
+            EcdsaSighashType::Single,
+            EcdsaSighashType::SinglePlusAnyoneCanPay,
+        ] {
+            match sign_with(&[EcdsaSighashType::All, ty]) {
+                Err(BuildError::Sighash(sighash::Error::SingleWithoutCorrespondingOutput {
+                    index: 1,
+                    outputs_size: 1,
+                })) => {}
+                other => panic!("{}: {:?}", ty, other),
+            }
+        }
+    }
+
+    #[test]
+    fn signed_weight_is_exact() {
+        let secp = Secp256k1::new();
+        for (m, n) in [(1, 1), (2, 3), (3, 5), (15, 15)] {
+            let keys = keys(n);
+            let public_keys: Vec<_> = keys.iter().map(|key| key.public_key(&secp)).collect();
+            let multisig = Multisig::new(&public_keys, m, KeyOrdering::SortedMulti).unwrap();
+            for wrapping in Wrapping::ALL {
+                let script_pubkey = wrapping
+                    .address(&multisig, Network::Bitcoin)
+                    .unwrap()
+                    .script_pubkey();
+                let prevouts = prevouts(&script_pubkey, &[60_000, 70_000]);
+                let template = wrapping.template(&multisig);
+                let unsigned = build(
+                    &template,
+                    PackedLockTime::ZERO,
+                    &prevouts,
+                    &[recipient(50_000)],
+                    &script_pubkey,
+                    2.0,
+                )
+                .unwrap();
+                let mut tx = unsigned.tx.clone();
+                sign(
+                    &secp,
+                    &mut tx,
+                    &prevouts,
+                    wrapping,
+                    multisig.witness_script(),
+                    &keys[..m],
+                    &[EcdsaSighashType::All; 2],
+                )
+                .unwrap();
+                assert_eq!(
+                    tx.weight(),
+                    signed_weight(&unsigned.tx, &template),
+                    "{}-of-{} {}",
+                    m,
+                    n,
+                    wrapping
+                );
+            }
+        }
+    }
+}
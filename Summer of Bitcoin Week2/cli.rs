@@ -0,0 +1,100 @@
+use bitcoin::{network::constants::Network, util::sighash::EcdsaSighashType};
+use std::error::Error;
+
+/// Command-line `--flag [value]` pairs. Flags listed as switches never take a
+/// value; every other flag takes the next argument (or the part after `=`).
+pub struct Args {
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    pub fn parse(args: &[String], switches: &[&str]) -> Result<Args, String> {
+        let mut flags = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => return Err(format!("unexpected argument {}", arg)),
+            };
+            if let Some((name, value)) = name.split_once('=') {
+                flags.push((name.to_owned(), Some(value.to_owned())));
+            } else if switches.contains(&name) {
+                flags.push((name.to_owned(), None));
+            } else {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("--{} needs a value", name))?;
+                flags.push((name.to_owned(), Some(value.clone())));
+            }
+        }
+        Ok(Args { flags })
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.flags.iter().any(|(n, _)| n == name)
+    }
+
+    /// The last value given for `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    /// Every value given for a repeatable flag, in order.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|(n, _)| n == name)
+            .filter_map(|(_, v)| v.as_deref())
+            .collect()
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        self.value(name)
+            .ok_or_else(|| format!("--{} is required", name))
+    }
+
+    pub fn network(&self) -> Result<Network, Box<dyn Error>> {
+        match self.value("network") {
+            None => Ok(Network::Bitcoin),
+            Some(name) => parse_network(name),
+        }
+    }
+}
+
+pub fn parse_network(name: &str) -> Result<Network, Box<dyn Error>> {
+    match name {
+        "bitcoin" | "main" | "mainnet" => Ok(Network::Bitcoin),
+        "testnet" | "test" => Ok(Network::Testnet),
+        "signet" => Ok(Network::Signet),
+        "regtest" => Ok(Network::Regtest),
+        _ => Err(format!("unknown network {}", name).into()),
+    }
+}
+
+/// Parses a sighash type such as `ALL`, `SINGLE|ANYONECANPAY` or
+/// `SIGHASH_NONE|SIGHASH_ANYONECANPAY`, in any case.
+pub fn parse_sighash(s: &str) -> Result<EcdsaSighashType, String> {
+    let mut base = None;
+    let mut anyone_can_pay = false;
+    for part in s.split('|') {
+        let part = part.trim().to_ascii_uppercase();
+        match part.strip_prefix("SIGHASH_").unwrap_or(&part) {
+            "ALL" if base.is_none() => base = Some(EcdsaSighashType::All),
+            "NONE" if base.is_none() => base = Some(EcdsaSighashType::None),
+            "SINGLE" if base.is_none() => base = Some(EcdsaSighashType::Single),
+            "ANYONECANPAY" if !anyone_can_pay => anyone_can_pay = true,
+            _ => return Err(format!("unknown sighash type {}", s)),
+        }
+    }
+    let base = base.ok_or_else(|| format!("sighash type {} has no ALL, NONE or SINGLE", s))?;
+    Ok(match (base, anyone_can_pay) {
+        (EcdsaSighashType::All, true) => EcdsaSighashType::AllPlusAnyoneCanPay,
+        (EcdsaSighashType::None, true) => EcdsaSighashType::NonePlusAnyoneCanPay,
+        (EcdsaSighashType::Single, true) => EcdsaSighashType::SinglePlusAnyoneCanPay,
+        (base, _) => base,
+    })
+}
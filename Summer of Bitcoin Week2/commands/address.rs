@@ -0,0 +1,69 @@
+use super::{key_origins, public_keys};
+use crate::account::ScriptType;
+use crate::builder::{self, Wrapping};
+use crate::cli::Args;
+use crate::descriptor::{Descriptor, DescriptorKey};
+use crate::multisig::{KeyOrdering, Multisig};
+use std::error::Error;
+
+/// Prints the script, its address under each wrapping and what a signed
+/// input costs in each.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let threshold: usize = args.required("threshold")?.parse()?;
+    let keys = public_keys(&args, "key")?;
+    let ordering = if args.has("sorted") {
+        KeyOrdering::SortedMulti
+    } else {
+        KeyOrdering::Multi
+    };
+    let network = args.network()?;
+    let origins = key_origins(&args)?;
+
+    let multisig = Multisig::new(&keys, threshold, ordering)?;
+    let descriptor = Descriptor {
+        script_type: ScriptType::P2shP2wsh,
+        threshold,
+        ordering,
+        keys: keys
+            .iter()
+            .map(|&key| DescriptorKey::Single {
+                origin: origins
+                    .iter()
+                    .find(|origin| origin.key == key)
+                    .map(|origin| origin.source.clone()),
+                key,
+            })
+            .collect(),
+    };
+    println!(
+        "Policy:         {}-of-{}",
+        multisig.threshold(),
+        multisig.keys().len()
+    );
+    println!(
+        "Witness script: {}",
+        hex::encode(multisig.witness_script().as_bytes())
+    );
+    println!("P2WSH:          {}", multisig.p2wsh_address(network));
+    println!("P2SH-P2WSH:     {}", multisig.p2sh_p2wsh_address(network));
+    match multisig.p2sh_address(network) {
+        Ok(address) => println!("P2SH:           {}", address),
+        Err(e) => println!("P2SH:           none, {}", e),
+    }
+    let fee_rate: f64 = args.value("fee-rate").unwrap_or("1").parse()?;
+    println!("Signed input at {} sat/vB:", fee_rate);
+    for wrapping in Wrapping::ALL {
+        let weight = builder::input_weight(&wrapping.template(&multisig));
+        let vsize = weight as f64 / 4.0;
+        println!(
+            "  {:<12} {:>6} WU {:>6.2} vB {:>6.0} sat",
+            wrapping,
+            weight,
+            vsize,
+            (vsize * fee_rate).ceil()
+        );
+    }
+    println!("Descriptor:     {}", descriptor);
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use super::psbt::write_psbt;
+use super::{
+    load_prevouts, multisig_from_args, outputs, report, report_policy, sighash_types, signing_keys,
+};
+use crate::builder::{self, Wrapping};
+use crate::cli::Args;
+use crate::decode;
+use crate::psbt;
+use crate::verify::{self, Flags};
+use bitcoin::{
+    blockdata::locktime::PackedLockTime, consensus::encode::serialize_hex, secp256k1::Secp256k1,
+};
+use std::error::Error;
+use std::fs;
+
+/// Builds and signs a multisig spend and writes it to out.txt, or hands it
+/// to the cosigners as a PSBT or an unsigned transaction.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted", "unsigned"])?;
+    println!("Starting transaction creation...");
+
+    //Initialize secp256k1 context for signing
+    let secp = Secp256k1::new();
+
+    //private keys from WIF, xprv/path or keystores, for the chosen network
+    let network = args.network()?;
+    let keys = signing_keys(&secp, &args, network)?;
+    println!("{} signing key(s) loaded.", keys.len());
+
+    //script: from --descriptor, or --threshold of the --key public keys,
+    //by default all of the signing keys
+    let own_keys = keys.iter().map(|key| key.public_key(&secp)).collect();
+    let (multisig, wrapping, origins) = multisig_from_args(&secp, &args, own_keys)?;
+    let witness_script = multisig.witness_script().clone();
+    println!("Witness script built.");
+
+    //P2SH-P2WSH by default: redeem script 0 <sha256 of witness script>
+    let script_pubkey = wrapping.address(&multisig, network)?.script_pubkey();
+    println!("{} output script created.", wrapping);
+
+    // Outputs being spent; each must be locked by that same script
+    let prevouts = load_prevouts(&args, &script_pubkey)?;
+    println!("Spending {} prevout(s).", prevouts.len());
+
+    // Recipients, and change back to the multisig unless --change is given
+    let (recipients, change, fee_rate) = outputs(&args, script_pubkey)?;
+
+    let template = wrapping.template(&multisig);
+    let unsigned = builder::build(
+        &template,
+        PackedLockTime::ZERO,
+        &prevouts,
+        &recipients,
+        &change,
+        fee_rate,
+    )?;
+    let mut tx = unsigned.tx;
+    println!(
+        "Transaction built: {} input(s), {} output(s)",
+        tx.input.len(),
+        tx.output.len()
+    );
+    let estimated_weight = builder::signed_weight(&tx, &template);
+    println!("Fee: {} at {} sat/vB", unsigned.fee, fee_rate);
+    if let Some(index) = unsigned.change {
+        println!(
+            "Change: {} sats in output {}",
+            tx.output[index].value, index
+        );
+    }
+
+    let sighash_types = sighash_types(&args, tx.input.len())?;
+    for (index, &ty) in sighash_types.iter().enumerate() {
+        if builder::commits_to_no_outputs(ty, index, &tx) {
+            println!(
+                "warning: input {} signs with {}, which commits to no outputs",
+                index, ty
+            );
+        }
+    }
+
+    // Hand the spend to the cosigners instead of signing it here
+    if let Some(path) = args.value("psbt") {
+        let psbt = psbt::create(
+            &tx,
+            &prevouts,
+            &multisig,
+            wrapping,
+            &origins,
+            unsigned.change,
+            &sighash_types,
+        )?;
+        write_psbt(path, &psbt)?;
+        println!("PSBT written to {}", path);
+        return Ok(());
+    }
+
+    // Or as a bare transaction for `partial sign` on air-gapped machines
+    if args.has("unsigned") {
+        println!("Unsigned transaction: {}", serialize_hex(&tx));
+        return Ok(());
+    }
+
+    // Sign every input; segwit ones over their own prevout amount (BIP143)
+    if keys.is_empty() {
+        return Err("no signing keys; use --signing-key, --keystore, --psbt or --unsigned".into());
+    }
+    builder::sign(
+        &secp,
+        &mut tx,
+        &prevouts,
+        wrapping,
+        &witness_script,
+        &keys,
+        &sighash_types,
+    )?;
+    if wrapping == Wrapping::P2sh {
+        println!("scriptSigs set.");
+    } else {
+        println!("Witness stacks set.");
+    }
+    println!(
+        "Weight: {} WU (estimated {} WU before signing)",
+        tx.weight(),
+        estimated_weight
+    );
+
+    print!(
+        "{}",
+        decode::Pretty {
+            tx: &tx,
+            network: args.network()?,
+        }
+    );
+
+    // Run every input through the script interpreter before writing it out
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+
+    // Serialize the transaction to hex
+    let tx_hex = serialize_hex(&tx);
+    println!("Transaction serialized: {}...", &tx_hex[..20]);
+
+    // Write the serialized transaction to out.txt
+    fs::write("out.txt", &tx_hex)?;
+    println!("Transaction hex written to out.txt");
+
+    // Debugging: Print the full transaction hex
+    println!("Full transaction hex: {}", tx_hex);
+    Ok(())
+}
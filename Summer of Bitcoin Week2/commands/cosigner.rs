@@ -0,0 +1,157 @@
+use super::keystore::write_keystore;
+use super::passphrase;
+use crate::account::{self, Account, Quorum, ScriptType};
+use crate::cli::Args;
+use crate::descriptor::{Descriptor, DescriptorKey};
+use crate::keys;
+use crate::multisig::KeyOrdering;
+use crate::USAGE;
+use bitcoin::{
+    secp256k1::{Secp256k1, Signing},
+    util::bip32::ExtendedPrivKey,
+};
+use std::error::Error;
+use std::fs;
+use zeroize::Zeroizing;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("new") => seed(&args[1..], false),
+        Some("restore") => seed(&args[1..], true),
+        Some("export") => export(&args[1..]),
+        Some("derive") => derive(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Generates a BIP39 mnemonic (or with `--raw` a seed), or restores one
+/// from stdin, and keeps the master xprv in a keystore.
+fn seed(args: &[String], restore: bool) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["raw"])?;
+    let network = args.network()?;
+    let seed: Zeroizing<Vec<u8>> = if restore {
+        let mut line = Zeroizing::new(String::new());
+        std::io::stdin().read_line(&mut line)?;
+        if args.has("raw") {
+            Zeroizing::new(hex::decode(line.trim())?)
+        } else {
+            Zeroizing::new(bip39::Mnemonic::parse(line.trim())?.to_seed("").to_vec())
+        }
+    } else if args.has("raw") {
+        let seed = account::generate_seed();
+        println!("Seed (keep it secret, it is the only backup):");
+        println!("{}", hex::encode(&seed[..]));
+        Zeroizing::new(seed.to_vec())
+    } else {
+        let words = args.value("words").unwrap_or("24").parse()?;
+        let mnemonic = account::generate_mnemonic(words)?;
+        println!("Mnemonic (keep it secret, it is the only backup):");
+        println!("{}", mnemonic);
+        Zeroizing::new(mnemonic.to_seed("").to_vec())
+    };
+
+    let secp = Secp256k1::new();
+    let mut master = ExtendedPrivKey::new_master(network, &seed)?;
+    let spec = Zeroizing::new(master.to_string());
+    let accounts = bip48_accounts(&secp, &master, &args);
+    keys::erase_xprv(&mut master);
+    let accounts = accounts?;
+    let out = write_keystore(&args, &spec)?;
+    println!("Master key written to {}", out);
+    for account in accounts {
+        println!(
+            "{:<11} {}",
+            account.script_type().expect("BIP48 path"),
+            account
+        );
+    }
+    Ok(())
+}
+
+/// Prints the BIP48 account xpubs in a keystore for the other cosigners.
+fn export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let json = fs::read_to_string(args.required("keystore")?)?;
+    let spec = keys::decrypt_keystore(
+        &json,
+        passphrase(&args, "Keystore passphrase: ")?.as_bytes(),
+    )?;
+    let secp = Secp256k1::new();
+    let mut master = keys::parse_xprv(&spec, args.network()?)?;
+    let accounts = bip48_accounts(&secp, &master, &args);
+    keys::erase_xprv(&mut master);
+    for account in accounts? {
+        println!(
+            "{:<11} {}",
+            account.script_type().expect("BIP48 path"),
+            account
+        );
+    }
+    Ok(())
+}
+
+/// Both BIP48 accounts of `--account` (0 by default) below `master`.
+fn bip48_accounts<C: Signing>(
+    secp: &Secp256k1<C>,
+    master: &ExtendedPrivKey,
+    args: &Args,
+) -> Result<Vec<Account>, Box<dyn Error>> {
+    let account = args.value("account").unwrap_or("0").parse()?;
+    [ScriptType::P2shP2wsh, ScriptType::P2wsh]
+        .into_iter()
+        .map(|ty| Ok(Account::from_master(secp, master, account, ty)?))
+        .collect()
+}
+
+/// Derives the quorum at `--index` from every cosigner's `--account-key`,
+/// with the origins `build --psbt` and the key paths signers need.
+fn derive(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["change"])?;
+    let accounts = args
+        .values("account-key")
+        .into_iter()
+        .map(str::parse)
+        .collect::<Result<Vec<Account>, _>>()?;
+    let threshold = args.required("threshold")?.parse()?;
+    let index = args.value("index").unwrap_or("0").parse()?;
+    let network = args.network()?;
+
+    let secp = Secp256k1::verification_only();
+    let quorum = Quorum::derive(
+        &secp,
+        &accounts,
+        threshold,
+        network,
+        args.has("change"),
+        index,
+    )?;
+    let multisig = &quorum.multisig;
+    println!(
+        "Policy:         {}-of-{} sortedmulti, {}",
+        multisig.threshold(),
+        multisig.keys().len(),
+        quorum.script_type
+    );
+    println!(
+        "Witness script: {}",
+        hex::encode(multisig.witness_script().as_bytes())
+    );
+    println!(
+        "Address:        {}",
+        quorum.script_type.address(multisig, network)
+    );
+    for origin in &quorum.origins {
+        println!("--origin {}", origin);
+    }
+    let descriptor = Descriptor {
+        script_type: quorum.script_type,
+        threshold,
+        ordering: KeyOrdering::SortedMulti,
+        keys: accounts
+            .iter()
+            .map(|account| DescriptorKey::from_account(account, args.has("change")))
+            .collect(),
+    };
+    println!("Descriptor:     {}", descriptor);
+    Ok(())
+}
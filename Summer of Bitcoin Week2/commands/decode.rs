@@ -0,0 +1,23 @@
+use crate::cli::Args;
+use crate::decode;
+use bitcoin::{blockdata::transaction::Transaction, consensus::encode::deserialize};
+use std::error::Error;
+use std::fs;
+
+/// Pretty-prints a raw transaction, by default the one in out.txt.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let tx_hex = match args.value("tx") {
+        Some(tx_hex) => tx_hex.to_owned(),
+        None => fs::read_to_string("out.txt")?,
+    };
+    let tx: Transaction = deserialize(&hex::decode(tx_hex.trim())?)?;
+    print!(
+        "{}",
+        decode::Pretty {
+            tx: &tx,
+            network: args.network()?,
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use crate::cli::Args;
+use crate::descriptor::Descriptor;
+use bitcoin::secp256k1::Secp256k1;
+use std::error::Error;
+
+/// Checks a descriptor and prints the script, address and key origins it
+/// stands for, at `--index` if it is ranged.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let descriptor: Descriptor = args.required("descriptor")?.parse()?;
+    let index = args.value("index").map(str::parse).transpose()?;
+    let network = args.network()?;
+
+    let secp = Secp256k1::verification_only();
+    let (multisig, origins) = descriptor.at(&secp, index, network)?;
+    println!("Descriptor:     {}", descriptor);
+    println!(
+        "Policy:         {}-of-{}, {}",
+        multisig.threshold(),
+        multisig.keys().len(),
+        descriptor.script_type
+    );
+    println!(
+        "Witness script: {}",
+        hex::encode(multisig.witness_script().as_bytes())
+    );
+    println!(
+        "Address:        {}",
+        descriptor.script_type.address(&multisig, network)
+    );
+    for origin in &origins {
+        println!("--origin {}", origin);
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use super::{load_prevouts, outputs, report, report_policy, sighash_types, signing_keys};
+use crate::builder;
+use crate::cli::Args;
+use crate::htlc::{self, HashLock, Htlc};
+use crate::timelock::Timelock;
+use crate::verify::{self, Flags};
+use crate::USAGE;
+use bitcoin::{
+    blockdata::{locktime::PackedLockTime, script::Script, transaction::Transaction},
+    consensus::encode::{deserialize, serialize_hex},
+    secp256k1::Secp256k1,
+    util::sighash::SighashCache,
+};
+use std::error::Error;
+use std::fs;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("address") => address(&args[1..]),
+        Some("claim") => spend(&args[1..], htlc::Path::Claim),
+        Some("refund") => spend(&args[1..], htlc::Path::Refund),
+        Some("extract") => extract(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Reads the HTLC the `htlc` subcommands share: `--receiver` and `--sender`
+/// keys, `--sha256` or `--hash160` of the preimage, and a `--csv` or
+/// `--cltv` refund.
+fn from_args(args: &Args) -> Result<Htlc, Box<dyn Error>> {
+    let hashlock = match (args.value("sha256"), args.value("hash160")) {
+        (Some(hash), None) => HashLock::Sha256(hash.parse()?),
+        (None, Some(hash)) => HashLock::Hash160(hash.parse()?),
+        _ => return Err("exactly one of --sha256 <hash> or --hash160 <hash> is required".into()),
+    };
+    let refund = match (args.value("csv"), args.value("cltv")) {
+        (Some(blocks), None) => Timelock::Blocks(blocks.parse()?),
+        (None, Some(height)) => Timelock::Height(height.parse()?),
+        _ => return Err("exactly one of --csv <blocks> or --cltv <height> is required".into()),
+    };
+    Ok(Htlc::new(
+        hashlock,
+        args.required("receiver")?.parse()?,
+        args.required("sender")?.parse()?,
+        refund,
+    )?)
+}
+
+fn address(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let htlc = from_args(&args)?;
+    let network = args.network()?;
+    match htlc.hashlock() {
+        HashLock::Sha256(hash) => println!("Hashlock:       SHA256 {}", hash),
+        HashLock::Hash160(hash) => println!("Hashlock:       HASH160 {}", hash),
+    }
+    println!("Refund:         {}", htlc.refund());
+    println!(
+        "Witness script: {}",
+        hex::encode(htlc.witness_script().as_bytes())
+    );
+    println!("P2WSH:          {}", htlc.p2wsh_address(network));
+    println!("P2SH-P2WSH:     {}", htlc.p2sh_p2wsh_address(network));
+    Ok(())
+}
+
+/// Claims HTLC outputs with `--preimage` or refunds them after the
+/// timelock, signing with the one `--signing-key` or `--keystore` key.
+/// Change goes to that key's P2WPKH address unless `--change` is given.
+fn spend(args: &[String], path: htlc::Path) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["wrapped"])?;
+    let htlc = from_args(&args)?;
+    let network = args.network()?;
+    let secp = Secp256k1::new();
+    let keys = signing_keys(&secp, &args, network)?;
+    let key = match &keys[..] {
+        [key] => key,
+        _ => return Err("exactly one signing key is required".into()),
+    };
+    let preimage = match path {
+        htlc::Path::Claim => hex::decode(args.required("preimage")?)?,
+        htlc::Path::Refund => Vec::new(),
+    };
+
+    let wrapped = args.has("wrapped");
+    let own_script = if wrapped {
+        htlc.p2sh_p2wsh_address(network).script_pubkey()
+    } else {
+        htlc.p2wsh_address(network).script_pubkey()
+    };
+    let prevouts = load_prevouts(&args, &own_script)?;
+    let wpubkey_hash = key
+        .public_key(&secp)
+        .wpubkey_hash()
+        .expect("key is compressed");
+    let (recipients, change, fee_rate) = outputs(&args, Script::new_v0_p2wpkh(&wpubkey_hash))?;
+    let lock_time = match path {
+        htlc::Path::Claim => PackedLockTime::ZERO,
+        htlc::Path::Refund => htlc.refund().lock_time(),
+    };
+    let unsigned = builder::build(
+        &htlc.template(path, wrapped),
+        lock_time,
+        &prevouts,
+        &recipients,
+        &change,
+        fee_rate,
+    )?;
+    let mut tx = unsigned.tx;
+    println!(
+        "{:?} of {} input(s), fee {}",
+        path,
+        tx.input.len(),
+        unsigned.fee
+    );
+
+    let sighash_types = sighash_types(&args, tx.input.len())?;
+    let mut cache = SighashCache::new(&tx);
+    let mut witnesses = Vec::with_capacity(prevouts.len());
+    for (index, (prevout, &ty)) in prevouts.iter().zip(&sighash_types).enumerate() {
+        let sig = htlc.sign(&secp, &mut cache, index, prevout.amount.to_sat(), key, ty)?;
+        witnesses.push(match path {
+            htlc::Path::Claim => htlc.claim_witness(&sig, &preimage)?,
+            htlc::Path::Refund => htlc.refund_witness(&sig),
+        });
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    fs::write("out.txt", serialize_hex(&tx))?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
+
+/// Prints the preimage a claim transaction revealed.
+fn extract(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let htlc = from_args(&args)?;
+    let tx: Transaction = deserialize(&hex::decode(args.required("tx")?.trim())?)?;
+    match htlc.extract_preimage(&tx) {
+        Some(preimage) => {
+            println!("{}", hex::encode(preimage));
+            Ok(())
+        }
+        None => Err(format!("transaction {} does not claim this HTLC", tx.txid()).into()),
+    }
+}
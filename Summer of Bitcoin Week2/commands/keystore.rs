@@ -0,0 +1,51 @@
+use super::passphrase;
+use crate::cli::Args;
+use crate::keys::{self, Keys};
+use crate::USAGE;
+use bitcoin::secp256k1::Secp256k1;
+use std::error::Error;
+use std::fs;
+use zeroize::Zeroizing;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("create") => create(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Encrypts a key read from stdin, WIF or `xprv/path`, into a keystore.
+fn create(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let mut spec = Zeroizing::new(String::new());
+    std::io::stdin().read_line(&mut spec)?;
+
+    let secp = Secp256k1::new();
+    let mut keys = Keys::with_capacity(1);
+    keys.push(keys::parse_key(&secp, &spec, args.network()?)?);
+    let out = write_keystore(&args, &spec)?;
+    println!(
+        "Keystore for {} written to {}",
+        keys[0].public_key(&secp),
+        out
+    );
+    Ok(())
+}
+
+/// Encrypts `spec` under a new passphrase, asked twice unless it comes
+/// from `--passphrase-file`, and writes the keystore to `--out`.
+pub fn write_keystore<'a>(args: &'a Args, spec: &str) -> Result<&'a str, Box<dyn Error>> {
+    let out = args.required("out")?;
+    let passphrase = passphrase(args, "New passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("the passphrase must not be empty".into());
+    }
+    if args.value("passphrase-file").is_none()
+        && *passphrase != *self::passphrase(args, "Repeat passphrase: ")?
+    {
+        return Err("passphrases do not match".into());
+    }
+    let keystore = keys::encrypt_keystore(spec, passphrase.as_bytes())?;
+    fs::write(out, format!("{}\n", keystore))?;
+    Ok(out)
+}
@@ -0,0 +1,262 @@
+//! The subcommands `main` dispatches to, one module per family, and the
+//! argument handling they share.
+
+pub mod address;
+pub mod build;
+pub mod cosigner;
+pub mod decode;
+pub mod descriptor;
+pub mod htlc;
+pub mod keystore;
+pub mod musig;
+pub mod partial;
+pub mod policy;
+pub mod psbt;
+pub mod taproot;
+pub mod timelock;
+pub mod verify;
+
+use crate::builder::{Recipient, Wrapping};
+use crate::cli::{self, Args};
+use crate::descriptor::Descriptor;
+use crate::keys::{self, Keys};
+use crate::multisig::{KeyOrdering, Multisig};
+use crate::prevout::{self, Prevout, PrevoutError};
+use crate::psbt::KeyOrigin;
+use crate::verify::InputResult;
+use bitcoin::{
+    blockdata::{script::Script, transaction::Transaction},
+    consensus::encode::deserialize,
+    network::constants::Network,
+    secp256k1::{Secp256k1, Signing, Verification},
+    util::sighash::EcdsaSighashType,
+    PublicKey,
+};
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Prints one line per input and returns whether all of them passed.
+fn report(results: &[InputResult]) -> bool {
+    for result in results {
+        println!("{}", result);
+    }
+    results.iter().all(InputResult::is_valid)
+}
+
+/// Reads `--tx` and the `--prevout`s or `--prevouts` file it spends, put
+/// in input order.
+fn tx_with_prevouts(args: &Args) -> Result<(Transaction, Vec<Prevout>), Box<dyn Error>> {
+    let tx: Transaction = deserialize(&hex::decode(args.required("tx")?.trim())?)?;
+    let mut prevouts = Vec::new();
+    for value in args.values("prevout") {
+        prevouts.push(value.parse::<Prevout>()?);
+    }
+    if let Some(path) = args.value("prevouts") {
+        prevouts.extend(prevout::from_json(&fs::read_to_string(path)?)?);
+    }
+    if prevouts.len() != tx.input.len() {
+        return Err(format!(
+            "{} prevouts given for {} inputs",
+            prevouts.len(),
+            tx.input.len()
+        )
+        .into());
+    }
+    let prevouts = prevout::order_for(&tx, prevouts)?;
+    Ok((tx, prevouts))
+}
+
+/// The `--sighash` type of each input: none means ALL everywhere, one
+/// applies to every input, otherwise one per input in order.
+fn sighash_types(args: &Args, inputs: usize) -> Result<Vec<EcdsaSighashType>, Box<dyn Error>> {
+    let types = args
+        .values("sighash")
+        .into_iter()
+        .map(cli::parse_sighash)
+        .collect::<Result<Vec<_>, _>>()?;
+    match types.len() {
+        0 => Ok(vec![EcdsaSighashType::All; inputs]),
+        1 => Ok(vec![types[0]; inputs]),
+        n if n == inputs => Ok(types),
+        n => Err(format!("{} sighash types given for {} inputs", n, inputs).into()),
+    }
+}
+
+/// Reads the public keys given with `flag`.
+fn public_keys(args: &Args, flag: &str) -> Result<Vec<PublicKey>, Box<dyn Error>> {
+    Ok(args
+        .values(flag)
+        .into_iter()
+        .map(PublicKey::from_str)
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Reads the passphrase for keystores from `--passphrase-file`, or asks for
+/// it on the terminal.
+fn passphrase(args: &Args, prompt: &str) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let mut passphrase = Zeroizing::new(match args.value("passphrase-file") {
+        Some(path) => fs::read_to_string(path)?,
+        None => rpassword::prompt_password(prompt)?,
+    });
+    let len = passphrase.trim_end_matches(['\r', '\n']).len();
+    passphrase.truncate(len);
+    Ok(passphrase)
+}
+
+/// Loads the private keys given with `--signing-key` (WIF or `xprv/path`)
+/// and `--keystore`, refusing any encoded for another network. Keystores
+/// holding an xprv are derived along `--key-path` if given.
+fn signing_keys<C: Signing>(
+    secp: &Secp256k1<C>,
+    args: &Args,
+    network: Network,
+) -> Result<Keys, Box<dyn Error>> {
+    let specs = args.values("signing-key");
+    let keystores = args.values("keystore");
+    let mut keys = Keys::with_capacity(specs.len() + keystores.len());
+    for spec in specs {
+        keys.push(keys::parse_key(secp, spec, network)?);
+    }
+    if !keystores.is_empty() {
+        let passphrase = passphrase(args, "Keystore passphrase: ")?;
+        for path in keystores {
+            let json = fs::read_to_string(path)?;
+            keys.push(keys::open_keystore(
+                secp,
+                &json,
+                passphrase.as_bytes(),
+                args.value("key-path"),
+                network,
+            )?);
+        }
+    }
+    Ok(keys)
+}
+
+/// Reads the `--origin` values, `[fingerprint/path]pubkey` each.
+fn key_origins(args: &Args) -> Result<Vec<KeyOrigin>, Box<dyn Error>> {
+    Ok(args
+        .values("origin")
+        .into_iter()
+        .map(str::parse)
+        .collect::<Result<Vec<KeyOrigin>, _>>()?)
+}
+
+/// Reads `--to`, `--change` and `--fee-rate`; change goes to `own_script`
+/// unless `--change` names another address.
+fn outputs(
+    args: &Args,
+    own_script: Script,
+) -> Result<(Vec<Recipient>, Script, f64), Box<dyn Error>> {
+    let network = args.network()?;
+    let recipients = args
+        .values("to")
+        .into_iter()
+        .map(|to| Recipient::parse(to, network))
+        .collect::<Result<Vec<_>, _>>()?;
+    let change = match args.value("change") {
+        Some(address) => Recipient::parse(&format!("{}:0", address), network)?.script_pubkey,
+        None => own_script,
+    };
+    let fee_rate: f64 = args.value("fee-rate").unwrap_or("1").parse()?;
+    if !(fee_rate >= 0.0 && fee_rate.is_finite()) {
+        return Err(format!("invalid fee rate {}", fee_rate).into());
+    }
+    Ok((recipients, change, fee_rate))
+}
+
+/// Collects the outputs to spend from `--prevout`, `--prevouts` and
+/// `--funding-tx`, and checks each is locked by `script_pubkey`.
+fn load_prevouts(args: &Args, script_pubkey: &Script) -> Result<Vec<Prevout>, Box<dyn Error>> {
+    let mut prevouts = Vec::new();
+    for value in args.values("prevout") {
+        prevouts.push(value.parse::<Prevout>()?);
+    }
+    if let Some(path) = args.value("prevouts") {
+        prevouts.extend(prevout::from_json(&fs::read_to_string(path)?)?);
+    }
+    if let Some(tx_hex) = args.value("funding-tx") {
+        let vout = args.value("vout").map(str::parse).transpose()?;
+        prevouts.extend(prevout::from_funding_tx(tx_hex, vout, script_pubkey)?);
+    }
+    if prevouts.is_empty() {
+        return Err("no prevouts given; use --prevout, --prevouts or --funding-tx".into());
+    }
+    if let Some(outpoint) = prevout::first_duplicate(&prevouts) {
+        return Err(PrevoutError::Duplicate(outpoint).into());
+    }
+    for prevout in &prevouts {
+        prevout.check_script(script_pubkey)?;
+    }
+    Ok(prevouts)
+}
+
+/// The unsigned `--tx` and the outputs it spends, checked to be locked by
+/// `own_script`, in input order.
+fn unsigned_tx(
+    args: &Args,
+    own_script: &Script,
+) -> Result<(Transaction, Vec<Prevout>), Box<dyn Error>> {
+    let tx: Transaction = deserialize(&hex::decode(args.required("tx")?.trim())?)?;
+    let prevouts = prevout::order_for(&tx, load_prevouts(args, own_script)?)?;
+    Ok((tx, prevouts))
+}
+
+/// Prints every relay policy violation and returns whether there were none.
+/// Non-standard transactions are still written out, as they may be mined.
+fn report_policy(tx: &Transaction, prevouts: &[Prevout]) -> Result<bool, Box<dyn Error>> {
+    let violations = crate::policy::check(tx, prevouts)?;
+    if violations.is_empty() {
+        println!("Policy: standard");
+    }
+    for violation in &violations {
+        println!("Policy: {}", violation);
+    }
+    Ok(violations.is_empty())
+}
+
+/// The multisig script and wrapping from `--descriptor`, or `--threshold`
+/// of the `--key` public keys (by default `default_keys`) and
+/// `--wrapping`, with every key origin known.
+fn multisig_from_args<C: Verification>(
+    secp: &Secp256k1<C>,
+    args: &Args,
+    default_keys: Vec<PublicKey>,
+) -> Result<(Multisig, Wrapping, Vec<KeyOrigin>), Box<dyn Error>> {
+    let network = args.network()?;
+    let mut origins = key_origins(args)?;
+    let mut wrapping = args
+        .value("wrapping")
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(Wrapping::P2shP2wsh);
+    let multisig = if let Some(descriptor) = args.value("descriptor") {
+        let descriptor: Descriptor = descriptor.parse()?;
+        if args.has("wrapping") && wrapping != descriptor.script_type.into() {
+            return Err("--wrapping contradicts the descriptor".into());
+        }
+        wrapping = descriptor.script_type.into();
+        let index = args.value("index").map(str::parse).transpose()?;
+        let (multisig, descriptor_origins) = descriptor.at(secp, index, network)?;
+        origins.extend(descriptor_origins);
+        multisig
+    } else {
+        let mut public_keys = public_keys(args, "key")?;
+        if public_keys.is_empty() {
+            public_keys = default_keys;
+        }
+        let threshold = match args.value("threshold") {
+            Some(threshold) => threshold.parse()?,
+            None => public_keys.len(),
+        };
+        let ordering = if args.has("sorted") {
+            KeyOrdering::SortedMulti
+        } else {
+            KeyOrdering::Multi
+        };
+        Multisig::new(&public_keys, threshold, ordering)?
+    };
+    Ok((multisig, wrapping, origins))
+}
@@ -0,0 +1,319 @@
+use super::{
+    load_prevouts, outputs, public_keys, report, report_policy, signing_keys, unsigned_tx,
+};
+use crate::builder::{self, InputTemplate};
+use crate::cli::Args;
+use crate::keys::Keys;
+use crate::musig::{
+    self, AggNonce, KeyAggContext, NonceMessage, PartialSig, PubNonce, SecNonce, Session,
+    SignatureMessage,
+};
+use crate::prevout::{self, Prevout};
+use crate::verify::{self, Flags};
+use crate::USAGE;
+use bitcoin::{
+    blockdata::{locktime::PackedLockTime, transaction::Transaction},
+    consensus::encode::{deserialize, serialize_hex},
+    secp256k1::{Secp256k1, Signing, Verification},
+    util::sighash::{Prevouts, SchnorrSighashType, SighashCache},
+};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("address") => address(&args[1..]),
+        Some("build") => build(&args[1..]),
+        Some("nonce") => nonce(&args[1..]),
+        Some("sign") => sign(&args[1..]),
+        Some("combine") => combine(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// The sorted `--key` values aggregated and tweaked into a key-path-only
+/// P2TR output key.
+fn from_args<C: Verification>(
+    secp: &Secp256k1<C>,
+    args: &Args,
+) -> Result<KeyAggContext, Box<dyn Error>> {
+    let mut keys: Vec<_> = public_keys(args, "key")?
+        .into_iter()
+        .map(|key| key.inner)
+        .collect();
+    musig::sort_keys(&mut keys);
+    let mut key_agg = KeyAggContext::new(secp, &keys)?;
+    key_agg.tweak_for_taproot(secp)?;
+    Ok(key_agg)
+}
+
+/// The BIP341 key-path sighash of every input, the message each session
+/// signs.
+fn key_path_sighashes(
+    tx: &Transaction,
+    prevouts: &[Prevout],
+) -> Result<Vec<[u8; 32]>, Box<dyn Error>> {
+    let spent = prevout::spent_outputs(prevouts);
+    let mut cache = SighashCache::new(tx);
+    (0..tx.input.len())
+        .map(|index| {
+            let sighash = cache.taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(&spent),
+                SchnorrSighashType::Default,
+            )?;
+            Ok(sighash[..].try_into()?)
+        })
+        .collect()
+}
+
+/// The `flag` messages ordered as the aggregate's keys, one from every
+/// signer with an item for each of `inputs`.
+fn messages<T: FromStr<Err = musig::MusigError>>(
+    args: &Args,
+    flag: &str,
+    key_agg: &KeyAggContext,
+    inputs: usize,
+) -> Result<Vec<musig::Message<T>>, Box<dyn Error>> {
+    let mut messages = args
+        .values(flag)
+        .into_iter()
+        .map(str::parse)
+        .collect::<Result<Vec<musig::Message<T>>, _>>()?;
+    let mut ordered = Vec::with_capacity(key_agg.keys().len());
+    for key in key_agg.keys() {
+        let position = messages
+            .iter()
+            .position(|message| message.key == *key)
+            .ok_or_else(|| format!("no --{} from {}", flag, key))?;
+        let message = messages.swap_remove(position);
+        if message.items.len() != inputs {
+            return Err(format!(
+                "--{} from {} has {} item(s) for {} input(s)",
+                flag,
+                key,
+                message.items.len(),
+                inputs
+            )
+            .into());
+        }
+        ordered.push(message);
+    }
+    if let Some(message) = messages.first() {
+        return Err(musig::MusigError::UnknownKey(message.key).into());
+    }
+    Ok(ordered)
+}
+
+/// The one signing key given, which must be one of the aggregated keys.
+fn signer<C: Signing>(
+    secp: &Secp256k1<C>,
+    args: &Args,
+    key_agg: &KeyAggContext,
+) -> Result<Keys, Box<dyn Error>> {
+    let keys = signing_keys(secp, args, args.network()?)?;
+    if keys.len() != 1 {
+        return Err("give exactly one signing key".into());
+    }
+    let key = keys[0].public_key(secp).inner;
+    if !key_agg.keys().contains(&key) {
+        return Err(musig::MusigError::UnknownKey(key).into());
+    }
+    Ok(keys)
+}
+
+/// Prints the aggregate key and the P2TR address it controls alone.
+fn address(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let secp = Secp256k1::verification_only();
+    let key_agg = from_args(&secp, &args)?;
+    let network = args.network()?;
+
+    println!(
+        "Policy:         {}-of-{} MuSig2",
+        key_agg.keys().len(),
+        key_agg.keys().len()
+    );
+    for key in key_agg.keys() {
+        println!("  key:          {}", key);
+    }
+    println!("Output key:     {}", key_agg.x_only());
+    println!("P2TR:           {}", key_agg.address(network));
+    println!(
+        "Signed input:   {} WU",
+        builder::input_weight(&InputTemplate::witness_only(musig::KEY_PATH_WITNESS_SIZE))
+    );
+    Ok(())
+}
+
+/// Builds the unsigned spend every signer then signs. Change goes back to
+/// the same address unless `--change` is given.
+fn build(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let secp = Secp256k1::verification_only();
+    let key_agg = from_args(&secp, &args)?;
+    let own_script = key_agg.address(args.network()?).script_pubkey();
+    let prevouts = load_prevouts(&args, &own_script)?;
+    let (recipients, change, fee_rate) = outputs(&args, own_script)?;
+    let unsigned = builder::build(
+        &InputTemplate::witness_only(musig::KEY_PATH_WITNESS_SIZE),
+        PackedLockTime::ZERO,
+        &prevouts,
+        &recipients,
+        &change,
+        fee_rate,
+    )?;
+    println!(
+        "Key-path spend of {} input(s), fee {}",
+        unsigned.tx.input.len(),
+        unsigned.fee
+    );
+    println!("{}", serialize_hex(&unsigned.tx));
+    Ok(())
+}
+
+/// First round: draws a nonce pair per input, keeps the secret halves in
+/// `--secnonce` for the second round and prints the public ones.
+fn nonce(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let secp = Secp256k1::new();
+    let key_agg = from_args(&secp, &args)?;
+    let keys = signer(&secp, &args, &key_agg)?;
+    let tx: Transaction = deserialize(&hex::decode(args.required("tx")?.trim())?)?;
+    let path = args.required("secnonce")?;
+    // Created atomically and readable by us alone: anyone who sees both
+    // the secret nonce and the partial signature can work out the key
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("{} exists; a secret nonce must never be reused", path).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let key = keys[0].public_key(&secp).inner;
+    let txid = tx.txid();
+    let mut secret = Zeroizing::new(String::new());
+    let mut message = musig::Message {
+        key,
+        items: Vec::with_capacity(tx.input.len()),
+    };
+    for index in 0..tx.input.len() as u32 {
+        // Binding the nonce to the transaction and input keeps a weak RNG
+        // from repeating it across spends
+        let mut extra_in = txid.to_vec();
+        extra_in.extend(index.to_be_bytes());
+        let secnonce = musig::nonce_gen(
+            Some(&keys[0].inner),
+            &key,
+            Some(&key_agg.x_only()),
+            None,
+            Some(&extra_in),
+        );
+        secret.push_str(&hex::encode(&secnonce.serialize()[..]));
+        secret.push('\n');
+        message.items.push(secnonce.public(&secp));
+    }
+    file.write_all(secret.as_bytes())?;
+    println!("--nonce {}", message);
+    Ok(())
+}
+
+/// Second round: signs every input with the nonces from `--secnonce`,
+/// which is deleted first so it can never sign twice.
+fn sign(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let secp = Secp256k1::new();
+    let key_agg = from_args(&secp, &args)?;
+    let keys = signer(&secp, &args, &key_agg)?;
+    let own_script = key_agg.address(args.network()?).script_pubkey();
+    let (tx, prevouts) = unsigned_tx(&args, &own_script)?;
+    let nonces: Vec<NonceMessage> = messages(&args, "nonce", &key_agg, tx.input.len())?;
+    let sighashes = key_path_sighashes(&tx, &prevouts)?;
+
+    let path = args.required("secnonce")?;
+    let secret = Zeroizing::new(fs::read_to_string(path)?);
+    fs::remove_file(path)?;
+    let secnonces = secret
+        .lines()
+        .map(|line| {
+            let bytes = Zeroizing::new(hex::decode(line)?);
+            Ok(SecNonce::from_slice(&bytes)?)
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    if secnonces.len() != tx.input.len() {
+        return Err(format!(
+            "{} holds {} nonce(s) for {} input(s)",
+            path,
+            secnonces.len(),
+            tx.input.len()
+        )
+        .into());
+    }
+
+    let key = keys[0].public_key(&secp).inner;
+    let mut message = musig::Message {
+        key,
+        items: Vec::with_capacity(tx.input.len()),
+    };
+    for (index, secnonce) in secnonces.into_iter().enumerate() {
+        let pubnonces: Vec<PubNonce> = nonces.iter().map(|nonce| nonce.items[index]).collect();
+        let own = nonces
+            .iter()
+            .find(|nonce| nonce.key == key)
+            .map(|nonce| nonce.items[index]);
+        if own != Some(secnonce.public(&secp)) {
+            return Err(format!("--nonce for input {} is not the one {} drew", index, path).into());
+        }
+        let aggnonce = AggNonce::new(&pubnonces);
+        let session = Session::new(&secp, &key_agg, &aggnonce, &sighashes[index]);
+        message
+            .items
+            .push(session.sign(&secp, secnonce, &keys[0].inner)?);
+    }
+    println!("--psig {}", message);
+    Ok(())
+}
+
+/// Checks every partial signature, sums them into one key-path signature
+/// per input and writes the finished transaction.
+fn combine(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let secp = Secp256k1::verification_only();
+    let key_agg = from_args(&secp, &args)?;
+    let own_script = key_agg.address(args.network()?).script_pubkey();
+    let (mut tx, prevouts) = unsigned_tx(&args, &own_script)?;
+    let inputs = tx.input.len();
+    let nonces: Vec<NonceMessage> = messages(&args, "nonce", &key_agg, inputs)?;
+    let psigs: Vec<SignatureMessage> = messages(&args, "psig", &key_agg, inputs)?;
+    let sighashes = key_path_sighashes(&tx, &prevouts)?;
+
+    for (index, sighash) in sighashes.iter().enumerate() {
+        let pubnonces: Vec<PubNonce> = nonces.iter().map(|nonce| nonce.items[index]).collect();
+        let aggnonce = AggNonce::new(&pubnonces);
+        let session = Session::new(&secp, &key_agg, &aggnonce, sighash);
+        for (nonce, psig) in nonces.iter().zip(&psigs) {
+            session.verify(&secp, &psig.items[index], &nonce.items[index], &psig.key)?;
+        }
+        let psigs: Vec<PartialSig> = psigs.iter().map(|psig| psig.items[index]).collect();
+        tx.input[index].witness = musig::key_path_witness(&session.aggregate(&psigs));
+    }
+    println!("{} partial signature(s) verified per input.", psigs.len());
+
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    fs::write("out.txt", serialize_hex(&tx))?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use super::{multisig_from_args, report, report_policy, sighash_types, signing_keys, unsigned_tx};
+use crate::builder;
+use crate::cli::Args;
+use crate::sigfile::{self, SignatureFile};
+use crate::verify::{self, Flags};
+use crate::USAGE;
+use bitcoin::{consensus::encode::serialize_hex, secp256k1::Secp256k1};
+use std::error::Error;
+use std::fs;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("sign") => sign(&args[1..]),
+        Some("combine") => combine(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Signs every input of the unsigned `--tx` with one cosigner's key and
+/// writes the signatures to `--out`, to be carried to the combiner.
+fn sign(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let secp = Secp256k1::new();
+    let network = args.network()?;
+    let keys = signing_keys(&secp, &args, network)?;
+    if keys.len() != 1 {
+        return Err("give exactly one signing key".into());
+    }
+    let (multisig, wrapping, _) = multisig_from_args(&secp, &args, Vec::new())?;
+    let own_script = wrapping.address(&multisig, network)?.script_pubkey();
+    let (tx, prevouts) = unsigned_tx(&args, &own_script)?;
+
+    let sighash_types = sighash_types(&args, tx.input.len())?;
+    for (index, &ty) in sighash_types.iter().enumerate() {
+        if builder::commits_to_no_outputs(ty, index, &tx) {
+            println!(
+                "warning: input {} signs with {}, which commits to no outputs",
+                index, ty
+            );
+        }
+    }
+    let file = sigfile::sign(
+        &secp,
+        &tx,
+        &prevouts,
+        wrapping,
+        &multisig,
+        &keys[0],
+        &sighash_types,
+    )?;
+    let out = args.required("out")?;
+    fs::write(out, format!("{}\n", file.to_json()))?;
+    println!(
+        "{} signature(s) by {} on {} written to {}",
+        file.signatures.len(),
+        file.key,
+        file.txid,
+        out
+    );
+    Ok(())
+}
+
+/// Verifies every `--signatures` file against the unsigned `--tx` and,
+/// once the threshold is met, assembles and writes the signed transaction.
+fn combine(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let secp = Secp256k1::verification_only();
+    let network = args.network()?;
+    let (multisig, wrapping, _) = multisig_from_args(&secp, &args, Vec::new())?;
+    let own_script = wrapping.address(&multisig, network)?.script_pubkey();
+    let (mut tx, prevouts) = unsigned_tx(&args, &own_script)?;
+    let files = args
+        .values("signatures")
+        .into_iter()
+        .map(|path| Ok(SignatureFile::from_json(&fs::read_to_string(path)?)?))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    let signers = sigfile::combine(&secp, &mut tx, &prevouts, wrapping, &multisig, &files)?;
+    println!(
+        "{} signature file(s) verified; signing {}-of-{} with:",
+        files.len(),
+        multisig.threshold(),
+        multisig.keys().len()
+    );
+    for key in signers {
+        println!("  {}", key);
+    }
+
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    fs::write("out.txt", serialize_hex(&tx))?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use super::{report_policy, tx_with_prevouts};
+use crate::cli::Args;
+use std::error::Error;
+
+/// Checks whether Bitcoin Core would relay a transaction.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let (tx, prevouts) = tx_with_prevouts(&args)?;
+    if !report_policy(&tx, &prevouts)? {
+        return Err("transaction is not standard".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use super::{report, report_policy, signing_keys};
+use crate::builder::Recipient;
+use crate::cli::Args;
+use crate::prevout::Prevout;
+use crate::psbt;
+use crate::verify::{self, Flags};
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{Sequence, TxIn, TxOut},
+    },
+    consensus::encode::serialize_hex,
+    secp256k1::Secp256k1,
+    util::psbt::{Input as PsbtInput, PartiallySignedTransaction as Psbt},
+};
+use std::error::Error;
+use std::fs;
+
+fn read_psbt(path: &str) -> Result<Psbt, Box<dyn Error>> {
+    Ok(fs::read_to_string(path)?.trim().parse()?)
+}
+
+/// Writes a PSBT as base64, the form Bitcoin Core and most wallets take.
+pub fn write_psbt(path: &str, psbt: &Psbt) -> Result<(), Box<dyn Error>> {
+    fs::write(path, format!("{}\n", psbt))?;
+    Ok(())
+}
+
+/// Adds one cosigner's partial signatures to a PSBT.
+pub fn sign(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let path = args.required("psbt")?;
+    let secp = Secp256k1::new();
+    let keys = signing_keys(&secp, &args, args.network()?)?;
+    if keys.is_empty() {
+        return Err("no signing keys; use --signing-key or --keystore".into());
+    }
+
+    let mut psbt = read_psbt(path)?;
+    for key in keys.iter() {
+        let signed = psbt::sign(&secp, &mut psbt, key)?;
+        println!("Signed {} input(s) with {}", signed, key.public_key(&secp));
+    }
+    let out = args.value("out").unwrap_or(path);
+    write_psbt(out, &psbt)?;
+    println!("Written to {}", out);
+    Ok(())
+}
+
+/// Adds another party's input to a PSBT, keeping ANYONECANPAY signatures.
+pub fn add_input(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let path = args.required("psbt")?;
+    let prevout: Prevout = args.required("prevout")?.parse()?;
+    let sequence = match args.value("sequence") {
+        Some(n) => Sequence(n.parse()?),
+        None => Sequence::MAX,
+    };
+    let script = |name| -> Result<Option<Script>, Box<dyn Error>> {
+        Ok(match args.value(name) {
+            Some(hex) => Some(Script::from(hex::decode(hex)?)),
+            None => None,
+        })
+    };
+    let input = PsbtInput {
+        witness_utxo: Some(prevout.to_txout()),
+        witness_script: script("witness-script")?,
+        redeem_script: script("redeem-script")?,
+        ..Default::default()
+    };
+    let txin = TxIn {
+        previous_output: prevout.outpoint,
+        sequence,
+        ..Default::default()
+    };
+
+    let mut psbt = read_psbt(path)?;
+    psbt::add_input(&mut psbt, txin, input)?;
+    let out = args.value("out").unwrap_or(path);
+    write_psbt(out, &psbt)?;
+    println!("Input {} added, written to {}", prevout.outpoint, out);
+    Ok(())
+}
+
+/// Appends an output to a PSBT whose signatures allow it.
+pub fn add_output(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let path = args.required("psbt")?;
+    let recipient = Recipient::parse(args.required("to")?, args.network()?)?;
+    let txout = TxOut {
+        value: recipient.amount.to_sat(),
+        script_pubkey: recipient.script_pubkey,
+    };
+
+    let mut psbt = read_psbt(path)?;
+    psbt::add_output(&mut psbt, txout, Default::default())?;
+    let out = args.value("out").unwrap_or(path);
+    write_psbt(out, &psbt)?;
+    println!("Output added, written to {}", out);
+    Ok(())
+}
+
+/// Merges the signatures from several copies of the same PSBT.
+pub fn combine(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let psbts = args
+        .values("psbt")
+        .into_iter()
+        .map(read_psbt)
+        .collect::<Result<Vec<_>, _>>()?;
+    let out = args.required("out")?;
+    write_psbt(out, &psbt::combine(psbts)?)?;
+    println!("Combined PSBT written to {}", out);
+    Ok(())
+}
+
+pub fn finalize(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let path = args.required("psbt")?;
+    let mut psbt = read_psbt(path)?;
+    psbt::finalize(&mut psbt)?;
+    let out = args.value("out").unwrap_or(path);
+    write_psbt(out, &psbt)?;
+    println!("Finalized PSBT written to {}", out);
+    Ok(())
+}
+
+/// Extracts, verifies and writes the signed transaction to out.txt.
+pub fn extract(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[])?;
+    let (tx, prevouts) = psbt::extract(read_psbt(args.required("psbt")?)?)?;
+    let secp = Secp256k1::verification_only();
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("extracted transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    let tx_hex = serialize_hex(&tx);
+    fs::write("out.txt", &tx_hex)?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
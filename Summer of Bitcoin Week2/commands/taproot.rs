@@ -0,0 +1,171 @@
+use super::{load_prevouts, outputs, report, report_policy, signing_keys};
+use crate::builder;
+use crate::cli::{self, Args};
+use crate::multisig::KeyOrdering;
+use crate::prevout;
+use crate::taproot::{self, TaprootMultisig, Tapscript};
+use crate::verify::{self, Flags};
+use crate::USAGE;
+use bitcoin::{
+    blockdata::locktime::PackedLockTime,
+    consensus::encode::serialize_hex,
+    secp256k1::{KeyPair, Secp256k1, Verification, XOnlyPublicKey},
+    util::sighash::{SchnorrSighashType, SighashCache},
+};
+use std::error::Error;
+use std::fs;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("address") => address(&args[1..]),
+        Some("spend") => spend(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// The script tree from a `--threshold` of `--key` leaf and any `--leaf`
+/// values, under `--internal-key` or the NUMS key.
+fn from_args<C: Verification>(
+    secp: &Secp256k1<C>,
+    args: &Args,
+) -> Result<TaprootMultisig, Box<dyn Error>> {
+    let mut leaves = Vec::new();
+    if args.has("key") {
+        let keys = args
+            .values("key")
+            .into_iter()
+            .map(taproot::parse_x_only)
+            .collect::<Result<Vec<_>, _>>()?;
+        let ordering = if args.has("sorted") {
+            KeyOrdering::SortedMulti
+        } else {
+            KeyOrdering::Multi
+        };
+        let threshold = args.required("threshold")?.parse()?;
+        leaves.push(Tapscript::new(&keys, threshold, ordering)?);
+    }
+    for leaf in args.values("leaf") {
+        leaves.push(leaf.parse()?);
+    }
+    if leaves.is_empty() {
+        return Err("no leaves; use --threshold with --key, or --leaf".into());
+    }
+    let internal_key = args
+        .value("internal-key")
+        .map(taproot::parse_x_only)
+        .transpose()?;
+    Ok(TaprootMultisig::new(secp, internal_key, leaves)?)
+}
+
+/// Prints the leaves, internal key and P2TR address of a taproot multisig.
+fn address(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let secp = Secp256k1::verification_only();
+    let taproot = from_args(&secp, &args)?;
+    let network = args.network()?;
+
+    print!("Internal key:   {}", taproot.internal_key());
+    if taproot.is_nums() {
+        print!(" (NUMS, no key path)");
+    }
+    println!();
+    for (index, leaf) in taproot.leaves().iter().enumerate() {
+        println!(
+            "Leaf {}:         {}-of-{}, {} WU witness",
+            index,
+            leaf.threshold(),
+            leaf.keys().len(),
+            taproot.witness_size(index, SchnorrSighashType::Default)?
+        );
+        println!("  script:       {}", hex::encode(leaf.script().as_bytes()));
+    }
+    println!("P2TR:           {}", taproot.address(network));
+    Ok(())
+}
+
+/// Spends taproot multisig outputs through one leaf, by default the
+/// cheapest the signing keys can satisfy. Change goes back to the same
+/// address unless `--change` is given.
+fn spend(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let secp = Secp256k1::new();
+    let taproot = from_args(&secp, &args)?;
+    let network = args.network()?;
+    let keys = signing_keys(&secp, &args, network)?;
+    let keypairs: Vec<KeyPair> = keys
+        .iter()
+        .map(|key| KeyPair::from_secret_key(&secp, &key.inner))
+        .collect();
+    let signers: Vec<XOnlyPublicKey> = keypairs
+        .iter()
+        .map(|keypair| keypair.x_only_public_key().0)
+        .collect();
+    let sighash_type = match args.value("sighash") {
+        Some(ty) => {
+            let ty = cli::parse_sighash(ty)?;
+            SchnorrSighashType::from_consensus_u8(ty.to_u32() as u8)?
+        }
+        None => SchnorrSighashType::Default,
+    };
+
+    let leaf = match args.value("leaf-index") {
+        Some(index) => index.parse()?,
+        None => (0..taproot.leaves().len())
+            .filter(|&index| {
+                let leaf = &taproot.leaves()[index];
+                let have = leaf.keys().iter().filter(|key| signers.contains(key));
+                have.count() >= leaf.threshold()
+            })
+            .min_by_key(|&index| {
+                taproot
+                    .witness_size(index, sighash_type)
+                    .unwrap_or(usize::MAX)
+            })
+            .ok_or("the signing keys satisfy no leaf")?,
+    };
+
+    let own_script = taproot.address(network).script_pubkey();
+    let prevouts = load_prevouts(&args, &own_script)?;
+    let (recipients, change, fee_rate) = outputs(&args, own_script)?;
+    let unsigned = builder::build(
+        &taproot.template(leaf, sighash_type)?,
+        PackedLockTime::ZERO,
+        &prevouts,
+        &recipients,
+        &change,
+        fee_rate,
+    )?;
+    let mut tx = unsigned.tx;
+    println!(
+        "Leaf {} spend of {} input(s), fee {}",
+        leaf,
+        tx.input.len(),
+        unsigned.fee
+    );
+
+    let spent = prevout::spent_outputs(&prevouts);
+    let mut cache = SighashCache::new(&tx);
+    let mut witnesses = Vec::with_capacity(prevouts.len());
+    for index in 0..prevouts.len() {
+        let signatures = taproot.leaves()[leaf].sign(
+            &secp,
+            &mut cache,
+            &spent,
+            index,
+            &keypairs,
+            sighash_type,
+        )?;
+        witnesses.push(taproot.witness(leaf, &signatures)?);
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    fs::write("out.txt", serialize_hex(&tx))?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
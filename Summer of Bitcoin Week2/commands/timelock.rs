@@ -0,0 +1,128 @@
+use super::{
+    load_prevouts, outputs, public_keys, report, report_policy, sighash_types, signing_keys,
+};
+use crate::builder;
+use crate::cli::Args;
+use crate::multisig::{KeyOrdering, Multisig};
+use crate::timelock::{self, Branch, RecoveryPolicy, Timelock};
+use crate::verify::{self, Flags};
+use crate::USAGE;
+use bitcoin::{consensus::encode::serialize_hex, secp256k1::Secp256k1};
+use std::error::Error;
+use std::fs;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("address") => address(&args[1..]),
+        Some("spend") => spend(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Reads the policy `timelock` subcommands share: `--threshold`/`--key`
+/// for the primary branch, `--recovery-threshold`/`--recovery-key` (the
+/// primary keys, 1 of them, by default) after `--csv` or `--cltv`.
+fn recovery_policy(args: &Args) -> Result<RecoveryPolicy, Box<dyn Error>> {
+    let ordering = if args.has("sorted") {
+        KeyOrdering::SortedMulti
+    } else {
+        KeyOrdering::Multi
+    };
+    let keys = public_keys(args, "key")?;
+    let primary = Multisig::new(&keys, args.required("threshold")?.parse()?, ordering)?;
+
+    let mut recovery_keys = public_keys(args, "recovery-key")?;
+    if recovery_keys.is_empty() {
+        recovery_keys = keys;
+    }
+    let recovery_threshold = args.value("recovery-threshold").unwrap_or("1").parse()?;
+    let recovery = Multisig::new(&recovery_keys, recovery_threshold, ordering)?;
+
+    let timelock = match (args.value("csv"), args.value("cltv")) {
+        (Some(blocks), None) => Timelock::Blocks(blocks.parse()?),
+        (None, Some(height)) => Timelock::Height(height.parse()?),
+        _ => return Err("exactly one of --csv <blocks> or --cltv <height> is required".into()),
+    };
+    Ok(RecoveryPolicy::new(primary, recovery, timelock)?)
+}
+
+/// Prints the witness script and addresses of a recovery policy.
+fn address(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let policy = recovery_policy(&args)?;
+    let network = args.network()?;
+
+    for branch in [Branch::Primary, Branch::Recovery] {
+        let signers = policy.signers(branch);
+        println!(
+            "{:?}: {}-of-{}{}",
+            branch,
+            signers.threshold(),
+            signers.keys().len(),
+            match branch {
+                Branch::Primary => String::new(),
+                Branch::Recovery => format!(" {}", policy.timelock()),
+            }
+        );
+    }
+    println!(
+        "Witness script: {}",
+        hex::encode(policy.witness_script().as_bytes())
+    );
+    println!("P2WSH:          {}", policy.p2wsh_address(network));
+    println!("P2SH-P2WSH:     {}", policy.p2sh_p2wsh_address(network));
+    Ok(())
+}
+
+/// Spends P2SH-P2WSH outputs of a recovery policy through either branch,
+/// signing with the `--signing-key` and `--keystore` keys.
+fn spend(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["sorted"])?;
+    let policy = recovery_policy(&args)?;
+    let branch = match args.value("branch").unwrap_or("primary") {
+        "primary" => Branch::Primary,
+        "recovery" => Branch::Recovery,
+        other => return Err(format!("unknown branch {}", other).into()),
+    };
+    let network = args.network()?;
+    let secp = Secp256k1::new();
+    let keys = signing_keys(&secp, &args, network)?;
+
+    let own_script = policy.p2sh_p2wsh_address(network).script_pubkey();
+    let prevouts = load_prevouts(&args, &own_script)?;
+    let (recipients, change, fee_rate) = outputs(&args, own_script)?;
+    let template = policy.template(branch);
+    let unsigned = builder::build(
+        &template,
+        policy.lock_time(branch),
+        &prevouts,
+        &recipients,
+        &change,
+        fee_rate,
+    )?;
+    let mut tx = unsigned.tx;
+    println!(
+        "Spending {} input(s) through the {:?} branch, fee {}",
+        tx.input.len(),
+        branch,
+        unsigned.fee
+    );
+
+    let sighash_types = sighash_types(&args, tx.input.len())?;
+    timelock::sign(
+        &secp,
+        &mut tx,
+        &prevouts,
+        &policy,
+        branch,
+        &keys,
+        &sighash_types,
+    )?;
+    if !report(&verify::verify(&secp, &tx, &prevouts, Flags::Standard)) {
+        return Err("signed transaction failed verification".into());
+    }
+    report_policy(&tx, &prevouts)?;
+    fs::write("out.txt", serialize_hex(&tx))?;
+    println!("Transaction hex written to out.txt");
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use super::{report, tx_with_prevouts};
+use crate::cli::Args;
+use crate::verify::{self, Flags};
+use bitcoin::secp256k1::Secp256k1;
+use std::error::Error;
+
+/// Verifies a signed transaction against the outputs it spends.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["consensus"])?;
+    let (tx, prevouts) = tx_with_prevouts(&args)?;
+    let flags = if args.has("consensus") {
+        Flags::Consensus
+    } else {
+        Flags::Standard
+    };
+
+    let secp = Secp256k1::verification_only();
+    if !report(&verify::verify(&secp, &tx, &prevouts, flags)) {
+        return Err("transaction failed verification".into());
+    }
+    Ok(())
+}
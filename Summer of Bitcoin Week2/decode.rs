@@ -0,0 +1,313 @@
+use crate::multisig::Multisig;
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{Transaction, TxIn},
+    },
+    network::constants::Network,
+    util::{amount::Amount, schnorr::SchnorrSig},
+    Address, EcdsaSig, PublicKey,
+};
+use std::fmt;
+
+/// Tapscript leaf version with the parity bit cleared, as the first byte of
+/// a control block holds it.
+const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// First byte of a taproot annex.
+const TAPROOT_ANNEX_PREFIX: u8 = 0x50;
+
+/// A human-readable dump of a transaction, down to each witness item.
+pub struct Pretty<'a> {
+    pub tx: &'a Transaction,
+    /// Used to show output addresses.
+    pub network: Network,
+}
+
+impl fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tx = self.tx;
+        writeln!(f, "txid:     {}", tx.txid())?;
+        writeln!(f, "wtxid:    {}", tx.wtxid())?;
+        writeln!(f, "version:  {}", tx.version)?;
+        writeln!(f, "locktime: {}", tx.lock_time)?;
+        writeln!(
+            f,
+            "size:     {} bytes, {} stripped",
+            tx.size(),
+            tx.strippedsize()
+        )?;
+        writeln!(f, "weight:   {} WU, vsize {} vB", tx.weight(), tx.vsize())?;
+
+        writeln!(f, "inputs:   {}", tx.input.len())?;
+        for (index, input) in tx.input.iter().enumerate() {
+            writeln!(f, "  [{}] {}", index, input.previous_output)?;
+            writeln!(f, "      sequence:  {:#010x}", input.sequence.0)?;
+            writeln!(f, "      scriptSig: {}", asm_or_empty(&input.script_sig))?;
+            if input.witness.is_empty() {
+                continue;
+            }
+            writeln!(f, "      witness:   {} item(s)", input.witness.len())?;
+            for (i, item) in classify_witness(input).iter().enumerate() {
+                writeln!(f, "        {}: {}", i, item)?;
+            }
+        }
+
+        let total: Amount = tx
+            .output
+            .iter()
+            .map(|out| Amount::from_sat(out.value))
+            .sum();
+        writeln!(f, "outputs:  {}, {} total", tx.output.len(), total)?;
+        for (index, output) in tx.output.iter().enumerate() {
+            let script = &output.script_pubkey;
+            write!(
+                f,
+                "  [{}] {} {}",
+                index,
+                Amount::from_sat(output.value),
+                script_type(script)
+            )?;
+            if let Ok(address) = Address::from_script(script, self.network) {
+                write!(f, " {}", address)?;
+            }
+            writeln!(f)?;
+            writeln!(f, "      scriptPubKey: {}", asm_or_empty(script))?;
+        }
+        Ok(())
+    }
+}
+
+fn asm_or_empty(script: &Script) -> String {
+    if script.is_empty() {
+        "(empty)".to_owned()
+    } else {
+        script.asm()
+    }
+}
+
+/// The standard template `script` follows, as Bitcoin Core names them.
+pub fn script_type(script: &Script) -> &'static str {
+    if script.is_p2pkh() {
+        "pubkeyhash"
+    } else if script.is_p2sh() {
+        "scripthash"
+    } else if script.is_v0_p2wpkh() {
+        "witness_v0_keyhash"
+    } else if script.is_v0_p2wsh() {
+        "witness_v0_scripthash"
+    } else if script.is_v1_p2tr() {
+        "witness_v1_taproot"
+    } else if script.is_witness_program() {
+        "witness_unknown"
+    } else if script.is_op_return() {
+        "nulldata"
+    } else if script.is_p2pk() {
+        "pubkey"
+    } else if Multisig::from_script(script).is_ok() {
+        "multisig"
+    } else {
+        "nonstandard"
+    }
+}
+
+/// Describes each witness item of `input`: signatures with their sighash
+/// type, public keys, and the script a script-path spend reveals.
+fn classify_witness(input: &TxIn) -> Vec<String> {
+    let items: Vec<&[u8]> = input.witness.iter().collect();
+    let mut script_index = None;
+    let mut control_index = None;
+    let mut annex_index = None;
+
+    // Without the prevout the spend type is a guess: a control block marks a
+    // taproot script path, and otherwise a last item that is not the key of
+    // a P2WPKH spend is the witness script of a P2WSH one.
+    let mut end = items.len();
+    if end >= 2 && items[end - 1].first() == Some(&TAPROOT_ANNEX_PREFIX) {
+        annex_index = Some(end - 1);
+        end -= 1;
+    }
+    if end >= 2 {
+        let last = items[end - 1];
+        if is_control_block(last) {
+            control_index = Some(end - 1);
+            script_index = Some(end - 2);
+        } else if PublicKey::from_slice(last).is_err() {
+            script_index = Some(end - 1);
+        }
+    }
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            if Some(i) == annex_index {
+                format!("annex {}", hex::encode(item))
+            } else if Some(i) == control_index {
+                format!(
+                    "control block (leaf version {:#04x}, {} path node(s)) {}",
+                    item[0] & 0xfe,
+                    (item.len() - 33) / 32,
+                    hex::encode(item)
+                )
+            } else if Some(i) == script_index {
+                format!("script: {}", Script::from(item.to_vec()).asm())
+            } else {
+                classify_item(item)
+            }
+        })
+        .collect()
+}
+
+fn classify_item(item: &[u8]) -> String {
+    if item.is_empty() {
+        return "(empty)".to_owned();
+    }
+    if let Ok(sig) = EcdsaSig::from_slice(item) {
+        return format!("signature {} {}", sig.hash_ty, hex::encode(item));
+    }
+    if item.len() == 33 || item.len() == 65 {
+        if let Ok(key) = PublicKey::from_slice(item) {
+            return format!("pubkey {}", key);
+        }
+    }
+    if item.len() == 64 || item.len() == 65 {
+        if let Ok(sig) = SchnorrSig::from_slice(item) {
+            return format!("schnorr signature {} {}", sig.hash_ty, hex::encode(item));
+        }
+    }
+    if item == [1] {
+        return "01 (true)".to_owned();
+    }
+    format!("{} bytes {}", item.len(), hex::encode(item))
+}
+
+fn is_control_block(item: &[u8]) -> bool {
+    item.len() >= 33
+        && (item.len() - 33).is_multiple_of(32)
+        && item.len() <= 33 + 128 * 32
+        && item[0] & 0xfe == TAPROOT_LEAF_TAPSCRIPT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        blockdata::{opcodes, script::Builder, witness::Witness},
+        consensus::encode,
+        hashes::Hash,
+        PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash,
+    };
+
+    const KEY: &str = "032ff8c5df0bc00fe1ac2319c3b8070d6d1e04cfbf4fedda499ae7b775185ad53b";
+
+    fn input(items: Vec<Vec<u8>>) -> TxIn {
+        TxIn {
+            witness: Witness::from_vec(items),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pretty_prints_golden_p2sh_p2wsh() {
+        let bytes = hex::decode(include_str!("test_data/out_p2sh_p2wsh.txt").trim()).unwrap();
+        let tx: Transaction = encode::deserialize(&bytes).unwrap();
+        let text = Pretty {
+            tx: &tx,
+            network: Network::Bitcoin,
+        }
+        .to_string();
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+
+        assert!(lines.contains(&"weight:   692 WU, vsize 173 vB"));
+        assert!(lines.contains(&"witness:   4 item(s)"));
+        assert!(lines.contains(&"0: (empty)"));
+        assert!(lines[12].starts_with("1: signature SIGHASH_ALL 3044"));
+        assert!(lines[13].starts_with("2: signature SIGHASH_ALL 3044"));
+        assert!(lines[14].starts_with("3: script: OP_PUSHNUM_2 OP_PUSHBYTES_33 032ff8c5"));
+        assert!(lines[14].ends_with("OP_PUSHNUM_2 OP_CHECKMULTISIG"));
+        assert!(lines.contains(&"[0] 0.001 BTC scripthash 325UUecEQuyrTd28Xs2hvAxdAjHM7XzqVF"));
+    }
+
+    #[test]
+    fn classifies_p2wpkh_witness() {
+        let bytes = hex::decode(include_str!("test_data/out_p2sh_p2wsh.txt").trim()).unwrap();
+        let tx: Transaction = encode::deserialize(&bytes).unwrap();
+        let signature = tx.input[0].witness.to_vec()[1].clone();
+        let items = classify_witness(&input(vec![signature, hex::decode(KEY).unwrap()]));
+        assert!(items[0].starts_with("signature SIGHASH_ALL "));
+        assert_eq!(items[1], format!("pubkey {}", KEY));
+    }
+
+    #[test]
+    fn names_script_types() {
+        let key = PublicKey::from_slice(&hex::decode(KEY).unwrap()).unwrap();
+        let multisig = Builder::new()
+            .push_int(1)
+            .push_key(&key)
+            .push_int(1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let p2tr = Builder::new()
+            .push_int(1)
+            .push_slice(&[2; 32])
+            .into_script();
+        let unknown = Builder::new()
+            .push_int(2)
+            .push_slice(&[2; 32])
+            .into_script();
+        for (script, name) in [
+            (Script::new_p2pkh(&PubkeyHash::all_zeros()), "pubkeyhash"),
+            (Script::new_p2sh(&ScriptHash::all_zeros()), "scripthash"),
+            (
+                Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+                "witness_v0_keyhash",
+            ),
+            (
+                Script::new_v0_p2wsh(&WScriptHash::all_zeros()),
+                "witness_v0_scripthash",
+            ),
+            (p2tr, "witness_v1_taproot"),
+            (unknown, "witness_unknown"),
+            (Script::new_op_return(b"hi"), "nulldata"),
+            (Script::new_p2pk(&key), "pubkey"),
+            (multisig, "multisig"),
+            (Script::from(vec![0x51]), "nonstandard"),
+        ] {
+            assert_eq!(script_type(&script), name, "{}", script.asm());
+        }
+    }
+
+    #[test]
+    fn detects_taproot_control_blocks_and_annexes() {
+        let signature = vec![1; 64];
+        let script = vec![0x51];
+        let mut control_block = vec![TAPROOT_LEAF_TAPSCRIPT | 1];
+        control_block.extend([2; 32 + 2 * 32]);
+        let annex = vec![TAPROOT_ANNEX_PREFIX, 9];
+
+        let items = classify_witness(&input(vec![
+            signature.clone(),
+            script.clone(),
+            control_block.clone(),
+        ]));
+        assert_eq!(items[1], "script: OP_PUSHNUM_1");
+        assert!(items[2].starts_with("control block (leaf version 0xc0, 2 path node(s)) c1"));
+
+        let items = classify_witness(&input(vec![
+            signature.clone(),
+            script,
+            control_block,
+            annex.clone(),
+        ]));
+        assert_eq!(items[1], "script: OP_PUSHNUM_1");
+        assert!(items[2].starts_with("control block"));
+        assert_eq!(items[3], "annex 5009");
+
+        // A key-path spend, with and without an annex
+        let items = classify_witness(&input(vec![signature.clone()]));
+        assert!(items[0].starts_with("schnorr signature SIGHASH_DEFAULT"));
+        let items = classify_witness(&input(vec![signature, annex]));
+        assert!(items[0].starts_with("schnorr signature"));
+        assert_eq!(items[1], "annex 5009");
+    }
+}
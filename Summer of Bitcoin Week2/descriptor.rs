@@ -0,0 +1,525 @@
+use crate::account::{Account, ScriptType};
+use crate::multisig::{KeyOrdering, Multisig, MultisigError};
+use crate::psbt::{self, KeyOrigin};
+use bitcoin::{
+    network::constants::Network,
+    secp256k1::{Secp256k1, Verification},
+    util::bip32::{self, ChildNumber, DerivationPath, ExtendedPubKey, KeySource},
+    PublicKey,
+};
+use std::fmt;
+use std::str::FromStr;
+
+/// Characters a descriptor may use, in the order BIP380 numbers them.
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub enum DescriptorError {
+    /// Not `sh(wsh(multi|sortedmulti(...)))` or `wsh(...)` over keys.
+    Syntax(String),
+    /// A key that is neither a compressed hex key nor an xpub path.
+    Key(String),
+    InvalidCharacter(char),
+    Checksum {
+        expected: String,
+        found: String,
+    },
+    /// A `*` descriptor needs the index of the address to derive.
+    NeedsIndex,
+    /// Steps after an xpub must be unhardened.
+    HardenedStep(String),
+    /// An xpub encoded for another network.
+    WrongNetwork {
+        expected: Network,
+        found: Network,
+    },
+    Bip32(bip32::Error),
+    Multisig(MultisigError),
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DescriptorError::Syntax(s) => write!(
+                f,
+                "unsupported descriptor `{}`, expected sh(wsh(multi(...))) or wsh(sortedmulti(...))",
+                s
+            ),
+            DescriptorError::Key(s) => write!(f, "invalid descriptor key `{}`", s),
+            DescriptorError::InvalidCharacter(c) => {
+                write!(f, "invalid character {:?} in descriptor", c)
+            }
+            DescriptorError::Checksum { expected, found } => write!(
+                f,
+                "descriptor checksum is {}, expected {}",
+                found, expected
+            ),
+            DescriptorError::NeedsIndex => {
+                write!(f, "ranged descriptor needs an index to derive")
+            }
+            DescriptorError::HardenedStep(s) => {
+                write!(f, "hardened step after xpub in `{}`", s)
+            }
+            DescriptorError::WrongNetwork { expected, found } => {
+                write!(f, "descriptor xpub is for {}, expected {}", found, expected)
+            }
+            DescriptorError::Bip32(e) => write!(f, "derivation: {}", e),
+            DescriptorError::Multisig(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DescriptorError {}
+
+impl From<bip32::Error> for DescriptorError {
+    fn from(e: bip32::Error) -> Self {
+        DescriptorError::Bip32(e)
+    }
+}
+
+impl From<MultisigError> for DescriptorError {
+    fn from(e: MultisigError) -> Self {
+        DescriptorError::Multisig(e)
+    }
+}
+
+/// The BIP380 checksum of a descriptor without its `#` suffix.
+pub fn checksum(descriptor: &str) -> Result<String, DescriptorError> {
+    fn polymod(c: u64, value: u64) -> u64 {
+        let top = c >> 35;
+        let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+        for (bit, generator) in [
+            0xf5_dee5_1989,
+            0xa9_fdca_3312,
+            0x1b_ab10_e32d,
+            0x37_06b1_677a,
+            0x64_4d62_6ffd,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if top >> bit & 1 == 1 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET
+            .find(ch)
+            .ok_or(DescriptorError::InvalidCharacter(ch))? as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..CHECKSUM_SIZE {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..CHECKSUM_SIZE)
+        .map(|i| CHECKSUM_CHARSET[(c >> (5 * (7 - i)) & 31) as usize] as char)
+        .collect())
+}
+
+/// One key of a multisig descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorKey {
+    Single {
+        origin: Option<KeySource>,
+        key: PublicKey,
+    },
+    /// An xpub followed by unhardened steps, ending in `*` when ranged.
+    Extended {
+        origin: Option<KeySource>,
+        xpub: ExtendedPubKey,
+        path: DerivationPath,
+        wildcard: bool,
+    },
+}
+
+impl DescriptorKey {
+    /// The `/<change>/*` keys of a BIP48 account.
+    pub fn from_account(account: &Account, change: bool) -> Self {
+        DescriptorKey::Extended {
+            origin: Some(account.source.clone()),
+            xpub: account.xpub,
+            path: DerivationPath::from(vec![ChildNumber::Normal {
+                index: u32::from(change),
+            }]),
+            wildcard: true,
+        }
+    }
+
+    fn is_ranged(&self) -> bool {
+        matches!(self, DescriptorKey::Extended { wildcard: true, .. })
+    }
+
+    /// The public key at `index`, with its full origin if one is known.
+    fn at<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<(PublicKey, Option<KeySource>), DescriptorError> {
+        match self {
+            DescriptorKey::Single { origin, key } => Ok((*key, origin.clone())),
+            DescriptorKey::Extended {
+                origin,
+                xpub,
+                path,
+                wildcard,
+            } => {
+                let mut path = path.clone();
+                if *wildcard {
+                    path = path.child(ChildNumber::from_normal_idx(index)?);
+                }
+                let key = PublicKey::new(xpub.derive_pub(secp, &path)?.public_key);
+                let origin = origin
+                    .as_ref()
+                    .map(|(fingerprint, prefix)| (*fingerprint, prefix.extend(&path)));
+                Ok((key, origin))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let origin = match self {
+            DescriptorKey::Single { origin, .. } | DescriptorKey::Extended { origin, .. } => origin,
+        };
+        if let Some(source) = origin {
+            psbt::fmt_origin(f, source)?;
+        }
+        match self {
+            DescriptorKey::Single { key, .. } => write!(f, "{}", key),
+            DescriptorKey::Extended {
+                xpub,
+                path,
+                wildcard,
+                ..
+            } => {
+                write!(f, "{}", xpub)?;
+                for step in path.as_ref() {
+                    write!(f, "/{}", step)?;
+                }
+                if *wildcard {
+                    write!(f, "/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for DescriptorKey {
+    type Err = DescriptorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DescriptorError::Key(s.to_owned());
+        let (origin, key) = if s.starts_with('[') {
+            let (source, key) = psbt::split_origin(s).ok_or_else(invalid)?;
+            (Some(source), key)
+        } else {
+            (None, s)
+        };
+        if !key.starts_with("xpub") && !key.starts_with("tpub") {
+            return Ok(DescriptorKey::Single {
+                origin,
+                key: PublicKey::from_str(key).map_err(|_| invalid())?,
+            });
+        }
+
+        let mut parts = key.split('/');
+        let xpub =
+            ExtendedPubKey::from_str(parts.next().unwrap_or_default()).map_err(|_| invalid())?;
+        let mut steps = Vec::new();
+        let mut wildcard = false;
+        for part in parts {
+            if wildcard {
+                return Err(invalid());
+            }
+            if part == "*" {
+                wildcard = true;
+                continue;
+            }
+            if part == "*'" || part == "*h" {
+                return Err(DescriptorError::HardenedStep(s.to_owned()));
+            }
+            let step = ChildNumber::from_str(part).map_err(|_| invalid())?;
+            if step.is_hardened() {
+                return Err(DescriptorError::HardenedStep(s.to_owned()));
+            }
+            steps.push(step);
+        }
+        Ok(DescriptorKey::Extended {
+            origin,
+            xpub,
+            path: DerivationPath::from(steps),
+            wildcard,
+        })
+    }
+}
+
+/// A `sh(wsh(...))` or `wsh(...)` multisig descriptor, written with its
+/// checksum so Bitcoin Core and other wallets can import it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    pub script_type: ScriptType,
+    pub threshold: usize,
+    pub ordering: KeyOrdering,
+    pub keys: Vec<DescriptorKey>,
+}
+
+impl Descriptor {
+    pub fn is_ranged(&self) -> bool {
+        self.keys.iter().any(DescriptorKey::is_ranged)
+    }
+
+    /// The multisig the descriptor describes on `network`, at `index` if it
+    /// is ranged, with the origins of the keys that have one.
+    pub fn at<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: Option<u32>,
+        network: Network,
+    ) -> Result<(Multisig, Vec<KeyOrigin>), DescriptorError> {
+        for key in &self.keys {
+            if let DescriptorKey::Extended { xpub, .. } = key {
+                // xpubs only tell mainnet from the test networks
+                if (xpub.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+                    return Err(DescriptorError::WrongNetwork {
+                        expected: network,
+                        found: xpub.network,
+                    });
+                }
+            }
+        }
+        let index = match index {
+            Some(index) => index,
+            None if self.is_ranged() => return Err(DescriptorError::NeedsIndex),
+            None => 0,
+        };
+        let mut keys = Vec::with_capacity(self.keys.len());
+        let mut origins = Vec::new();
+        for descriptor_key in &self.keys {
+            let (key, source) = descriptor_key.at(secp, index)?;
+            keys.push(key);
+            if let Some(source) = source {
+                origins.push(KeyOrigin { key, source });
+            }
+        }
+        let multisig = Multisig::new(&keys, self.threshold, self.ordering)?;
+        Ok((multisig, origins))
+    }
+
+    fn body(&self) -> String {
+        let function = match self.ordering {
+            KeyOrdering::Multi => "multi",
+            KeyOrdering::SortedMulti => "sortedmulti",
+        };
+        let mut body = format!("{}({}", function, self.threshold);
+        for key in &self.keys {
+            body.push_str(&format!(",{}", key));
+        }
+        body.push(')');
+        match self.script_type {
+            ScriptType::P2shP2wsh => format!("sh(wsh({}))", body),
+            ScriptType::P2wsh => format!("wsh({})", body),
+        }
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body = self.body();
+        let checksum = checksum(&body).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", body, checksum)
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = DescriptorError;
+
+    /// Parses a descriptor, checking its checksum if it has one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let syntax = || DescriptorError::Syntax(s.to_owned());
+        let body = match s.split_once('#') {
+            Some((body, found)) => {
+                let expected = checksum(body)?;
+                if found != expected {
+                    return Err(DescriptorError::Checksum {
+                        expected,
+                        found: found.to_owned(),
+                    });
+                }
+                body
+            }
+            None => s,
+        };
+
+        let (script_type, inner) = if let Some(rest) = body.strip_prefix("sh(wsh(") {
+            (ScriptType::P2shP2wsh, rest.strip_suffix("))"))
+        } else if let Some(rest) = body.strip_prefix("wsh(") {
+            (ScriptType::P2wsh, rest.strip_suffix(')'))
+        } else {
+            return Err(syntax());
+        };
+        let inner = inner.ok_or_else(syntax)?;
+        let (ordering, args) = if let Some(args) = inner.strip_prefix("sortedmulti(") {
+            (KeyOrdering::SortedMulti, args)
+        } else if let Some(args) = inner.strip_prefix("multi(") {
+            (KeyOrdering::Multi, args)
+        } else {
+            return Err(syntax());
+        };
+        let args = args.strip_suffix(')').ok_or_else(syntax)?;
+
+        let mut args = args.split(',');
+        let threshold = args
+            .next()
+            .and_then(|k| k.parse().ok())
+            .ok_or_else(syntax)?;
+        let keys = args.map(str::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Descriptor {
+            script_type,
+            threshold,
+            ordering,
+            keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::util::bip32::{ExtendedPrivKey, Fingerprint};
+
+    fn xpub(seed: u8) -> ExtendedPubKey {
+        let secp = Secp256k1::new();
+        let xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &[seed; 32]).unwrap();
+        ExtendedPubKey::from_priv(&secp, &xprv)
+    }
+
+    fn source(fingerprint: &str, path: &str) -> KeySource {
+        (
+            Fingerprint::from_str(fingerprint).unwrap(),
+            DerivationPath::from_str(path).unwrap(),
+        )
+    }
+
+    #[test]
+    fn bip380_checksums() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        // A payload error changes the checksum
+        assert_ne!(checksum("raw(Deadbeef)").unwrap(), "89f8spxm");
+        assert!(matches!(
+            checksum("raw(Ü)"),
+            Err(DescriptorError::InvalidCharacter('Ü'))
+        ));
+    }
+
+    #[test]
+    fn display_from_str_round_trip() {
+        let descriptor = Descriptor {
+            script_type: ScriptType::P2shP2wsh,
+            threshold: 2,
+            ordering: KeyOrdering::SortedMulti,
+            keys: vec![
+                DescriptorKey::Extended {
+                    origin: Some(source("d34db33f", "m/48'/0'/0'/1'")),
+                    xpub: xpub(1),
+                    path: DerivationPath::from_str("m/0").unwrap(),
+                    wildcard: true,
+                },
+                DescriptorKey::Extended {
+                    origin: None,
+                    xpub: xpub(2),
+                    path: DerivationPath::from_str("m/1/2").unwrap(),
+                    wildcard: false,
+                },
+                DescriptorKey::Single {
+                    origin: Some(source("0badf00d", "m/0'/5")),
+                    key: xpub(3).to_pub(),
+                },
+            ],
+        };
+        let text = descriptor.to_string();
+        assert!(text.starts_with("sh(wsh(sortedmulti(2,[d34db33f/48'/0'/0'/1']xpub"));
+        assert!(text.contains("/0/*,xpub"));
+        let (body, sum) = text.split_once('#').unwrap();
+        assert_eq!(sum, checksum(body).unwrap());
+        assert_eq!(text.parse::<Descriptor>().unwrap(), descriptor);
+        // The checksum is optional on input
+        assert_eq!(body.parse::<Descriptor>().unwrap(), descriptor);
+
+        let secp = Secp256k1::verification_only();
+        assert!(matches!(
+            descriptor.at(&secp, None, Network::Bitcoin),
+            Err(DescriptorError::NeedsIndex)
+        ));
+        assert!(matches!(
+            descriptor.at(&secp, Some(0), Network::Testnet),
+            Err(DescriptorError::WrongNetwork { .. })
+        ));
+        let (_, origins) = descriptor.at(&secp, Some(7), Network::Bitcoin).unwrap();
+        assert_eq!(origins.len(), 2);
+        assert_eq!(origins[0].source, source("d34db33f", "m/48'/0'/0'/1'/0/7"));
+        assert_eq!(origins[1].source, source("0badf00d", "m/0'/5"));
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let key = xpub(1).to_pub();
+        let body = format!("wsh(multi(1,{}))", key);
+        let sum = checksum(&body).unwrap();
+        let mut wrong = sum.clone().into_bytes();
+        wrong[0] = if wrong[0] == b'q' { b'p' } else { b'q' };
+        let wrong = String::from_utf8(wrong).unwrap();
+        for found in [wrong.as_str(), "", &sum[1..]] {
+            match format!("{}#{}", body, found).parse::<Descriptor>() {
+                Err(DescriptorError::Checksum { expected, found: f }) => {
+                    assert_eq!(expected, sum);
+                    assert_eq!(f, found);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_hardened_steps_after_xpub() {
+        for path in ["/0'/*", "/0h", "/0/*'", "/*h"] {
+            let key = format!("{}{}", xpub(1), path);
+            assert!(
+                matches!(
+                    key.parse::<DescriptorKey>(),
+                    Err(DescriptorError::HardenedStep(s)) if s == key
+                ),
+                "{}",
+                path
+            );
+        }
+        // Nothing may follow the wildcard
+        let key = format!("{}/*/0", xpub(1));
+        assert!(matches!(
+            key.parse::<DescriptorKey>(),
+            Err(DescriptorError::Key(_))
+        ));
+    }
+}
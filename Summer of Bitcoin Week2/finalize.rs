@@ -0,0 +1,250 @@
+use crate::multisig::{Multisig, MultisigError};
+use bitcoin::{
+    blockdata::{
+        opcodes,
+        script::{Builder, Script},
+        witness::Witness,
+    },
+    secp256k1::{Message, Secp256k1},
+    util::sighash::EcdsaSighashType,
+    EcdsaSig, PublicKey,
+};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalizeError {
+    Script(MultisigError),
+    /// The signature at this position verifies against none of the script keys.
+    UnknownSignature(usize),
+    /// Two signatures verify against the same key.
+    DuplicateSignature(PublicKey),
+    MissingSignatures {
+        have: usize,
+        need: usize,
+    },
+    ExtraSignatures {
+        have: usize,
+        need: usize,
+    },
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FinalizeError::Script(e) => write!(f, "witness script: {}", e),
+            FinalizeError::UnknownSignature(i) => {
+                write!(f, "signature {} does not match any key in the script", i)
+            }
+            FinalizeError::DuplicateSignature(key) => {
+                write!(f, "more than one signature for key {}", key)
+            }
+            FinalizeError::MissingSignatures { have, need } => {
+                write!(f, "{} signatures given, {} required", have, need)
+            }
+            FinalizeError::ExtraSignatures { have, need } => {
+                write!(
+                    f,
+                    "{} signatures given, script takes exactly {}",
+                    have, need
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FinalizeError {}
+
+impl From<MultisigError> for FinalizeError {
+    fn from(e: MultisigError) -> Self {
+        FinalizeError::Script(e)
+    }
+}
+
+/// Builds the `OP_CHECKMULTISIG` witness `<> <sig>... <witness script>`.
+///
+/// Signatures may come in any order: each is matched to its key by
+/// verifying it against the sighash for its own sighash type, which
+/// `sighash` computes, and they are emitted in the order of the keys in the
+/// script as `OP_CHECKMULTISIG` requires.
+pub fn multisig_witness<F>(
+    witness_script: &Script,
+    signatures: &[EcdsaSig],
+    sighash: F,
+) -> Result<Witness, FinalizeError>
+where
+    F: FnMut(EcdsaSighashType) -> Message,
+{
+    let multisig = Multisig::from_script(witness_script)?;
+    let ordered = order_signatures(&multisig, signatures, sighash)?;
+
+    let mut witness = Witness::new();
+    // OP_CHECKMULTISIG pops one element more than it uses.
+    witness.push([]);
+    for signature in ordered {
+        witness.push(signature.to_vec());
+    }
+    witness.push(witness_script.as_bytes());
+    Ok(witness)
+}
+
+/// Builds the legacy P2SH scriptSig `OP_0 <sig>... <redeem script>`,
+/// ordering the signatures as `multisig_witness` does.
+pub fn multisig_script_sig<F>(
+    redeem_script: &Script,
+    signatures: &[EcdsaSig],
+    sighash: F,
+) -> Result<Script, FinalizeError>
+where
+    F: FnMut(EcdsaSighashType) -> Message,
+{
+    let multisig = Multisig::from_script(redeem_script)?;
+    let ordered = order_signatures(&multisig, signatures, sighash)?;
+
+    let mut builder = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0);
+    for signature in ordered {
+        builder = builder.push_slice(&signature.to_vec());
+    }
+    Ok(builder.push_slice(redeem_script.as_bytes()).into_script())
+}
+
+/// Puts exactly `threshold` signatures in the order of the keys of
+/// `multisig`, matching them as `multisig_witness` does.
+pub fn order_signatures<F>(
+    multisig: &Multisig,
+    signatures: &[EcdsaSig],
+    mut sighash: F,
+) -> Result<Vec<EcdsaSig>, FinalizeError>
+where
+    F: FnMut(EcdsaSighashType) -> Message,
+{
+    let secp = Secp256k1::verification_only();
+
+    let mut by_key: Vec<Option<&EcdsaSig>> = vec![None; multisig.keys().len()];
+    for (i, signature) in signatures.iter().enumerate() {
+        let message = sighash(signature.hash_ty);
+        let position = multisig
+            .keys()
+            .iter()
+            .position(|key| {
+                secp.verify_ecdsa(&message, &signature.sig, &key.inner)
+                    .is_ok()
+            })
+            .ok_or(FinalizeError::UnknownSignature(i))?;
+        if by_key[position].is_some() {
+            return Err(FinalizeError::DuplicateSignature(multisig.keys()[position]));
+        }
+        by_key[position] = Some(signature);
+    }
+
+    let need = multisig.threshold();
+    if signatures.len() < need {
+        return Err(FinalizeError::MissingSignatures {
+            have: signatures.len(),
+            need,
+        });
+    }
+    if signatures.len() > need {
+        return Err(FinalizeError::ExtraSignatures {
+            have: signatures.len(),
+            need,
+        });
+    }
+    Ok(by_key.into_iter().flatten().copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multisig::KeyOrdering;
+    use bitcoin::{
+        hashes::{sha256, Hash},
+        secp256k1::SecretKey,
+    };
+
+    fn secret(i: u8) -> SecretKey {
+        SecretKey::from_slice(&[i; 32]).unwrap()
+    }
+
+    /// A 2-of-3 over keys 1-3.
+    fn multisig() -> Multisig {
+        let secp = Secp256k1::signing_only();
+        let keys: Vec<_> = (1..=3)
+            .map(|i| PublicKey::new(secret(i).public_key(&secp)))
+            .collect();
+        Multisig::new(&keys, 2, KeyOrdering::Multi).unwrap()
+    }
+
+    /// Stands in for the sighash, a different message per type.
+    fn sighash(ty: EcdsaSighashType) -> Message {
+        let digest = sha256::Hash::hash(&[ty as u8]);
+        Message::from_slice(&digest[..]).unwrap()
+    }
+
+    fn sign(i: u8, ty: EcdsaSighashType) -> EcdsaSig {
+        let secp = Secp256k1::signing_only();
+        let sig = secp.sign_ecdsa(&sighash(ty), &secret(i));
+        EcdsaSig { sig, hash_ty: ty }
+    }
+
+    #[test]
+    fn orders_signatures_by_key() {
+        let multisig = multisig();
+        let third = sign(3, EcdsaSighashType::All);
+        let first = sign(1, EcdsaSighashType::Single);
+        let witness = multisig_witness(multisig.witness_script(), &[third, first], sighash)
+            .unwrap()
+            .to_vec();
+        assert_eq!(
+            witness,
+            [
+                Vec::new(),
+                first.to_vec(),
+                third.to_vec(),
+                multisig.witness_script().to_bytes()
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_foreign_and_duplicate_signatures() {
+        let multisig = multisig();
+        let script = multisig.witness_script();
+        let foreign = sign(4, EcdsaSighashType::All);
+        let first = sign(1, EcdsaSighashType::All);
+        assert_eq!(
+            multisig_witness(script, &[first, foreign], sighash),
+            Err(FinalizeError::UnknownSignature(1))
+        );
+
+        // Signed for one sighash type but labelled as another
+        let relabelled = EcdsaSig {
+            hash_ty: EcdsaSighashType::None,
+            ..sign(2, EcdsaSighashType::All)
+        };
+        assert_eq!(
+            multisig_witness(script, &[relabelled, first], sighash),
+            Err(FinalizeError::UnknownSignature(0))
+        );
+
+        let again = sign(1, EcdsaSighashType::Single);
+        assert_eq!(
+            multisig_witness(script, &[first, again], sighash),
+            Err(FinalizeError::DuplicateSignature(multisig.keys()[0]))
+        );
+    }
+
+    #[test]
+    fn takes_exactly_threshold_signatures() {
+        let multisig = multisig();
+        let script = multisig.witness_script();
+        let signatures: Vec<_> = (1..=3).map(|i| sign(i, EcdsaSighashType::All)).collect();
+        assert_eq!(
+            multisig_witness(script, &signatures[..1], sighash),
+            Err(FinalizeError::MissingSignatures { have: 1, need: 2 })
+        );
+        assert_eq!(
+            multisig_witness(script, &signatures, sighash),
+            Err(FinalizeError::ExtraSignatures { have: 3, need: 2 })
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! The spend the unit tests share: keys made of one repeated byte, outputs
+//! of txid 0707…07 worth 50000 sat each, and a P2WPKH recipient, built at
+//! 1 sat/vB with change back to the spent script.
+
+use crate::builder::{self, InputTemplate, Recipient, Unsigned};
+use crate::prevout::Prevout;
+use bitcoin::{
+    blockdata::{script::Script, transaction::OutPoint},
+    hashes::Hash,
+    network::constants::Network,
+    secp256k1::SecretKey,
+    util::amount::Amount,
+    PackedLockTime, PrivateKey, Txid, WPubkeyHash,
+};
+
+pub fn secret_key(i: u8) -> SecretKey {
+    SecretKey::from_slice(&[i; 32]).unwrap()
+}
+
+pub fn key(i: u8) -> PrivateKey {
+    PrivateKey::new(secret_key(i), Network::Bitcoin)
+}
+
+/// Keys 1 to `n`.
+pub fn keys(n: u8) -> Vec<PrivateKey> {
+    (1..=n).map(key).collect()
+}
+
+/// One output of `script_pubkey` per amount, at vouts 0, 1, ….
+pub fn prevouts(script_pubkey: &Script, amounts: &[u64]) -> Vec<Prevout> {
+    amounts
+        .iter()
+        .enumerate()
+        .map(|(vout, &amount)| Prevout {
+            outpoint: OutPoint {
+                txid: Txid::from_slice(&[7; 32]).unwrap(),
+                vout: vout as u32,
+            },
+            amount: Amount::from_sat(amount),
+            script_pubkey: script_pubkey.clone(),
+        })
+        .collect()
+}
+
+/// A single 50000 sat output of `script_pubkey`.
+pub fn prevout(script_pubkey: &Script) -> Vec<Prevout> {
+    prevouts(script_pubkey, &[50_000])
+}
+
+pub fn recipient(amount: u64) -> Recipient {
+    Recipient {
+        script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+        amount: Amount::from_sat(amount),
+    }
+}
+
+/// Spends `prevouts` to one recipient of `amount` sat.
+pub fn build(
+    template: &InputTemplate,
+    lock_time: PackedLockTime,
+    prevouts: &[Prevout],
+    amount: u64,
+) -> Unsigned {
+    builder::build(
+        template,
+        lock_time,
+        prevouts,
+        &[recipient(amount)],
+        &prevouts[0].script_pubkey,
+        1.0,
+    )
+    .unwrap()
+}
@@ -0,0 +1,414 @@
+use crate::builder::InputTemplate;
+use crate::multisig::MultisigError;
+use crate::signature::{self, SIGNATURE_SIZE};
+use crate::timelock::Timelock;
+use bitcoin::{
+    blockdata::{
+        opcodes,
+        script::{Builder, Script},
+        transaction::Transaction,
+        witness::Witness,
+    },
+    consensus::encode::VarInt,
+    hashes::{hash160, sha256, Hash},
+    network::constants::Network,
+    secp256k1::{Message, Secp256k1, Signing},
+    util::sighash::{self, EcdsaSighashType, SighashCache},
+    Address, EcdsaSig, PrivateKey, PublicKey,
+};
+use std::fmt;
+
+/// Length the script requires of the preimage, so a claim cannot be made
+/// with one the counterparty's chain would reject.
+pub const PREIMAGE_SIZE: usize = 32;
+
+/// The hash the receiver must open to claim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashLock {
+    Sha256(sha256::Hash),
+    Hash160(hash160::Hash),
+}
+
+impl HashLock {
+    /// Whether `preimage` opens this lock.
+    pub fn matches(&self, preimage: &[u8]) -> bool {
+        preimage.len() == PREIMAGE_SIZE
+            && match self {
+                HashLock::Sha256(hash) => sha256::Hash::hash(preimage) == *hash,
+                HashLock::Hash160(hash) => hash160::Hash::hash(preimage) == *hash,
+            }
+    }
+}
+
+/// Which way an HTLC output is spent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Path {
+    /// The receiver reveals the preimage.
+    Claim,
+    /// The sender takes the funds back after the timelock.
+    Refund,
+}
+
+#[derive(Debug)]
+pub enum HtlcError {
+    UncompressedKey(PublicKey),
+    InvalidTimelock(Timelock),
+    WrongPreimage,
+    Sighash(sighash::Error),
+}
+
+impl fmt::Display for HtlcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HtlcError::UncompressedKey(key) => {
+                write!(f, "{}", MultisigError::UncompressedKey(*key))
+            }
+            HtlcError::InvalidTimelock(timelock) => {
+                write!(f, "invalid refund timelock: {}", timelock)
+            }
+            HtlcError::WrongPreimage => write!(
+                f,
+                "preimage is not {} bytes hashing to the hashlock",
+                PREIMAGE_SIZE
+            ),
+            HtlcError::Sighash(e) => write!(f, "sighash: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HtlcError {}
+
+impl From<sighash::Error> for HtlcError {
+    fn from(e: sighash::Error) -> Self {
+        HtlcError::Sighash(e)
+    }
+}
+
+/// A hashed timelock contract:
+///
+/// ```text
+/// OP_IF
+///     OP_SIZE 32 OP_EQUALVERIFY OP_SHA256|OP_HASH160 <hash> OP_EQUALVERIFY
+///     <receiver>
+/// OP_ELSE
+///     <timelock> OP_CHECKSEQUENCEVERIFY|OP_CHECKLOCKTIMEVERIFY OP_DROP
+///     <sender>
+/// OP_ENDIF
+/// OP_CHECKSIG
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Htlc {
+    hashlock: HashLock,
+    refund: Timelock,
+    witness_script: Script,
+}
+
+impl Htlc {
+    pub fn new(
+        hashlock: HashLock,
+        receiver: PublicKey,
+        sender: PublicKey,
+        refund: Timelock,
+    ) -> Result<Self, HtlcError> {
+        for key in [receiver, sender] {
+            if !key.compressed {
+                return Err(HtlcError::UncompressedKey(key));
+            }
+        }
+        if !refund.is_valid() {
+            return Err(HtlcError::InvalidTimelock(refund));
+        }
+
+        let mut builder = Builder::new()
+            .push_opcode(opcodes::all::OP_IF)
+            .push_opcode(opcodes::all::OP_SIZE)
+            .push_int(PREIMAGE_SIZE as i64)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY);
+        builder = match hashlock {
+            HashLock::Sha256(hash) => builder
+                .push_opcode(opcodes::all::OP_SHA256)
+                .push_slice(&hash[..]),
+            HashLock::Hash160(hash) => builder
+                .push_opcode(opcodes::all::OP_HASH160)
+                .push_slice(&hash[..]),
+        };
+        builder = builder
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_key(&receiver)
+            .push_opcode(opcodes::all::OP_ELSE);
+        let witness_script = refund
+            .push_verify(builder)
+            .push_key(&sender)
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+
+        Ok(Htlc {
+            hashlock,
+            refund,
+            witness_script,
+        })
+    }
+
+    pub fn hashlock(&self) -> HashLock {
+        self.hashlock
+    }
+
+    pub fn refund(&self) -> Timelock {
+        self.refund
+    }
+
+    pub fn witness_script(&self) -> &Script {
+        &self.witness_script
+    }
+
+    pub fn p2wsh_address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script, network)
+    }
+
+    pub fn p2sh_p2wsh_address(&self, network: Network) -> Address {
+        Address::p2shwsh(&self.witness_script, network)
+    }
+
+    /// Exact size of the signed witness for `path`.
+    pub fn witness_size(&self, path: Path) -> usize {
+        let script_len = self.witness_script.len();
+        let (items, middle) = match path {
+            // <sig> <preimage> <1> <script>
+            Path::Claim => (4, 1 + PREIMAGE_SIZE + 2),
+            // <sig> <> <script>
+            Path::Refund => (3, 1),
+        };
+        VarInt(items).len()
+            + 1
+            + SIGNATURE_SIZE
+            + middle
+            + VarInt(script_len as u64).len()
+            + script_len
+    }
+
+    /// An input spending the HTLC along `path`, native P2WSH or wrapped in
+    /// P2SH, with the sequence a CSV refund needs.
+    pub fn template(&self, path: Path, wrapped: bool) -> InputTemplate {
+        let size = self.witness_size(path);
+        let mut template = if wrapped {
+            InputTemplate::p2sh_wrapped(&self.witness_script, size)
+        } else {
+            InputTemplate::p2wsh(size)
+        };
+        if path == Path::Refund {
+            template.sequence = self.refund.sequence();
+        }
+        template
+    }
+
+    /// `<sig> <preimage> <1> <witness script>`
+    pub fn claim_witness(
+        &self,
+        signature: &EcdsaSig,
+        preimage: &[u8],
+    ) -> Result<Witness, HtlcError> {
+        if !self.hashlock.matches(preimage) {
+            return Err(HtlcError::WrongPreimage);
+        }
+        let mut witness = Witness::new();
+        witness.push(signature.to_vec());
+        witness.push(preimage);
+        witness.push([1]);
+        witness.push(self.witness_script.as_bytes());
+        Ok(witness)
+    }
+
+    /// `<sig> <> <witness script>`
+    pub fn refund_witness(&self, signature: &EcdsaSig) -> Witness {
+        let mut witness = Witness::new();
+        witness.push(signature.to_vec());
+        witness.push([]);
+        witness.push(self.witness_script.as_bytes());
+        witness
+    }
+
+    /// Finds the preimage revealed by an input of `tx` claiming this HTLC.
+    pub fn extract_preimage(&self, tx: &Transaction) -> Option<Vec<u8>> {
+        tx.input.iter().find_map(|input| {
+            let items: Vec<&[u8]> = input.witness.iter().collect();
+            match items[..] {
+                [_, preimage, [1], script]
+                    if script == self.witness_script.as_bytes()
+                        && self.hashlock.matches(preimage) =>
+                {
+                    Some(preimage.to_vec())
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Signs input `index`, which spends `amount` sats of this HTLC, of the
+    /// transaction behind `cache`. The transaction must already carry the
+    /// sequence and lock time of its path.
+    pub fn sign<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        cache: &mut SighashCache<&Transaction>,
+        index: usize,
+        amount: u64,
+        key: &PrivateKey,
+        sighash_type: EcdsaSighashType,
+    ) -> Result<EcdsaSig, HtlcError> {
+        let sighash =
+            cache.segwit_signature_hash(index, &self.witness_script, amount, sighash_type)?;
+        let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        Ok(EcdsaSig {
+            sig: signature::sign(secp, &message, &key.inner),
+            hash_ty: sighash_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, key};
+    use crate::prevout::Prevout;
+    use crate::verify::{self, Flags};
+    use bitcoin::blockdata::locktime::PackedLockTime;
+
+    const PREIMAGE: [u8; PREIMAGE_SIZE] = [42; PREIMAGE_SIZE];
+
+    fn hashlocks(preimage: &[u8]) -> [HashLock; 2] {
+        [
+            HashLock::Sha256(sha256::Hash::hash(preimage)),
+            HashLock::Hash160(hash160::Hash::hash(preimage)),
+        ]
+    }
+
+    /// Receiver key 1, sender key 2.
+    fn htlc(hashlock: HashLock, refund: Timelock) -> Htlc {
+        let secp = Secp256k1::new();
+        Htlc::new(
+            hashlock,
+            key(1).public_key(&secp),
+            key(2).public_key(&secp),
+            refund,
+        )
+        .unwrap()
+    }
+
+    /// Spends the HTLC along `path`, signed by the key that path needs.
+    fn spend(htlc: &Htlc, path: Path) -> (Transaction, Vec<Prevout>) {
+        let secp = Secp256k1::new();
+        let prevouts = fixture::prevout(&htlc.p2wsh_address(Network::Bitcoin).script_pubkey());
+        let lock_time = match path {
+            Path::Claim => PackedLockTime::ZERO,
+            Path::Refund => htlc.refund().lock_time(),
+        };
+        let mut tx = fixture::build(&htlc.template(path, false), lock_time, &prevouts, 40_000).tx;
+        let signer = match path {
+            Path::Claim => key(1),
+            Path::Refund => key(2),
+        };
+        let signature = htlc
+            .sign(
+                &secp,
+                &mut SighashCache::new(&tx),
+                0,
+                50_000,
+                &signer,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        tx.input[0].witness = match path {
+            Path::Claim => htlc.claim_witness(&signature, &PREIMAGE).unwrap(),
+            Path::Refund => htlc.refund_witness(&signature),
+        };
+        (tx, prevouts)
+    }
+
+    #[test]
+    fn claim_and_refund_verify() {
+        let secp = Secp256k1::verification_only();
+        for hashlock in hashlocks(&PREIMAGE) {
+            for refund in [Timelock::Blocks(144), Timelock::Height(800_000)] {
+                let htlc = htlc(hashlock, refund);
+                for path in [Path::Claim, Path::Refund] {
+                    let (tx, prevouts) = spend(&htlc, path);
+                    let context = format!("{:?} {} {:?}", hashlock, refund, path);
+                    assert_eq!(
+                        tx.input[0].witness.serialized_len(),
+                        htlc.witness_size(path),
+                        "{}",
+                        context
+                    );
+                    let results = verify::verify(&secp, &tx, &prevouts, Flags::Standard);
+                    assert!(results[0].is_valid(), "{}: {}", context, results[0]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_preimages() {
+        let secp = Secp256k1::new();
+        let signature = EcdsaSig::sighash_all(signature::sign(
+            &secp,
+            &Message::from_slice(&[1; 32]).unwrap(),
+            &key(1).inner,
+        ));
+        // The script checks OP_SIZE, so even a preimage that hashes right
+        // must be 32 bytes
+        let short = [42; PREIMAGE_SIZE - 1];
+        for hashlock in hashlocks(&short) {
+            let htlc = htlc(hashlock, Timelock::Blocks(144));
+            assert!(!hashlock.matches(&short));
+            assert!(matches!(
+                htlc.claim_witness(&signature, &short),
+                Err(HtlcError::WrongPreimage)
+            ));
+        }
+        for hashlock in hashlocks(&PREIMAGE) {
+            let htlc = htlc(hashlock, Timelock::Blocks(144));
+            assert!(matches!(
+                htlc.claim_witness(&signature, &[43; PREIMAGE_SIZE]),
+                Err(HtlcError::WrongPreimage)
+            ));
+        }
+    }
+
+    #[test]
+    fn extracts_preimage_from_claims_only() {
+        for hashlock in hashlocks(&PREIMAGE) {
+            let htlc = htlc(hashlock, Timelock::Blocks(144));
+            let (claim, _) = spend(&htlc, Path::Claim);
+            assert_eq!(htlc.extract_preimage(&claim), Some(PREIMAGE.to_vec()));
+            let (refund, _) = spend(&htlc, Path::Refund);
+            assert_eq!(htlc.extract_preimage(&refund), None);
+
+            // Nor from a claim of a different HTLC
+            let other = self::htlc(hashlock, Timelock::Blocks(145));
+            assert_eq!(other.extract_preimage(&claim), None);
+        }
+    }
+
+    #[test]
+    fn rejects_uncompressed_keys_and_useless_timelocks() {
+        let secp = Secp256k1::new();
+        let mut uncompressed = key(1).public_key(&secp);
+        uncompressed.compressed = false;
+        let hashlock = hashlocks(&PREIMAGE)[0];
+        let sender = key(2).public_key(&secp);
+        assert!(matches!(
+            Htlc::new(hashlock, uncompressed, sender, Timelock::Blocks(144)),
+            Err(HtlcError::UncompressedKey(_))
+        ));
+        assert!(matches!(
+            Htlc::new(
+                hashlock,
+                sender,
+                key(3).public_key(&secp),
+                Timelock::Blocks(0)
+            ),
+            Err(HtlcError::InvalidTimelock(Timelock::Blocks(0)))
+        ));
+    }
+}
@@ -0,0 +1,399 @@
+use bitcoin::{
+    network::constants::Network,
+    secp256k1::{Secp256k1, Signing, ONE_KEY},
+    util::{
+        bip32::{self, ChainCode, DerivationPath, ExtendedPrivKey},
+        key,
+    },
+    PrivateKey,
+};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Keystore format: scrypt over the passphrase keys ChaCha20-Poly1305,
+/// which seals the key as text (a WIF or `xprv/path`).
+const KEYSTORE_VERSION: u8 = 1;
+/// scrypt cost for new keystores: N = 2^15, r = 8, p = 1, about 32 MiB.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// Largest scrypt cost a keystore may ask for, so that a crafted file
+/// cannot make opening it take gigabytes or hours: 2^18 * 8 * 128 bytes is
+/// 256 MiB.
+const MAX_SCRYPT_LOG_N: u8 = 18;
+const MAX_SCRYPT_R: u32 = 8;
+const MAX_SCRYPT_P: u32 = 4;
+const SALT_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum KeyError {
+    /// Raw hex carries no network, so it is refused.
+    Hex,
+    Wif(key::Error),
+    /// A WIF for an uncompressed key, which segwit scripts cannot use and
+    /// whose compressed form would be a different address.
+    UncompressedWif,
+    Bip32(bip32::Error),
+    WrongNetwork {
+        expected: Network,
+        found: Network,
+    },
+    /// A derivation path given for a keystore that holds a WIF.
+    NotExtended,
+    Json(serde_json::Error),
+    UnsupportedKeystore(u8),
+    /// A keystore field that is not valid hex, or scrypt parameters out of
+    /// range.
+    CorruptKeystore(&'static str),
+    /// The ciphertext did not authenticate.
+    WrongPassphrase,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyError::Hex => write!(
+                f,
+                "raw hex private keys are not accepted, give a WIF or xprv/path"
+            ),
+            KeyError::Wif(e) => write!(f, "invalid WIF: {}", e),
+            KeyError::UncompressedWif => write!(f, "WIF is for an uncompressed key"),
+            KeyError::Bip32(e) => write!(f, "invalid extended key: {}", e),
+            KeyError::WrongNetwork { expected, found } => {
+                write!(f, "key is for {}, expected {}", found, expected)
+            }
+            KeyError::NotExtended => {
+                write!(
+                    f,
+                    "a derivation path needs an xprv, the keystore holds a WIF"
+                )
+            }
+            KeyError::Json(e) => write!(f, "invalid keystore: {}", e),
+            KeyError::UnsupportedKeystore(version) => {
+                write!(f, "unsupported keystore version {}", version)
+            }
+            KeyError::CorruptKeystore(field) => write!(f, "corrupt keystore: bad {}", field),
+            KeyError::WrongPassphrase => write!(f, "wrong keystore passphrase"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl From<key::Error> for KeyError {
+    fn from(e: key::Error) -> Self {
+        KeyError::Wif(e)
+    }
+}
+
+impl From<bip32::Error> for KeyError {
+    fn from(e: bip32::Error) -> Self {
+        KeyError::Bip32(e)
+    }
+}
+
+impl From<serde_json::Error> for KeyError {
+    fn from(e: serde_json::Error) -> Self {
+        KeyError::Json(e)
+    }
+}
+
+/// Signing keys, wiped when dropped.
+#[derive(Default)]
+pub struct Keys(Vec<PrivateKey>);
+
+impl Keys {
+    /// Room for `n` keys, so pushing them leaves no copies behind in a
+    /// reallocated buffer.
+    pub fn with_capacity(n: usize) -> Self {
+        Keys(Vec::with_capacity(n))
+    }
+
+    pub fn push(&mut self, key: PrivateKey) {
+        self.0.push(key);
+    }
+}
+
+impl Deref for Keys {
+    type Target = [PrivateKey];
+
+    fn deref(&self) -> &[PrivateKey] {
+        &self.0
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        for key in &mut self.0 {
+            erase(&mut key.inner, ONE_KEY);
+        }
+    }
+}
+
+/// Overwrites a secret with a write the compiler may not drop. secp256k1
+/// keys are `Copy` and have no zeroize support of their own, so this can
+/// only clear the copies we hold.
+pub fn erase<T: Copy>(secret: &mut T, blank: T) {
+    // SAFETY: `secret` is a valid, exclusive reference and `T` has no drop
+    // glue to skip.
+    unsafe { std::ptr::write_volatile(secret, blank) }
+}
+
+/// Whether a key encoded for `found` may be used on `expected`. WIF and
+/// BIP32 serializations only tell mainnet from the test networks.
+fn check_network(expected: Network, found: Network) -> Result<(), KeyError> {
+    if (expected == Network::Bitcoin) == (found == Network::Bitcoin) {
+        Ok(())
+    } else {
+        Err(KeyError::WrongNetwork { expected, found })
+    }
+}
+
+pub fn erase_xprv(xprv: &mut ExtendedPrivKey) {
+    erase(&mut xprv.private_key, ONE_KEY);
+    erase(&mut xprv.chain_code, ChainCode::from(&[0; 32][..]));
+}
+
+fn is_extended(spec: &str) -> bool {
+    spec.starts_with("xprv") || spec.starts_with("tprv")
+}
+
+/// Parses an `xprv`/`tprv` for `network`.
+pub fn parse_xprv(s: &str, network: Network) -> Result<ExtendedPrivKey, KeyError> {
+    let mut xprv = ExtendedPrivKey::from_str(s.trim())?;
+    if let Err(e) = check_network(network, xprv.network) {
+        erase_xprv(&mut xprv);
+        return Err(e);
+    }
+    xprv.network = network;
+    Ok(xprv)
+}
+
+/// Parses a WIF private key, or an `xprv`/`tprv` followed by a derivation
+/// path such as `xprv.../48'/0'/0'/2'/0/0`, checking it is for `network`.
+pub fn parse_key<C: Signing>(
+    secp: &Secp256k1<C>,
+    spec: &str,
+    network: Network,
+) -> Result<PrivateKey, KeyError> {
+    let spec = spec.trim();
+    if is_extended(spec) {
+        let (xprv, path) = spec.split_once('/').unwrap_or((spec, ""));
+        let path = if path.is_empty() {
+            DerivationPath::master()
+        } else {
+            DerivationPath::from_str(&format!("m/{}", path))?
+        };
+        let mut master = parse_xprv(xprv, network)?;
+        let derived = master.derive_priv(secp, &path);
+        erase_xprv(&mut master);
+        let mut derived = derived?;
+        let key = PrivateKey::new(derived.private_key, network);
+        erase_xprv(&mut derived);
+        Ok(key)
+    } else if spec.len() == 64 && spec.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err(KeyError::Hex)
+    } else {
+        let mut wif = PrivateKey::from_wif(spec)?;
+        let checked = if wif.compressed {
+            check_network(network, wif.network)
+        } else {
+            Err(KeyError::UncompressedWif)
+        };
+        let key = PrivateKey::new(wif.inner, network);
+        erase(&mut wif.inner, ONE_KEY);
+        checked.map(|_| key)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u8,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn cipher(
+    passphrase: &[u8],
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Result<ChaCha20Poly1305, KeyError> {
+    let params = scrypt::Params::new(log_n, r, p, 32)
+        .map_err(|_| KeyError::CorruptKeystore("scrypt parameters"))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase, salt, &params, &mut key[..])
+        .map_err(|_| KeyError::CorruptKeystore("scrypt parameters"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key[..])))
+}
+
+/// Seals `spec`, a key as `parse_key` reads it, under `passphrase` and
+/// returns the keystore as JSON.
+pub fn encrypt_keystore(spec: &str, passphrase: &[u8]) -> Result<String, KeyError> {
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?
+        .encrypt(&nonce, spec.trim().as_bytes())
+        .expect("ChaCha20-Poly1305 takes any plaintext length we give it");
+    let keystore = Keystore {
+        version: KEYSTORE_VERSION,
+        log_n: SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    Ok(serde_json::to_string_pretty(&keystore)?)
+}
+
+/// Opens a keystore written by `encrypt_keystore` and returns the key in
+/// it as text.
+pub fn decrypt_keystore(json: &str, passphrase: &[u8]) -> Result<Zeroizing<String>, KeyError> {
+    let keystore: Keystore = serde_json::from_str(json)?;
+    if keystore.version != KEYSTORE_VERSION {
+        return Err(KeyError::UnsupportedKeystore(keystore.version));
+    }
+    let field = |value: &str, name| hex::decode(value).map_err(|_| KeyError::CorruptKeystore(name));
+    let salt = field(&keystore.salt, "salt")?;
+    let nonce = field(&keystore.nonce, "nonce")?;
+    if nonce.len() != 12 {
+        return Err(KeyError::CorruptKeystore("nonce"));
+    }
+    let ciphertext = field(&keystore.ciphertext, "ciphertext")?;
+    if keystore.log_n > MAX_SCRYPT_LOG_N || keystore.r > MAX_SCRYPT_R || keystore.p > MAX_SCRYPT_P {
+        return Err(KeyError::CorruptKeystore("scrypt parameters"));
+    }
+
+    let plaintext = cipher(passphrase, &salt, keystore.log_n, keystore.r, keystore.p)?
+        .decrypt(Nonce::from_slice(&nonce), &ciphertext[..])
+        .map_err(|_| KeyError::WrongPassphrase)?;
+    let plaintext = Zeroizing::new(plaintext);
+    let spec = std::str::from_utf8(&plaintext).map_err(|_| KeyError::CorruptKeystore("key"))?;
+    Ok(Zeroizing::new(spec.to_owned()))
+}
+
+/// Opens a keystore and parses the key in it for `network`, first deriving
+/// along `path` if the keystore holds an xprv.
+pub fn open_keystore<C: Signing>(
+    secp: &Secp256k1<C>,
+    json: &str,
+    passphrase: &[u8],
+    path: Option<&str>,
+    network: Network,
+) -> Result<PrivateKey, KeyError> {
+    let spec = decrypt_keystore(json, passphrase)?;
+    match path {
+        None => parse_key(secp, &spec, network),
+        Some(_) if !is_extended(&spec) => Err(KeyError::NotExtended),
+        Some(path) => {
+            let path = path.trim_start_matches('m').trim_start_matches('/');
+            let spec = Zeroizing::new(format!("{}/{}", spec.as_str(), path));
+            parse_key(secp, &spec, network)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIF: &str = "KyABXNtAZeRnWwmiUSMT1ebGErQBqWKq3UuaAm5MbW8eAazmG829";
+
+    #[test]
+    fn keystore_round_trip() {
+        let secp = Secp256k1::new();
+        let json = encrypt_keystore(WIF, b"correct horse").unwrap();
+        assert_eq!(*decrypt_keystore(&json, b"correct horse").unwrap(), WIF);
+        let key = open_keystore(&secp, &json, b"correct horse", None, Network::Bitcoin).unwrap();
+        assert_eq!(key.to_wif(), WIF);
+        assert!(matches!(
+            decrypt_keystore(&json, b"battery staple"),
+            Err(KeyError::WrongPassphrase)
+        ));
+        assert!(matches!(
+            open_keystore(
+                &secp,
+                &json,
+                b"correct horse",
+                Some("0/1"),
+                Network::Bitcoin
+            ),
+            Err(KeyError::NotExtended)
+        ));
+    }
+
+    #[test]
+    fn keystore_scrypt_cost_is_capped() {
+        let json = encrypt_keystore(WIF, b"correct horse").unwrap();
+        let mut keystore: Keystore = serde_json::from_str(&json).unwrap();
+        keystore.log_n = 30;
+        let json = serde_json::to_string(&keystore).unwrap();
+        assert!(matches!(
+            decrypt_keystore(&json, b"correct horse"),
+            Err(KeyError::CorruptKeystore("scrypt parameters"))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_networks_raw_hex_and_uncompressed_keys() {
+        let secp = Secp256k1::new();
+        assert!(matches!(
+            parse_key(&secp, WIF, Network::Regtest),
+            Err(KeyError::WrongNetwork {
+                expected: Network::Regtest,
+                found: Network::Bitcoin
+            })
+        ));
+        let hex = "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d";
+        assert!(matches!(
+            parse_key(&secp, hex, Network::Bitcoin),
+            Err(KeyError::Hex)
+        ));
+        // The same key as an uncompressed WIF
+        let uncompressed = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        assert!(matches!(
+            parse_key(&secp, uncompressed, Network::Bitcoin),
+            Err(KeyError::UncompressedWif)
+        ));
+    }
+
+    #[test]
+    fn derives_bip32_test_vector_1() {
+        let secp = Secp256k1::new();
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap();
+        for (path, expected) in [
+            (
+                "0'",
+                "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            ),
+            (
+                "0'/1",
+                "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+            ),
+        ] {
+            let spec = format!("{}/{}", master, path);
+            let key = parse_key(&secp, &spec, Network::Bitcoin).unwrap();
+            assert_eq!(key.inner.display_secret().to_string(), expected, "{}", path);
+        }
+        let spec = format!("{}/0'", master);
+        assert!(matches!(
+            parse_key(&secp, &spec, Network::Testnet),
+            Err(KeyError::WrongNetwork { .. })
+        ));
+    }
+}
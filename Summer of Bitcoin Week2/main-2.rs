@@ -1,133 +1,116 @@
-use bitcoin::{
-    blockdata::{
-        script::Script,
-        transaction::{OutPoint, Transaction, TxIn, TxOut},
-        witness::Witness,
-    },
-    consensus::encode::serialize_hex,
-    hash_types::WScriptHash,
-    hashes::{sha256, Hash},
-    network::constants::Network,
-    secp256k1::{Message, Secp256k1, SecretKey},
-    util::{amount::Amount, sighash::EcdsaSighashType, sighash::SighashCache},
-    Address, PrivateKey, Txid,
-};
-use std::fs;
-use std::str::FromStr;
+mod account;
+mod builder;
+mod cli;
+mod commands;
+mod decode;
+mod descriptor;
+mod finalize;
+#[cfg(test)]
+mod fixture;
+mod htlc;
+mod keys;
+mod multisig;
+mod musig;
+mod policy;
+mod prevout;
+mod psbt;
+mod sigfile;
+mod signature;
+mod taproot;
+mod timelock;
+#[cfg(test)]
+mod vectors;
+mod verify;
+
+const USAGE: &str = "usage:
+  address --threshold <m> --key <pubkey>... [--sorted] [--origin <[fingerprint/path]pubkey>...]
+        [--fee-rate <sat/vB>] [--network <name>]
+  descriptor --descriptor <sh(wsh(multi|sortedmulti(...)))> [--index <i>] [--network <name>]
+  build ([--threshold <m>] [--key <pubkey>...] [--sorted] | --descriptor <desc> [--index <i>])
+        [--wrapping p2sh-p2wsh|p2wsh|p2sh]
+        <signing keys>
+        (--prevout <txid:vout:amount_sat:script_hex>... | --prevouts <file.json>
+         | --funding-tx <hex> [--vout <n>])
+        --to <address:amount_sat>... [--fee-rate <sat/vB>] [--change <address>]
+        [--network <name>] [--sighash <type>...]
+        [--psbt <file> [--origin <[fingerprint/path]pubkey>...] | --unsigned]
+  timelock address --threshold <m> --key <pubkey>... [--sorted]
+        [--recovery-threshold <k>] [--recovery-key <pubkey>...] (--csv <blocks> | --cltv <height>)
+        [--network <name>]
+  timelock spend <timelock address flags> <build prevout and output flags>
+        [--branch primary|recovery] <signing keys>
+  htlc address --receiver <pubkey> --sender <pubkey> (--sha256 <hash> | --hash160 <hash>)
+        (--csv <blocks> | --cltv <height>) [--network <name>]
+  htlc claim <htlc address flags> <build prevout and output flags> [--wrapped]
+        <signing key> --preimage <hex>
+  htlc refund <htlc address flags> <build prevout and output flags> [--wrapped]
+        <signing key>
+  htlc extract <htlc address flags> --tx <hex>
+  taproot address ([--threshold <m> --key <pubkey>... [--sorted]] [--leaf <m:pubkey,pubkey...>...])
+        [--internal-key <pubkey>] [--network <name>]
+  taproot spend <taproot address flags> <build prevout and output flags> [--leaf-index <i>]
+        [--sighash <type>] <signing keys>
+  musig address --key <pubkey>... [--network <name>]
+  musig build <musig address flags> <build prevout and output flags>
+  musig nonce <musig address flags> --tx <unsigned hex> <signing key> --secnonce <file>
+  musig sign <musig address flags> --tx <unsigned hex> <prevout flags> <signing key>
+        --secnonce <file> --nonce <pubkey:nonce,...>...
+  musig combine <musig address flags> --tx <unsigned hex> <prevout flags>
+        --nonce <pubkey:nonce,...>... --psig <pubkey:psig,...>...
+  partial sign <build script flags> --tx <unsigned hex> <prevout flags> <signing key>
+        [--sighash <type>...] --out <signature file>
+  partial combine <build script flags> --tx <unsigned hex> <prevout flags>
+        --signatures <signature file>...
+  sign --psbt <file> <signing keys> [--out <file>] [--network <name>]
+  add-input --psbt <file> --prevout <txid:vout:amount_sat:script_hex>
+            [--witness-script <hex>] [--redeem-script <hex>] [--sequence <n>] [--out <file>]
+  add-output --psbt <file> --to <address:amount_sat> [--network <name>] [--out <file>]
+  combine --psbt <file>... --out <file>
+  finalize --psbt <file> [--out <file>]
+  extract --psbt <file>
+  decode [--tx <hex>] [--network <name>]
+  verify --tx <hex> (--prevout <txid:vout:amount_sat:script_hex>... | --prevouts <file.json>)
+         [--consensus]
+  policy --tx <hex> (--prevout <txid:vout:amount_sat:script_hex>... | --prevouts <file.json>)
+  cosigner new [--words <12-24> | --raw] --out <keystore> [--account <n>]
+        [--passphrase-file <file>] [--network <name>]
+  cosigner restore [--raw] --out <keystore> [--account <n>] [--passphrase-file <file>]
+        [--network <name>] < mnemonic or seed hex
+  cosigner export --keystore <file> [--account <n>] [--passphrase-file <file>] [--network <name>]
+  cosigner derive --account-key <[fingerprint/48'/coin'/account'/type']xpub>... --threshold <m>
+        [--index <i>] [--change] [--network <name>]
+  keystore create --out <file> [--passphrase-file <file>] [--network <name>] < key
+
+Signing keys are given as --signing-key <WIF | xprv/path> or --keystore <file>,
+repeated as needed, with [--passphrase-file <file>] to skip the prompt and
+[--key-path <path>] to derive from keystores holding an xprv.";
 
 fn main() {
-    println!("Starting transaction creation...");
-
-    //Initialize secp256k1 context for signing
-    let secp = Secp256k1::new();
-
-    //private keys from hex
-    let privkey1_hex = "39dc0a9f0b185a2ee56349691f34716e6e0cda06a7f9707742ac113c4e2317bf";
-    let privkey2_hex = "5077ccd9c558b7d04a81920d38aa11b4a9f9de3b23fab45c3ef28039920fdd6d";
-    let secret_key1 = SecretKey::from_slice(&hex::decode(privkey1_hex).unwrap()).unwrap();
-    let secret_key2 = SecretKey::from_slice(&hex::decode(privkey2_hex).unwrap()).unwrap();
-    let privkey1 = PrivateKey::new(secret_key1, Network::Bitcoin);
-    let privkey2 = PrivateKey::new(secret_key2, Network::Bitcoin);
-    println!("Private keys parsed.");
-
-    //script
-    let witness_script_hex = "5221032ff8c5df0bc00fe1ac2319c3b8070d6d1e04cfbf4fedda499ae7b775185ad53b21039bbc8d24f89e5bc44c5b0d1980d6658316a6b2440023117c3c03a4975b04dd5652ae";
-    let witness_script_bytes =
-        hex::decode(witness_script_hex).expect("Invalid hex in witness script");
-    let witness_script = Script::from(witness_script_bytes);
-    println!("Witness script parsed.");
-
-    //P2SH redeem script: 0 <sha256 of witness script>
-    let witness_script_hash = sha256::Hash::hash(&witness_script.as_bytes());
-    let wscript_hash = WScriptHash::from_hash(witness_script_hash);
-    let p2sh_redeem_script = Script::new_v0_p2wsh(&wscript_hash);
-    println!("P2SH redeem script created.");
-
-    // Create output: 0.001 BTC to the given address
-    let output_address = Address::from_str("325UUecEQuyrTd28Xs2hvAxdAjHM7XzqVF").unwrap();
-    let output_script_pubkey = output_address.script_pubkey();
-    let output_value = Amount::from_sat(100000); // 0.001 BTC = 100,000 satoshis
-    let output = TxOut {
-        value: output_value.to_sat(),
-        script_pubkey: output_script_pubkey,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("address") => commands::address::run(&args[1..]),
+        Some("build") => commands::build::run(&args[1..]),
+        Some("decode") => commands::decode::run(&args[1..]),
+        Some("descriptor") => commands::descriptor::run(&args[1..]),
+        Some("verify") => commands::verify::run(&args[1..]),
+        Some("policy") => commands::policy::run(&args[1..]),
+        Some("timelock") => commands::timelock::run(&args[1..]),
+        Some("htlc") => commands::htlc::run(&args[1..]),
+        Some("taproot") => commands::taproot::run(&args[1..]),
+        Some("musig") => commands::musig::run(&args[1..]),
+        Some("partial") => commands::partial::run(&args[1..]),
+        Some("sign") => commands::psbt::sign(&args[1..]),
+        Some("add-input") => commands::psbt::add_input(&args[1..]),
+        Some("add-output") => commands::psbt::add_output(&args[1..]),
+        Some("combine") => commands::psbt::combine(&args[1..]),
+        Some("finalize") => commands::psbt::finalize(&args[1..]),
+        Some("extract") => commands::psbt::extract(&args[1..]),
+        Some("keystore") => commands::keystore::run(&args[1..]),
+        Some("cosigner") => commands::cosigner::run(&args[1..]),
+        _ => Err(USAGE.into()),
     };
-    println!("Output created: {:?}", output);
-
-    // Create input with manually constructed null outpoint (hash of zeros, index = 0)
-    let outpoint = OutPoint {
-        txid: Txid::from_slice(&[0u8; 32]).unwrap(),
-        vout: 0,
-    };
-
-    // Build scriptSig with P2SH redeem script
-    let script_sig = bitcoin::blockdata::script::Builder::new()
-        .push_slice(p2sh_redeem_script.as_bytes())
-        .into_script();
-
-    let input = TxIn {
-        previous_output: outpoint,
-        script_sig,
-        sequence: bitcoin::Sequence(0xffffffff),
-        witness: Witness::new(), // Empty witness, populated later
-    };
-    println!("Input created: {:?}", input);
-
-    // Build transaction: version 2 for SegWit, locktime 0
-    let mut tx = Transaction {
-        version: 2,
-        lock_time: bitcoin::PackedLockTime(0),
-        input: vec![input],
-        output: vec![output],
-    };
-    println!("Transaction built: {:?}", tx);
-
-    // Debugging: Print the transaction details before verification
-    println!("Transaction details: {:?}", tx);
-
-    // Compute SegWit sighash using BIP143
-    let mut cache = SighashCache::new(&tx);
-    let sighash_type = EcdsaSighashType::All;
-    let sighash = cache
-        .segwit_signature_hash(0, &witness_script, output_value.to_sat(), sighash_type)
-        .expect("Failed to compute sighash");
-    println!("Sighash computed: {:x}", sighash);
-
-    // Sign the sighash with both private keys
-    let message = Message::from_slice(&sighash[..]).unwrap();
-    let sig1 = secp.sign_ecdsa(&message, &privkey1.inner);
-    let sig2 = secp.sign_ecdsa(&message, &privkey2.inner);
-    println!("Signatures created.");
-
-    // Serialize signatures with sighash type (SIGHASH_ALL)
-    let mut sig1_bytes = sig1.serialize_der().to_vec();
-    sig1_bytes.push(sighash_type.to_u32() as u8);
-    
-    let mut sig2_bytes = sig2.serialize_der().to_vec();
-    sig2_bytes.push(sighash_type.to_u32() as u8);
-
-    // Set witness stack with swapped signature order
-    let mut witness = Witness::new();
-    witness.push(&[]); // Required dummy element for multisig
-    witness.push(&sig2_bytes); // Changed order: sig2 first
-    witness.push(&sig1_bytes); // Changed order: sig1 second
-    witness.push(&witness_script[..]);
-    tx.input[0].witness = witness;
-    println!("Witness stack set.");
-
-    // Debugging: Print the witness stack details
-    println!("Witness stack elements: {}", tx.input[0].witness.len());
-    for (i, item) in tx.input[0].witness.iter().enumerate() {
-        println!("Witness item {}: {:?}", i, item);
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
-
-    // Serialize the transaction to hex
-    let tx_hex = serialize_hex(&tx);
-    println!("Transaction serialized: {}...", &tx_hex[..20]);
-
-    // Write the serialized transaction to out.txt
-    fs::write("out.txt", &tx_hex).expect("Failed to write transaction hex to out.txt");
-    println!("Transaction hex written to out.txt");
-
-    // Debugging: Print the full transaction hex
-    println!("Full transaction hex: {}", tx_hex);
-}
\ No newline at end of file
+}
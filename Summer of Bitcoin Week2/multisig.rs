@@ -0,0 +1,327 @@
+use bitcoin::{
+    blockdata::{
+        opcodes,
+        script::{read_scriptint, Builder, Instruction, Script},
+    },
+    network::constants::Network,
+    Address, PublicKey,
+};
+use std::fmt;
+
+/// Consensus limit on the keys an `OP_CHECKMULTISIG` can check.
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Policy limit on the size of a P2WSH witness script.
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+/// Consensus limit on a single push, and so on a legacy P2SH redeem script.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// How the keys are placed in the script: as given (`multi`) or sorted
+/// lexicographically by their compressed encoding (BIP67, `sortedmulti`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrdering {
+    Multi,
+    SortedMulti,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultisigError {
+    NoKeys,
+    InvalidThreshold {
+        m: usize,
+        n: usize,
+    },
+    TooManyKeys(usize),
+    /// Segwit policy only relays compressed keys.
+    UncompressedKey(PublicKey),
+    DuplicateKey(PublicKey),
+    ScriptTooLarge(usize),
+    /// The script is too large to push as a legacy P2SH redeem script.
+    RedeemScriptTooLarge(usize),
+    /// The script is not `<m> <key>... <n> OP_CHECKMULTISIG`.
+    NotMultisig,
+}
+
+impl fmt::Display for MultisigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultisigError::NoKeys => write!(f, "at least one public key is required"),
+            MultisigError::InvalidThreshold { m, n } => {
+                write!(f, "threshold {} is not between 1 and {} keys", m, n)
+            }
+            MultisigError::TooManyKeys(n) => write!(
+                f,
+                "{} keys exceed the {}-key multisig limit",
+                n, MAX_PUBKEYS_PER_MULTISIG
+            ),
+            MultisigError::UncompressedKey(key) => write!(f, "key {} is not compressed", key),
+            MultisigError::DuplicateKey(key) => write!(f, "key {} appears more than once", key),
+            MultisigError::ScriptTooLarge(size) => write!(
+                f,
+                "witness script is {} bytes, over the {}-byte standard limit",
+                size, MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            ),
+            MultisigError::RedeemScriptTooLarge(size) => write!(
+                f,
+                "script is {} bytes, over the {}-byte legacy P2SH limit",
+                size, MAX_SCRIPT_ELEMENT_SIZE
+            ),
+            MultisigError::NotMultisig => {
+                write!(f, "script is not <m> <key>... <n> OP_CHECKMULTISIG")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MultisigError {}
+
+/// An m-of-n `OP_CHECKMULTISIG` witness script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multisig {
+    threshold: usize,
+    /// Keys in the order they appear in the script.
+    keys: Vec<PublicKey>,
+    witness_script: Script,
+}
+
+impl Multisig {
+    pub fn new(
+        keys: &[PublicKey],
+        threshold: usize,
+        ordering: KeyOrdering,
+    ) -> Result<Self, MultisigError> {
+        let n = keys.len();
+        if n == 0 {
+            return Err(MultisigError::NoKeys);
+        }
+        if n > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(MultisigError::TooManyKeys(n));
+        }
+        if threshold == 0 || threshold > n {
+            return Err(MultisigError::InvalidThreshold { m: threshold, n });
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if !key.compressed {
+                return Err(MultisigError::UncompressedKey(*key));
+            }
+            if keys[..i].contains(key) {
+                return Err(MultisigError::DuplicateKey(*key));
+            }
+        }
+
+        let mut keys = keys.to_vec();
+        if ordering == KeyOrdering::SortedMulti {
+            keys.sort_by_key(|key| key.to_bytes());
+        }
+
+        let mut builder = Builder::new().push_int(threshold as i64);
+        for key in &keys {
+            builder = builder.push_key(key);
+        }
+        let witness_script = builder
+            .push_int(n as i64)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        if witness_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(MultisigError::ScriptTooLarge(witness_script.len()));
+        }
+
+        Ok(Multisig {
+            threshold,
+            keys,
+            witness_script,
+        })
+    }
+
+    /// Parses a `multi` witness script, keeping the keys in script order.
+    pub fn from_script(script: &Script) -> Result<Self, MultisigError> {
+        let instructions = script
+            .instructions_minimal()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| MultisigError::NotMultisig)?;
+        let (last, rest) = instructions
+            .split_last()
+            .ok_or(MultisigError::NotMultisig)?;
+        if *last != Instruction::Op(opcodes::all::OP_CHECKMULTISIG) || rest.len() < 3 {
+            return Err(MultisigError::NotMultisig);
+        }
+        let threshold = small_int(&rest[0])?;
+        let n = small_int(&rest[rest.len() - 1])?;
+        let keys = rest[1..rest.len() - 1]
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::PushBytes(bytes) => {
+                    PublicKey::from_slice(bytes).map_err(|_| MultisigError::NotMultisig)
+                }
+                Instruction::Op(_) => Err(MultisigError::NotMultisig),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.len() != n {
+            return Err(MultisigError::NotMultisig);
+        }
+
+        let multisig = Multisig::new(&keys, threshold, KeyOrdering::Multi)?;
+        if multisig.witness_script != *script {
+            return Err(MultisigError::NotMultisig);
+        }
+        Ok(multisig)
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    pub fn witness_script(&self) -> &Script {
+        &self.witness_script
+    }
+
+    /// The P2SH redeem script `0 <sha256(witness script)>`, which is also the
+    /// native P2WSH scriptPubKey.
+    pub fn redeem_script(&self) -> Script {
+        self.witness_script.to_v0_p2wsh()
+    }
+
+    pub fn p2wsh_address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script, network)
+    }
+
+    pub fn p2sh_p2wsh_address(&self, network: Network) -> Address {
+        Address::p2shwsh(&self.witness_script, network)
+    }
+
+    /// The legacy P2SH address with the script itself as redeem script,
+    /// which only fits up to 15 compressed keys.
+    pub fn p2sh_address(&self, network: Network) -> Result<Address, MultisigError> {
+        let size = self.witness_script.len();
+        if size > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(MultisigError::RedeemScriptTooLarge(size));
+        }
+        Ok(Address::p2sh(&self.witness_script, network).expect("script fits in a push"))
+    }
+}
+
+/// Reads the number pushed by `OP_1`..`OP_16` or a minimal script number push.
+fn small_int(instruction: &Instruction) -> Result<usize, MultisigError> {
+    let value = match instruction {
+        Instruction::Op(op) => {
+            let code = op.to_u8();
+            let first = opcodes::all::OP_PUSHNUM_1.to_u8();
+            let last = opcodes::all::OP_PUSHNUM_16.to_u8();
+            if !(first..=last).contains(&code) {
+                return Err(MultisigError::NotMultisig);
+            }
+            (code - first + 1) as i64
+        }
+        Instruction::PushBytes(bytes) => {
+            read_scriptint(bytes).map_err(|_| MultisigError::NotMultisig)?
+        }
+    };
+    usize::try_from(value).map_err(|_| MultisigError::NotMultisig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    fn keys(n: u8) -> Vec<PublicKey> {
+        let secp = Secp256k1::signing_only();
+        (1..=n)
+            .map(|i| {
+                let key = SecretKey::from_slice(&[i; 32]).unwrap();
+                PublicKey::new(key.public_key(&secp))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn key_count_limits() {
+        let keys = keys(21);
+        assert_eq!(
+            Multisig::new(&keys, 2, KeyOrdering::Multi),
+            Err(MultisigError::TooManyKeys(21))
+        );
+        // The 20-key consensus limit binds long before the 3600-byte P2WSH
+        // policy limit: 20 compressed keys make a 685-byte script.
+        let multisig = Multisig::new(&keys[..20], 20, KeyOrdering::Multi).unwrap();
+        assert_eq!(multisig.witness_script().len(), 685);
+        assert!(multisig.witness_script().len() <= MAX_STANDARD_P2WSH_SCRIPT_SIZE);
+
+        assert_eq!(
+            Multisig::new(&[], 1, KeyOrdering::Multi),
+            Err(MultisigError::NoKeys)
+        );
+        for m in [0, 4] {
+            assert_eq!(
+                Multisig::new(&keys[..3], m, KeyOrdering::Multi),
+                Err(MultisigError::InvalidThreshold { m, n: 3 })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_uncompressed_and_duplicate_keys() {
+        let mut keys = keys(3);
+        let mut uncompressed = keys[1];
+        uncompressed.compressed = false;
+        assert_eq!(
+            Multisig::new(&[keys[0], uncompressed], 1, KeyOrdering::Multi),
+            Err(MultisigError::UncompressedKey(uncompressed))
+        );
+        keys.push(keys[0]);
+        assert_eq!(
+            Multisig::new(&keys, 2, KeyOrdering::SortedMulti),
+            Err(MultisigError::DuplicateKey(keys[0]))
+        );
+    }
+
+    #[test]
+    fn from_script_round_trip() {
+        let keys = keys(5);
+        for ordering in [KeyOrdering::Multi, KeyOrdering::SortedMulti] {
+            let multisig = Multisig::new(&keys, 3, ordering).unwrap();
+            let parsed = Multisig::from_script(multisig.witness_script()).unwrap();
+            assert_eq!(parsed, multisig);
+        }
+        let sorted = Multisig::new(&keys, 3, KeyOrdering::SortedMulti).unwrap();
+        assert!(sorted
+            .keys()
+            .windows(2)
+            .all(|w| w[0].to_bytes() < w[1].to_bytes()));
+
+        // A wrong key count, and a threshold that is not minimally encoded
+        let multisig = Multisig::new(&keys[..2], 1, KeyOrdering::Multi).unwrap();
+        let mut bytes = multisig.witness_script().to_bytes();
+        let n = bytes.len() - 2;
+        bytes[n] = opcodes::all::OP_PUSHNUM_3.to_u8();
+        assert_eq!(
+            Multisig::from_script(&Script::from(bytes)),
+            Err(MultisigError::NotMultisig)
+        );
+        let mut bytes = multisig.witness_script().to_bytes();
+        bytes.splice(..1, [0x01, 0x01]);
+        assert_eq!(
+            Multisig::from_script(&Script::from(bytes)),
+            Err(MultisigError::NotMultisig)
+        );
+    }
+
+    #[test]
+    fn legacy_p2sh_fits_fifteen_keys() {
+        let keys = keys(16);
+        let multisig = Multisig::new(&keys[..15], 15, KeyOrdering::Multi).unwrap();
+        assert_eq!(multisig.witness_script().len(), 513);
+        assert!(multisig.p2sh_address(Network::Bitcoin).is_ok());
+
+        let multisig = Multisig::new(&keys, 16, KeyOrdering::Multi).unwrap();
+        assert_eq!(
+            multisig.p2sh_address(Network::Bitcoin),
+            Err(MultisigError::RedeemScriptTooLarge(547))
+        );
+        // Segwit still takes it
+        multisig.p2wsh_address(Network::Bitcoin);
+    }
+}
@@ -0,0 +1,985 @@
+use crate::keys;
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine},
+    network::constants::Network,
+    secp256k1::{
+        constants::{CURVE_ORDER, GENERATOR_X},
+        schnorr, PublicKey, Scalar, Secp256k1, SecretKey, Verification, XOnlyPublicKey, ONE_KEY,
+    },
+    util::taproot::TapTweakHash,
+    Address, Witness,
+};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Size of a public nonce, two compressed points, and of an aggregate one.
+pub const PUB_NONCE_SIZE: usize = 66;
+/// Size of a secret nonce: both scalars and the signer's key.
+pub const SEC_NONCE_SIZE: usize = 97;
+/// Witness of a key-path spend with the default sighash type: the item
+/// count, then one 64-byte signature with its length.
+pub const KEY_PATH_WITNESS_SIZE: usize = 1 + 1 + 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MusigError {
+    /// The keys, or the tweaked key, sum to the point at infinity.
+    InfiniteKey,
+    /// A tweak not below the group order.
+    InvalidTweak,
+    /// No keys to aggregate.
+    NoKeys,
+    /// A secret nonce with a zero scalar or made for another key.
+    InvalidSecNonce,
+    /// A partial signature not below the group order.
+    InvalidPartialSig,
+    /// This key is not one of the aggregated keys.
+    UnknownKey(PublicKey),
+    /// The partial signature from this key does not verify.
+    BadPartialSig(PublicKey),
+    /// A message that is not `pubkey:hex,hex...`, or hex of the wrong size.
+    Encoding(String),
+}
+
+impl fmt::Display for MusigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MusigError::InfiniteKey => write!(f, "keys aggregate to the point at infinity"),
+            MusigError::InvalidTweak => write!(f, "tweak is not below the group order"),
+            MusigError::NoKeys => write!(f, "no keys to aggregate"),
+            MusigError::InvalidSecNonce => {
+                write!(f, "secret nonce is invalid or belongs to another key")
+            }
+            MusigError::InvalidPartialSig => {
+                write!(f, "partial signature is not below the group order")
+            }
+            MusigError::UnknownKey(key) => write!(f, "key {} is not in the aggregate", key),
+            MusigError::BadPartialSig(key) => {
+                write!(f, "partial signature from {} does not verify", key)
+            }
+            MusigError::Encoding(s) => write!(f, "invalid MuSig2 encoding `{}`", s),
+        }
+    }
+}
+
+impl std::error::Error for MusigError {}
+
+// Scalars mod n are `Option<SecretKey>` and points `Option<PublicKey>`, with
+// `None` for zero and the point at infinity, which secp256k1 cannot hold.
+
+fn generator() -> PublicKey {
+    let mut bytes = [0x02; 33];
+    bytes[1..].copy_from_slice(&GENERATOR_X);
+    PublicKey::from_slice(&bytes).expect("G is on the curve")
+}
+
+/// `bytes` as a scalar, failing unless it is below the group order.
+fn scalar(bytes: &[u8; 32]) -> Result<Option<SecretKey>, ()> {
+    if *bytes == [0; 32] {
+        Ok(None)
+    } else {
+        SecretKey::from_slice(bytes).map(Some).map_err(|_| ())
+    }
+}
+
+/// `bytes` as an integer reduced mod n. One subtraction is enough, as
+/// 2^256 < 2n.
+fn reduce(bytes: [u8; 32]) -> Option<SecretKey> {
+    if let Ok(value) = scalar(&bytes) {
+        return value;
+    }
+    let mut reduced = [0u8; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let mut digit = bytes[i] as i16 - CURVE_ORDER[i] as i16 - borrow;
+        borrow = i16::from(digit < 0);
+        if digit < 0 {
+            digit += 256;
+        }
+        reduced[i] = digit as u8;
+    }
+    scalar(&reduced).expect("below the order after one subtraction")
+}
+
+fn scalar_bytes(value: Option<SecretKey>) -> [u8; 32] {
+    value.map_or([0; 32], |value| value.secret_bytes())
+}
+
+fn add(a: Option<SecretKey>, b: Option<SecretKey>) -> Option<SecretKey> {
+    match (a, b) {
+        (None, x) | (x, None) => x,
+        // Fails only when the sum is zero
+        (Some(a), Some(b)) => a.add_tweak(&Scalar::from(b)).ok(),
+    }
+}
+
+fn mul(a: Option<SecretKey>, b: Option<SecretKey>) -> Option<SecretKey> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(
+            a.mul_tweak(&Scalar::from(b))
+                .expect("n is prime, so non-zero scalars have a non-zero product"),
+        ),
+        _ => None,
+    }
+}
+
+fn negate(a: Option<SecretKey>) -> Option<SecretKey> {
+    a.map(SecretKey::negate)
+}
+
+fn point_add(a: Option<PublicKey>, b: Option<PublicKey>) -> Option<PublicKey> {
+    match (a, b) {
+        (None, x) | (x, None) => x,
+        // Fails only when the sum is the point at infinity
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+    }
+}
+
+fn point_mul<C: Verification>(
+    secp: &Secp256k1<C>,
+    point: Option<PublicKey>,
+    by: Option<SecretKey>,
+) -> Option<PublicKey> {
+    match (point, by) {
+        (Some(point), Some(by)) => Some(
+            point
+                .mul_tweak(secp, &Scalar::from(by))
+                .expect("non-zero multiple of a point of prime order"),
+        ),
+        _ => None,
+    }
+}
+
+fn has_even_y(point: &PublicKey) -> bool {
+    point.serialize()[0] == 0x02
+}
+
+fn xbytes(point: &PublicKey) -> [u8; 32] {
+    point.x_only_public_key().0.serialize()
+}
+
+/// 1 if `point` has an even Y coordinate, -1 otherwise.
+fn parity_factor(point: &PublicKey) -> Option<SecretKey> {
+    Some(if has_even_y(point) {
+        ONE_KEY
+    } else {
+        ONE_KEY.negate()
+    })
+}
+
+/// BIP340 tagged hash: `sha256(sha256(tag) || sha256(tag) || parts...)`.
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for part in parts {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Sorts keys by their compressed encoding (KeySort), so every signer
+/// aggregates them in the same order.
+pub fn sort_keys(keys: &mut [PublicKey]) {
+    keys.sort_by_key(|key| key.serialize());
+}
+
+/// The aggregate of the signers' keys, with any tweaks applied to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAggContext {
+    keys: Vec<PublicKey>,
+    /// Hash of the whole key list, committed to by every coefficient.
+    list_hash: [u8; 32],
+    /// The first key differing from the first one, whose coefficient is 1.
+    second_key: Option<PublicKey>,
+    q: PublicKey,
+    gacc: Option<SecretKey>,
+    tacc: Option<SecretKey>,
+}
+
+impl KeyAggContext {
+    /// Aggregates `keys` in the order given (KeyAgg).
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        keys: &[PublicKey],
+    ) -> Result<Self, MusigError> {
+        let first = keys.first().ok_or(MusigError::NoKeys)?;
+        let serialized: Vec<[u8; 33]> = keys.iter().map(PublicKey::serialize).collect();
+        let parts: Vec<&[u8]> = serialized.iter().map(|key| &key[..]).collect();
+        let list_hash = tagged_hash("KeyAgg list", &parts);
+        let second_key = keys.iter().find(|&key| key != first).copied();
+
+        let mut context = KeyAggContext {
+            keys: keys.to_vec(),
+            list_hash,
+            second_key,
+            q: generator(),
+            gacc: Some(ONE_KEY),
+            tacc: None,
+        };
+        let mut q = None;
+        for key in keys {
+            q = point_add(q, point_mul(secp, Some(*key), context.coefficient(key)));
+        }
+        context.q = q.ok_or(MusigError::InfiniteKey)?;
+        Ok(context)
+    }
+
+    /// The coefficient `a_i` of `key` (KeyAggCoeff).
+    fn coefficient(&self, key: &PublicKey) -> Option<SecretKey> {
+        if Some(*key) == self.second_key {
+            return Some(ONE_KEY);
+        }
+        reduce(tagged_hash(
+            "KeyAgg coefficient",
+            &[&self.list_hash, &key.serialize()],
+        ))
+    }
+
+    /// Adds `tweak` times G to the aggregate key, after negating it if
+    /// `x_only` and its Y is odd (ApplyTweak).
+    pub fn tweak<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        tweak: &[u8; 32],
+        x_only: bool,
+    ) -> Result<(), MusigError> {
+        let g = if x_only {
+            parity_factor(&self.q)
+        } else {
+            Some(ONE_KEY)
+        };
+        let t = scalar(tweak).map_err(|_| MusigError::InvalidTweak)?;
+        let q = point_add(
+            point_mul(secp, Some(self.q), g),
+            point_mul(secp, Some(generator()), t),
+        );
+        self.q = q.ok_or(MusigError::InfiniteKey)?;
+        self.gacc = mul(g, self.gacc);
+        self.tacc = add(t, mul(g, self.tacc));
+        Ok(())
+    }
+
+    /// Applies the BIP341 tweak of a key-path-only output, committing to no
+    /// script tree, so the aggregate key becomes the P2TR output key.
+    pub fn tweak_for_taproot<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), MusigError> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.x_only(), None).into_inner();
+        self.tweak(secp, &tweak, true)
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        self.q.x_only_public_key().0
+    }
+
+    /// The P2TR address paying to the aggregate key as it stands, which
+    /// must already be tweaked with `tweak_for_taproot`.
+    pub fn address(&self, network: Network) -> Address {
+        let output_key =
+            bitcoin::util::schnorr::TweakedPublicKey::dangerous_assume_tweaked(self.x_only());
+        Address::p2tr_tweaked(output_key, network)
+    }
+}
+
+/// A signer's secret nonce for one signing session. Using one twice leaks
+/// the signing key, so `Session::sign` consumes it.
+pub struct SecNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+    key: PublicKey,
+}
+
+impl SecNonce {
+    /// Serializes the nonce so it can wait on disk between the two rounds.
+    pub fn serialize(&self) -> Zeroizing<[u8; SEC_NONCE_SIZE]> {
+        let mut bytes = Zeroizing::new([0u8; SEC_NONCE_SIZE]);
+        bytes[..32].copy_from_slice(&self.k1.secret_bytes());
+        bytes[32..64].copy_from_slice(&self.k2.secret_bytes());
+        bytes[64..].copy_from_slice(&self.key.serialize());
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        if bytes.len() != SEC_NONCE_SIZE {
+            return Err(MusigError::InvalidSecNonce);
+        }
+        let k = |range: std::ops::Range<usize>| {
+            SecretKey::from_slice(&bytes[range]).map_err(|_| MusigError::InvalidSecNonce)
+        };
+        Ok(SecNonce {
+            k1: k(0..32)?,
+            k2: k(32..64)?,
+            key: PublicKey::from_slice(&bytes[64..]).map_err(|_| MusigError::InvalidSecNonce)?,
+        })
+    }
+
+    /// The public nonce sent to the other signers in the first round.
+    pub fn public<C: Verification>(&self, secp: &Secp256k1<C>) -> PubNonce {
+        let g = Some(generator());
+        PubNonce {
+            r1: point_mul(secp, g, Some(self.k1)).expect("k1 is not zero"),
+            r2: point_mul(secp, g, Some(self.k2)).expect("k2 is not zero"),
+        }
+    }
+}
+
+impl Drop for SecNonce {
+    fn drop(&mut self) {
+        keys::erase(&mut self.k1, ONE_KEY);
+        keys::erase(&mut self.k2, ONE_KEY);
+    }
+}
+
+/// A signer's public nonce, sent in the first round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PubNonce {
+    r1: PublicKey,
+    r2: PublicKey,
+}
+
+impl PubNonce {
+    pub fn serialize(&self) -> [u8; PUB_NONCE_SIZE] {
+        let mut bytes = [0u8; PUB_NONCE_SIZE];
+        bytes[..33].copy_from_slice(&self.r1.serialize());
+        bytes[33..].copy_from_slice(&self.r2.serialize());
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        let invalid = || MusigError::Encoding(hex::encode(bytes));
+        if bytes.len() != PUB_NONCE_SIZE {
+            return Err(invalid());
+        }
+        Ok(PubNonce {
+            r1: PublicKey::from_slice(&bytes[..33]).map_err(|_| invalid())?,
+            r2: PublicKey::from_slice(&bytes[33..]).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for PubNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialize()))
+    }
+}
+
+impl FromStr for PubNonce {
+    type Err = MusigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim()).map_err(|_| MusigError::Encoding(s.to_owned()))?;
+        PubNonce::from_slice(&bytes)
+    }
+}
+
+/// Draws a fresh nonce pair for the signer of `key`. Whatever of the
+/// secret key, aggregate key, message and extra input is known is mixed in
+/// as well, so a broken random source alone does not repeat nonces
+/// (NonceGen).
+pub fn nonce_gen(
+    secret_key: Option<&SecretKey>,
+    key: &PublicKey,
+    aggregate_key: Option<&XOnlyPublicKey>,
+    msg: Option<&[u8]>,
+    extra_in: Option<&[u8]>,
+) -> SecNonce {
+    loop {
+        let mut rand = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut rand[..]);
+        if let Some(nonce) =
+            nonce_gen_with_rand(&rand, secret_key, key, aggregate_key, msg, extra_in)
+        {
+            return nonce;
+        }
+    }
+}
+
+/// `nonce_gen` with the random bytes given, for the test vectors. `None` in
+/// the negligible case of a zero nonce.
+pub fn nonce_gen_with_rand(
+    rand: &[u8; 32],
+    secret_key: Option<&SecretKey>,
+    key: &PublicKey,
+    aggregate_key: Option<&XOnlyPublicKey>,
+    msg: Option<&[u8]>,
+    extra_in: Option<&[u8]>,
+) -> Option<SecNonce> {
+    let mut rand = Zeroizing::new(*rand);
+    if let Some(secret_key) = secret_key {
+        let aux = tagged_hash("MuSig/aux", &[&rand[..]]);
+        for (byte, (sk, aux)) in rand
+            .iter_mut()
+            .zip(secret_key.secret_bytes().iter().zip(aux))
+        {
+            *byte = sk ^ aux;
+        }
+    }
+    let key_bytes = key.serialize();
+    let aggregate_key = aggregate_key.map(XOnlyPublicKey::serialize);
+    let aggregate_key: &[u8] = aggregate_key.as_ref().map_or(&[], |key| &key[..]);
+    let msg_prefixed = match msg {
+        None => vec![0],
+        Some(msg) => {
+            let mut prefixed = vec![1];
+            prefixed.extend((msg.len() as u64).to_be_bytes());
+            prefixed.extend(msg);
+            prefixed
+        }
+    };
+    let extra_in = extra_in.unwrap_or(&[]);
+
+    let k = |i: u8| {
+        reduce(tagged_hash(
+            "MuSig/nonce",
+            &[
+                &rand[..],
+                &[key_bytes.len() as u8],
+                &key_bytes,
+                &[aggregate_key.len() as u8],
+                aggregate_key,
+                &msg_prefixed,
+                &(extra_in.len() as u32).to_be_bytes(),
+                extra_in,
+                &[i],
+            ],
+        ))
+    };
+    Some(SecNonce {
+        k1: k(0)?,
+        k2: k(1)?,
+        key: *key,
+    })
+}
+
+/// The sum of every signer's public nonce, either half of which may be the
+/// point at infinity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggNonce {
+    r1: Option<PublicKey>,
+    r2: Option<PublicKey>,
+}
+
+impl AggNonce {
+    /// Sums the public nonces (NonceAgg).
+    pub fn new(nonces: &[PubNonce]) -> AggNonce {
+        let (mut r1, mut r2) = (None, None);
+        for nonce in nonces {
+            r1 = point_add(r1, Some(nonce.r1));
+            r2 = point_add(r2, Some(nonce.r2));
+        }
+        AggNonce { r1, r2 }
+    }
+
+    pub fn serialize(&self) -> [u8; PUB_NONCE_SIZE] {
+        let mut bytes = [0u8; PUB_NONCE_SIZE];
+        if let Some(r1) = self.r1 {
+            bytes[..33].copy_from_slice(&r1.serialize());
+        }
+        if let Some(r2) = self.r2 {
+            bytes[33..].copy_from_slice(&r2.serialize());
+        }
+        bytes
+    }
+}
+
+impl fmt::Display for AggNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialize()))
+    }
+}
+
+/// A signer's share of the final signature, sent in the second round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSig(Option<SecretKey>);
+
+impl PartialSig {
+    pub fn serialize(&self) -> [u8; 32] {
+        scalar_bytes(self.0)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        let bytes: &[u8; 32] = bytes
+            .try_into()
+            .map_err(|_| MusigError::Encoding(hex::encode(bytes)))?;
+        scalar(bytes)
+            .map(PartialSig)
+            .map_err(|_| MusigError::InvalidPartialSig)
+    }
+}
+
+impl fmt::Display for PartialSig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialize()))
+    }
+}
+
+impl FromStr for PartialSig {
+    type Err = MusigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim()).map_err(|_| MusigError::Encoding(s.to_owned()))?;
+        PartialSig::from_slice(&bytes)
+    }
+}
+
+/// What every signer derives from the aggregate key, the aggregate nonce
+/// and the message before the second round (GetSessionValues).
+pub struct Session<'a> {
+    key_agg: &'a KeyAggContext,
+    /// Nonce coefficient.
+    b: Option<SecretKey>,
+    /// Final nonce point.
+    r: PublicKey,
+    /// BIP340 challenge.
+    e: Option<SecretKey>,
+}
+
+impl<'a> Session<'a> {
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        key_agg: &'a KeyAggContext,
+        aggnonce: &AggNonce,
+        msg: &[u8],
+    ) -> Self {
+        let q = xbytes(&key_agg.q);
+        let b = reduce(tagged_hash(
+            "MuSig/noncecoef",
+            &[&aggnonce.serialize(), &q, msg],
+        ));
+        let r = point_add(aggnonce.r1, point_mul(secp, aggnonce.r2, b)).unwrap_or_else(generator);
+        let e = reduce(tagged_hash("BIP0340/challenge", &[&xbytes(&r), &q, msg]));
+        Session { key_agg, b, r, e }
+    }
+
+    fn coefficient(&self, key: &PublicKey) -> Result<Option<SecretKey>, MusigError> {
+        if !self.key_agg.keys.contains(key) {
+            return Err(MusigError::UnknownKey(*key));
+        }
+        Ok(self.key_agg.coefficient(key))
+    }
+
+    /// `g * gacc`: the sign the signers' keys carry into the final key.
+    fn key_sign(&self) -> Option<SecretKey> {
+        mul(parity_factor(&self.key_agg.q), self.key_agg.gacc)
+    }
+
+    /// Signs with `secret_key` and the nonce drawn for it, then checks the
+    /// result as the other signers will (Sign).
+    pub fn sign<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        secnonce: SecNonce,
+        secret_key: &SecretKey,
+    ) -> Result<PartialSig, MusigError> {
+        let key = point_mul(secp, Some(generator()), Some(*secret_key)).expect("key is not zero");
+        if secnonce.key != key {
+            return Err(MusigError::InvalidSecNonce);
+        }
+        let a = self.coefficient(&key)?;
+        let (mut k1, mut k2) = (Some(secnonce.k1), Some(secnonce.k2));
+        if !has_even_y(&self.r) {
+            k1 = negate(k1);
+            k2 = negate(k2);
+        }
+        let d = mul(self.key_sign(), Some(*secret_key));
+        let s = add(add(k1, mul(self.b, k2)), mul(mul(self.e, a), d));
+        keys::erase(&mut k1, None);
+        keys::erase(&mut k2, None);
+        let psig = PartialSig(s);
+        self.verify(secp, &psig, &secnonce.public(secp), &key)?;
+        Ok(psig)
+    }
+
+    /// Checks the partial signature of the signer of `key`, who sent
+    /// `pubnonce` in the first round (PartialSigVerifyInternal).
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        psig: &PartialSig,
+        pubnonce: &PubNonce,
+        key: &PublicKey,
+    ) -> Result<(), MusigError> {
+        let a = self.coefficient(key)?;
+        let mut r = point_add(
+            Some(pubnonce.r1),
+            point_mul(secp, Some(pubnonce.r2), self.b),
+        );
+        if !has_even_y(&self.r) {
+            r = r.map(|r| r.negate(secp));
+        }
+        let lhs = point_mul(secp, Some(generator()), psig.0);
+        let challenge = mul(mul(self.e, a), self.key_sign());
+        let rhs = point_add(r, point_mul(secp, Some(*key), challenge));
+        if lhs == rhs {
+            Ok(())
+        } else {
+            Err(MusigError::BadPartialSig(*key))
+        }
+    }
+
+    /// Sums the partial signatures into a BIP340 signature for the
+    /// aggregate key (PartialSigAgg). Check each with `verify` first, since
+    /// a bad one only shows up here as an invalid final signature.
+    pub fn aggregate(&self, psigs: &[PartialSig]) -> schnorr::Signature {
+        let mut s = mul(
+            mul(self.e, parity_factor(&self.key_agg.q)),
+            self.key_agg.tacc,
+        );
+        for psig in psigs {
+            s = add(s, psig.0);
+        }
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&xbytes(&self.r));
+        bytes[32..].copy_from_slice(&scalar_bytes(s));
+        schnorr::Signature::from_slice(&bytes).expect("64 bytes")
+    }
+}
+
+/// What a signer sends in either round: their key and one nonce or partial
+/// signature per input, as `pubkey:hex,hex...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<T> {
+    pub key: PublicKey,
+    pub items: Vec<T>,
+}
+
+/// First round: the public nonces.
+pub type NonceMessage = Message<PubNonce>;
+/// Second round: the partial signatures.
+pub type SignatureMessage = Message<PartialSig>;
+
+impl<T: fmt::Display> fmt::Display for Message<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.key)?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: FromStr<Err = MusigError>> FromStr for Message<T> {
+    type Err = MusigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MusigError::Encoding(s.to_owned());
+        let (key, items) = s.trim().split_once(':').ok_or_else(invalid)?;
+        Ok(Message {
+            key: PublicKey::from_str(key).map_err(|_| invalid())?,
+            items: items
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+/// The key-path witness: the aggregate signature alone, the same size as
+/// a single signer's.
+pub fn key_path_witness(signature: &schnorr::Signature) -> Witness {
+    let mut witness = Witness::new();
+    witness.push(signature.as_ref());
+    witness
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{KeyPair, Message as SecpMessage};
+
+    fn key(s: &str) -> PublicKey {
+        PublicKey::from_str(s).unwrap()
+    }
+
+    fn bytes32(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    // BIP327 key_agg_vectors.json
+    const KEY_AGG_PUBKEYS: [&str; 3] = [
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+        "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+    ];
+
+    #[test]
+    fn key_agg_vectors() {
+        let secp = Secp256k1::verification_only();
+        let cases: [(&[usize], &str); 4] = [
+            (
+                &[0, 1, 2],
+                "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+            ),
+            (
+                &[2, 1, 0],
+                "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+            ),
+            (
+                &[0, 0, 0],
+                "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+            ),
+            (
+                &[0, 0, 1, 1],
+                "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+            ),
+        ];
+        for (indices, expected) in cases {
+            let keys: Vec<PublicKey> = indices.iter().map(|&i| key(KEY_AGG_PUBKEYS[i])).collect();
+            let context = KeyAggContext::new(&secp, &keys).unwrap();
+            assert_eq!(context.x_only().serialize(), bytes32(expected));
+        }
+    }
+
+    #[test]
+    fn key_agg_errors() {
+        let secp = Secp256k1::verification_only();
+        let keys: Vec<PublicKey> = KEY_AGG_PUBKEYS.iter().map(|s| key(s)).collect();
+        let mut context = KeyAggContext::new(&secp, &keys).unwrap();
+        // The group order itself is not a valid tweak
+        assert_eq!(
+            context.tweak(&secp, &CURVE_ORDER, true),
+            Err(MusigError::InvalidTweak)
+        );
+        assert_eq!(KeyAggContext::new(&secp, &[]), Err(MusigError::NoKeys));
+    }
+
+    #[test]
+    fn nonce_agg_vectors() {
+        // BIP327 nonce_agg_vectors.json
+        let pnonces: Vec<PubNonce> = [
+            "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E66603BA47FBC1834437B3212E89A84D8425E7BF12E0245D98262268EBDCB385D50641",
+            "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833",
+            "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E6660279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60379BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        assert_eq!(
+            AggNonce::new(&pnonces[..2]).to_string().to_uppercase(),
+            "035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B024725377345BDE0E9C33AF3C43C0A29A9249F2F2956FA8CFEB55C8573D0262DC8"
+        );
+        // G + -G: the second half is the point at infinity
+        let infinite = AggNonce::new(&pnonces[2..]);
+        assert_eq!(
+            infinite.to_string().to_uppercase(),
+            format!(
+                "035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B{}",
+                "00".repeat(33)
+            )
+        );
+        // Not on the curve
+        assert!(PubNonce::from_str(&format!("0251{}", "00".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn nonce_gen_binds_every_input() {
+        let secp = Secp256k1::verification_only();
+        let secret_key = SecretKey::from_slice(&[0x02; 32]).unwrap();
+        let key = key("024D4B6CD1361032CA9BD2AEB9D900AA4D45D9EAD80AC9423374C451A7254D0766");
+        let aggregate_key = XOnlyPublicKey::from_slice(&[0x07; 32]).unwrap();
+        let gen = |rand: &[u8; 32], msg: Option<&[u8]>, extra_in: Option<&[u8]>| {
+            nonce_gen_with_rand(
+                rand,
+                Some(&secret_key),
+                &key,
+                Some(&aggregate_key),
+                msg,
+                extra_in,
+            )
+            .unwrap()
+            .public(&secp)
+        };
+        let nonce = gen(&[0; 32], Some(&[0x01; 32]), Some(&[0x08; 32]));
+        assert_eq!(nonce, gen(&[0; 32], Some(&[0x01; 32]), Some(&[0x08; 32])));
+        assert_ne!(nonce, gen(&[1; 32], Some(&[0x01; 32]), Some(&[0x08; 32])));
+        assert_ne!(nonce, gen(&[0; 32], Some(&[0x02; 32]), Some(&[0x08; 32])));
+        // An empty message differs from none at all
+        assert_ne!(gen(&[0; 32], Some(&[]), None), gen(&[0; 32], None, None));
+        assert_ne!(nonce, gen(&[0; 32], Some(&[0x01; 32]), None));
+
+        let secnonce = nonce_gen_with_rand(&[0; 32], None, &key, None, None, None).unwrap();
+        assert_eq!(&secnonce.serialize()[64..], &key.serialize()[..]);
+        let restored = SecNonce::from_slice(&secnonce.serialize()[..]).unwrap();
+        assert_eq!(restored.public(&secp), secnonce.public(&secp));
+    }
+
+    // BIP327 sign_verify_vectors.json
+    const SIGN_SECRET_KEY: &str =
+        "7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671";
+    const SIGN_PUBKEYS: [&str; 3] = [
+        "03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9",
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661",
+    ];
+    const SIGN_SECNONCE: &str = "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F703935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9";
+    const SIGN_PNONCES: [&str; 3] = [
+        "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+        "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+    ];
+    const SIGN_MSG: &str = "F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF";
+
+    #[test]
+    fn sign_verify_vectors() {
+        let secp = Secp256k1::verification_only();
+        let secret_key = SecretKey::from_str(SIGN_SECRET_KEY).unwrap();
+        let keys: Vec<PublicKey> = SIGN_PUBKEYS.iter().map(|s| key(s)).collect();
+        let pnonces: Vec<PubNonce> = SIGN_PNONCES.iter().map(|s| s.parse().unwrap()).collect();
+        let msg = hex::decode(SIGN_MSG).unwrap();
+        let secnonce = || SecNonce::from_slice(&hex::decode(SIGN_SECNONCE).unwrap()).unwrap();
+        assert_eq!(secnonce().public(&secp), pnonces[0]);
+
+        let key_agg = KeyAggContext::new(&secp, &keys).unwrap();
+        let aggnonce = AggNonce::new(&pnonces);
+        let session = Session::new(&secp, &key_agg, &aggnonce, &msg);
+        let psig = session.sign(&secp, secnonce(), &secret_key).unwrap();
+        assert_eq!(
+            psig.to_string().to_uppercase(),
+            "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB"
+        );
+
+        // The same signature checked against the wrong signer, negated, and
+        // at the group order all fail
+        assert_eq!(
+            session.verify(&secp, &psig, &pnonces[1], &keys[1]),
+            Err(MusigError::BadPartialSig(keys[1]))
+        );
+        let negated = PartialSig(negate(psig.0));
+        assert!(session
+            .verify(&secp, &negated, &pnonces[0], &keys[0])
+            .is_err());
+        assert_eq!(
+            PartialSig::from_slice(&CURVE_ORDER),
+            Err(MusigError::InvalidPartialSig)
+        );
+
+        // A secret nonce only signs for its own key
+        let other = SecretKey::from_slice(&[0x01; 32]).unwrap();
+        assert_eq!(
+            session.sign(&secp, secnonce(), &other).err(),
+            Some(MusigError::InvalidSecNonce)
+        );
+        // A signer outside the aggregate cannot sign
+        let outside = KeyAggContext::new(&secp, &keys[1..]).unwrap();
+        let session = Session::new(&secp, &outside, &aggnonce, &msg);
+        assert_eq!(
+            session.sign(&secp, secnonce(), &secret_key).err(),
+            Some(MusigError::UnknownKey(keys[0]))
+        );
+    }
+
+    #[test]
+    fn sign_with_infinite_aggnonce() {
+        let secp = Secp256k1::verification_only();
+        let secret_key = SecretKey::from_str(SIGN_SECRET_KEY).unwrap();
+        let keys: Vec<PublicKey> = SIGN_PUBKEYS[..2].iter().map(|s| key(s)).collect();
+        let pnonce: PubNonce = SIGN_PNONCES[0].parse().unwrap();
+        // The negation of the signer's nonce cancels it entirely
+        let cancelling = PubNonce {
+            r1: pnonce.r1.negate(&secp),
+            r2: pnonce.r2.negate(&secp),
+        };
+        let aggnonce = AggNonce::new(&[pnonce, cancelling]);
+        assert_eq!(aggnonce.serialize(), [0; PUB_NONCE_SIZE]);
+
+        let key_agg = KeyAggContext::new(&secp, &keys).unwrap();
+        let msg = hex::decode(SIGN_MSG).unwrap();
+        let session = Session::new(&secp, &key_agg, &aggnonce, &msg);
+        let secnonce = SecNonce::from_slice(&hex::decode(SIGN_SECNONCE).unwrap()).unwrap();
+        let psig = session.sign(&secp, secnonce, &secret_key).unwrap();
+        assert_eq!(
+            psig.to_string().to_uppercase(),
+            "AE386064B26105404798F75DE2EB9AF5EDA5387B064B83D049CB7C5E08879531"
+        );
+    }
+
+    #[test]
+    fn key_path_spend_verifies() {
+        let secp = Secp256k1::new();
+        let secret_keys = [
+            SecretKey::from_slice(&[0x11; 32]).unwrap(),
+            SecretKey::from_slice(&[0x22; 32]).unwrap(),
+        ];
+        let mut keys: Vec<PublicKey> = secret_keys
+            .iter()
+            .map(|secret_key| PublicKey::from_secret_key(&secp, secret_key))
+            .collect();
+        sort_keys(&mut keys);
+        let mut key_agg = KeyAggContext::new(&secp, &keys).unwrap();
+        let internal_key = key_agg.x_only();
+        key_agg.tweak_for_taproot(&secp).unwrap();
+        assert_eq!(
+            key_agg.address(Network::Regtest),
+            Address::p2tr(&secp, internal_key, None, Network::Regtest)
+        );
+
+        let msg = [0x42; 32];
+        let secnonces: Vec<SecNonce> = secret_keys
+            .iter()
+            .map(|secret_key| {
+                let key = PublicKey::from_secret_key(&secp, secret_key);
+                nonce_gen(
+                    Some(secret_key),
+                    &key,
+                    Some(&key_agg.x_only()),
+                    Some(&msg),
+                    None,
+                )
+            })
+            .collect();
+        let round1: Vec<NonceMessage> = secnonces
+            .iter()
+            .map(|secnonce| Message {
+                key: secnonce.key,
+                items: vec![secnonce.public(&secp)],
+            })
+            .collect();
+        let round1: Vec<NonceMessage> = round1
+            .iter()
+            .map(|message| message.to_string().parse().unwrap())
+            .collect();
+        let pnonces: Vec<PubNonce> = round1.iter().map(|message| message.items[0]).collect();
+
+        let session = Session::new(&secp, &key_agg, &AggNonce::new(&pnonces), &msg);
+        let psigs: Vec<PartialSig> = secnonces
+            .into_iter()
+            .zip(&secret_keys)
+            .map(|(secnonce, secret_key)| session.sign(&secp, secnonce, secret_key).unwrap())
+            .collect();
+        for (i, psig) in psigs.iter().enumerate() {
+            session
+                .verify(&secp, psig, &pnonces[i], &round1[i].key)
+                .unwrap();
+        }
+        let signature = session.aggregate(&psigs);
+        secp.verify_schnorr(
+            &signature,
+            &SecpMessage::from_slice(&msg).unwrap(),
+            &key_agg.x_only(),
+        )
+        .unwrap();
+        assert_eq!(key_path_witness(&signature).len(), 1);
+        assert_eq!(
+            bitcoin::consensus::serialize(&key_path_witness(&signature)).len(),
+            KEY_PATH_WITNESS_SIZE
+        );
+
+        // Against a single signer's key-path spend, the size is the same
+        let keypair = KeyPair::from_secret_key(&secp, &secret_keys[0]);
+        let single =
+            secp.sign_schnorr_no_aux_rand(&SecpMessage::from_slice(&msg).unwrap(), &keypair);
+        assert_eq!(single.as_ref().len(), signature.as_ref().len());
+    }
+}
@@ -0,0 +1,714 @@
+use crate::multisig::MAX_STANDARD_P2WSH_SCRIPT_SIZE;
+use crate::prevout::Prevout;
+use bitcoin::{
+    blockdata::{
+        opcodes::{self, all::*},
+        script::{Instruction, Script},
+        transaction::{Transaction, TxOut},
+    },
+    consensus::encode::serialize,
+};
+use std::fmt;
+
+// Bitcoin Core v30.0's relay defaults, from policy/policy.h. Fee rates are
+// in sat/kvB, as Core keeps them.
+
+/// Largest transaction weight relayed.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// Largest total sigop cost relayed, a fifth of the block limit.
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+/// Most sigops a legacy P2SH redeem script may count.
+pub const MAX_P2SH_SIGOPS: usize = 15;
+/// Most legacy sigops the inputs may execute: those of each scriptSig and
+/// the output it spends, or its redeem script.
+pub const MAX_TX_LEGACY_SIGOPS: usize = 2500;
+/// Most witness stack items below a P2WSH witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+/// Largest witness stack item below a P2WSH witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+/// Largest scriptSig relayed, enough for a 15-of-15 P2SH multisig.
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// `-datacarriersize`: most OP_RETURN output script bytes relayed, summed
+/// over every such output.
+pub const MAX_DATACARRIER_BYTES: usize = 100_000;
+/// Most keys in a bare multisig output, which `-permitbaremultisig=0` keeps
+/// from being relayed at all.
+pub const MAX_BARE_MULTISIG_KEYS: usize = 3;
+/// `-dustrelayfee`.
+pub const DUST_RELAY_FEE: u64 = 3000;
+/// `-minrelaytxfee`.
+pub const MIN_RELAY_FEE: u64 = 100;
+/// `-bytespersigop`: each sigop counts as at least this many vbytes.
+pub const BYTES_PER_SIGOP: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    /// Not exactly one prevout per input.
+    Prevouts { have: usize, inputs: usize },
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Prevouts { have, inputs } => {
+                write!(f, "{} prevouts given for {} inputs", have, inputs)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Something that keeps a transaction out of Core's mempool, though it
+/// may still be valid in a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    Weight(usize),
+    SigopCost(usize),
+    LegacySigops(usize),
+    /// The fee, with the sigop-adjusted size, is below the minimum relay fee.
+    MinRelayFee {
+        fee: u64,
+        required: u64,
+    },
+    Dust {
+        output: usize,
+        value: u64,
+        threshold: u64,
+    },
+    NonStandardOutput(usize),
+    /// A bare multisig output, not relayed by default.
+    BareMultisig(usize),
+    /// The OP_RETURN output scripts total this many bytes.
+    DataCarrierSize(usize),
+    NonStandardPrevout(usize),
+    ScriptSigSize {
+        input: usize,
+        size: usize,
+    },
+    ScriptSigNotPushOnly(usize),
+    P2shSigops {
+        input: usize,
+        count: usize,
+    },
+    WitnessScriptSize {
+        input: usize,
+        size: usize,
+    },
+    WitnessStackItems {
+        input: usize,
+        count: usize,
+    },
+    WitnessStackItemSize {
+        input: usize,
+        item: usize,
+        size: usize,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Weight(weight) => write!(
+                f,
+                "weight {} WU is over the {} WU standard limit",
+                weight, MAX_STANDARD_TX_WEIGHT
+            ),
+            Violation::SigopCost(cost) => write!(
+                f,
+                "sigop cost {} is over the {} standard limit",
+                cost, MAX_STANDARD_TX_SIGOPS_COST
+            ),
+            Violation::LegacySigops(count) => write!(
+                f,
+                "{} legacy sigops is over the {} standard limit",
+                count, MAX_TX_LEGACY_SIGOPS
+            ),
+            Violation::MinRelayFee { fee, required } => write!(
+                f,
+                "fee {} sat is below the {} sat minimum relay fee",
+                fee, required
+            ),
+            Violation::Dust {
+                output,
+                value,
+                threshold,
+            } => write!(
+                f,
+                "output {} of {} sat is dust, below {} sat",
+                output, value, threshold
+            ),
+            Violation::NonStandardOutput(output) => {
+                write!(f, "output {} has a non-standard script", output)
+            }
+            Violation::BareMultisig(output) => write!(
+                f,
+                "output {} is bare multisig, which is not relayed by default",
+                output
+            ),
+            Violation::DataCarrierSize(size) => write!(
+                f,
+                "OP_RETURN outputs total {} bytes, over the {}-byte limit",
+                size, MAX_DATACARRIER_BYTES
+            ),
+            Violation::NonStandardPrevout(input) => {
+                write!(f, "input {} spends a non-standard script", input)
+            }
+            Violation::ScriptSigSize { input, size } => write!(
+                f,
+                "input {} scriptSig is {} bytes, over the {}-byte limit",
+                input, size, MAX_STANDARD_SCRIPTSIG_SIZE
+            ),
+            Violation::ScriptSigNotPushOnly(input) => {
+                write!(f, "input {} scriptSig is not push-only", input)
+            }
+            Violation::P2shSigops { input, count } => write!(
+                f,
+                "input {} redeem script has {} sigops, over the {} limit",
+                input, count, MAX_P2SH_SIGOPS
+            ),
+            Violation::WitnessScriptSize { input, size } => write!(
+                f,
+                "input {} witness script is {} bytes, over the {}-byte limit",
+                input, size, MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            ),
+            Violation::WitnessStackItems { input, count } => write!(
+                f,
+                "input {} has {} witness stack items, over the {} limit",
+                input, count, MAX_STANDARD_P2WSH_STACK_ITEMS
+            ),
+            Violation::WitnessStackItemSize { input, item, size } => write!(
+                f,
+                "input {} witness item {} is {} bytes, over the {}-byte limit",
+                input, item, size, MAX_STANDARD_P2WSH_STACK_ITEM_SIZE
+            ),
+        }
+    }
+}
+
+/// Checks `tx` against Core's relay policy and returns every violation.
+/// `prevouts` must be in input order, one for each input.
+pub fn check(tx: &Transaction, prevouts: &[Prevout]) -> Result<Vec<Violation>, PolicyError> {
+    if prevouts.len() != tx.input.len() {
+        return Err(PolicyError::Prevouts {
+            have: prevouts.len(),
+            inputs: tx.input.len(),
+        });
+    }
+    let mut violations = Vec::new();
+
+    let weight = tx.weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        violations.push(Violation::Weight(weight));
+    }
+
+    let mut data_carrier = 0;
+    for (output, txout) in tx.output.iter().enumerate() {
+        let script = &txout.script_pubkey;
+        if !is_standard_script(script) {
+            violations.push(Violation::NonStandardOutput(output));
+            continue;
+        }
+        if is_bare_multisig(script) {
+            violations.push(Violation::BareMultisig(output));
+        }
+        if script.is_op_return() {
+            data_carrier += script.len();
+            continue;
+        }
+        let threshold = dust_threshold(txout);
+        if txout.value < threshold {
+            violations.push(Violation::Dust {
+                output,
+                value: txout.value,
+                threshold,
+            });
+        }
+    }
+    if data_carrier > MAX_DATACARRIER_BYTES {
+        violations.push(Violation::DataCarrierSize(data_carrier));
+    }
+
+    for (input, (txin, prevout)) in tx.input.iter().zip(prevouts).enumerate() {
+        let script_sig = &txin.script_sig;
+        if script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            violations.push(Violation::ScriptSigSize {
+                input,
+                size: script_sig.len(),
+            });
+        }
+        if !is_push_only(script_sig) {
+            violations.push(Violation::ScriptSigNotPushOnly(input));
+        }
+
+        let spent = &prevout.script_pubkey;
+        // Spends of future witness versions are left non-standard so
+        // that soft forks can give them meaning
+        if !is_standard_script(spent) || spent.is_op_return() || is_unknown_witness(spent) {
+            violations.push(Violation::NonStandardPrevout(input));
+            continue;
+        }
+        let mut program = spent.clone();
+        if spent.is_p2sh() {
+            let redeem_script = match last_push(script_sig) {
+                Some(redeem_script) => redeem_script,
+                None => continue,
+            };
+            let count = sigops(&redeem_script, true);
+            if count > MAX_P2SH_SIGOPS {
+                violations.push(Violation::P2shSigops { input, count });
+            }
+            program = redeem_script;
+        }
+        if program.is_v0_p2wsh() {
+            violations.extend(check_p2wsh_witness(input, &txin.witness.to_vec()));
+        }
+    }
+
+    let sigop_cost = sigop_cost(tx, prevouts);
+    if sigop_cost > MAX_STANDARD_TX_SIGOPS_COST {
+        violations.push(Violation::SigopCost(sigop_cost));
+    }
+    let legacy_sigops = legacy_sigops(tx, prevouts);
+    if legacy_sigops > MAX_TX_LEGACY_SIGOPS {
+        violations.push(Violation::LegacySigops(legacy_sigops));
+    }
+
+    let spent: u64 = prevouts.iter().map(|prevout| prevout.amount.to_sat()).sum();
+    let paid: u64 = tx.output.iter().map(|txout| txout.value).sum();
+    let fee = spent.saturating_sub(paid);
+    let vsize = weight.max(sigop_cost * BYTES_PER_SIGOP).div_ceil(4);
+    let required = vsize as u64 * MIN_RELAY_FEE / 1000;
+    if fee < required {
+        violations.push(Violation::MinRelayFee { fee, required });
+    }
+
+    Ok(violations)
+}
+
+/// The smallest value `txout` may carry: what spending it would cost at
+/// the dust relay fee, with a witness spend discounted as Core does.
+pub fn dust_threshold(txout: &TxOut) -> u64 {
+    if txout.script_pubkey.is_op_return() {
+        return 0;
+    }
+    // Outpoint, scriptSig length, sequence and a 107-byte P2PKH scriptSig
+    // or its witness equivalent
+    let spend_size = if txout.script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    (serialize(txout).len() + spend_size) as u64 * DUST_RELAY_FEE / 1000
+}
+
+fn check_p2wsh_witness(input: usize, witness: &[Vec<u8>]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let (script, items) = match witness.split_last() {
+        Some(split) => split,
+        None => return violations,
+    };
+    if script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+        violations.push(Violation::WitnessScriptSize {
+            input,
+            size: script.len(),
+        });
+    }
+    if items.len() > MAX_STANDARD_P2WSH_STACK_ITEMS {
+        violations.push(Violation::WitnessStackItems {
+            input,
+            count: items.len(),
+        });
+    }
+    for (item, bytes) in items.iter().enumerate() {
+        if bytes.len() > MAX_STANDARD_P2WSH_STACK_ITEM_SIZE {
+            violations.push(Violation::WitnessStackItemSize {
+                input,
+                item,
+                size: bytes.len(),
+            });
+        }
+    }
+    violations
+}
+
+/// Whether `script` is one of Core's standard output templates: P2PK,
+/// P2PKH, P2SH, a witness program, bare multisig of up to three keys, or
+/// an OP_RETURN followed only by pushes. Bare multisig is further subject
+/// to `-permitbaremultisig`.
+pub fn is_standard_script(script: &Script) -> bool {
+    if script.is_witness_program() {
+        // Version 0 programs are either P2WPKH or P2WSH
+        return script.as_bytes()[0] != OP_PUSHBYTES_0.to_u8()
+            || script.is_v0_p2wpkh()
+            || script.is_v0_p2wsh();
+    }
+    if script.is_op_return() {
+        return is_push_only(&Script::from(script.as_bytes()[1..].to_vec()));
+    }
+    script.is_p2pk() || script.is_p2pkh() || script.is_p2sh() || is_bare_multisig(script)
+}
+
+fn is_unknown_witness(script: &Script) -> bool {
+    script.is_witness_program()
+        && script.as_bytes()[0] != OP_PUSHBYTES_0.to_u8()
+        && !script.is_v1_p2tr()
+}
+
+/// `<m> <key>... <n> OP_CHECKMULTISIG` with 1 <= m <= n <= 3.
+fn is_bare_multisig(script: &Script) -> bool {
+    let instructions = match script.instructions().collect::<Result<Vec<_>, _>>() {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    let (m, keys, n) = match &instructions[..] {
+        [Instruction::Op(m), keys @ .., Instruction::Op(n), Instruction::Op(check)]
+            if *check == OP_CHECKMULTISIG =>
+        {
+            (small_int(*m), keys, small_int(*n))
+        }
+        _ => return false,
+    };
+    let keys_ok = keys.iter().all(|key| {
+        matches!(key, Instruction::PushBytes(bytes) if bytes.len() == 33 || bytes.len() == 65)
+    });
+    match (m, n) {
+        (Some(m), Some(n)) => {
+            keys_ok && n == keys.len() && (1..=n).contains(&m) && n <= MAX_BARE_MULTISIG_KEYS
+        }
+        _ => false,
+    }
+}
+
+/// The value of OP_1 to OP_16.
+fn small_int(op: opcodes::All) -> Option<usize> {
+    let code = op.to_u8();
+    (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8())
+        .contains(&code)
+        .then(|| (code - OP_PUSHNUM_1.to_u8() + 1) as usize)
+}
+
+/// Whether `script` only pushes data, counting OP_1NEGATE to OP_16 as
+/// pushes as Core does.
+fn is_push_only(script: &Script) -> bool {
+    script.instructions().all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(op)) => op.to_u8() <= OP_PUSHNUM_16.to_u8(),
+        Err(_) => false,
+    })
+}
+
+/// The last push of a scriptSig: the redeem script of a P2SH spend.
+fn last_push(script_sig: &Script) -> Option<Script> {
+    match script_sig.instructions().last()? {
+        Ok(Instruction::PushBytes(bytes)) => Some(Script::from(bytes.to_vec())),
+        _ => None,
+    }
+}
+
+/// Counts signature operations the way Core's `GetSigOpCount` does. A
+/// multisig counts 20 unless `accurate` and its key count precedes it.
+fn sigops(script: &Script, accurate: bool) -> usize {
+    let mut count = 0;
+    let mut last = None;
+    for instruction in script.instructions() {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+        if let Instruction::Op(op) = instruction {
+            if op == OP_CHECKSIG || op == OP_CHECKSIGVERIFY {
+                count += 1;
+            } else if op == OP_CHECKMULTISIG || op == OP_CHECKMULTISIGVERIFY {
+                count += match last {
+                    Some(Instruction::Op(n)) if accurate => small_int(n).unwrap_or(20),
+                    _ => 20,
+                };
+            }
+        }
+        last = Some(instruction);
+    }
+    count
+}
+
+/// The legacy sigops the inputs execute, all counted accurately: each
+/// scriptSig's, plus the spent output's or, for P2SH, its redeem script's.
+fn legacy_sigops(tx: &Transaction, prevouts: &[Prevout]) -> usize {
+    tx.input
+        .iter()
+        .zip(prevouts)
+        .map(|(txin, prevout)| {
+            let spent = &prevout.script_pubkey;
+            let executed = if !spent.is_p2sh() {
+                sigops(spent, true)
+            } else if is_push_only(&txin.script_sig) {
+                last_push(&txin.script_sig).map_or(0, |redeem_script| sigops(&redeem_script, true))
+            } else {
+                0
+            };
+            sigops(&txin.script_sig, true) + executed
+        })
+        .sum()
+}
+
+/// The sigop cost Core limits: legacy and P2SH sigops count four each,
+/// witness ones one each.
+fn sigop_cost(tx: &Transaction, prevouts: &[Prevout]) -> usize {
+    let mut legacy: usize = tx
+        .input
+        .iter()
+        .map(|txin| sigops(&txin.script_sig, false))
+        .sum();
+    legacy += tx
+        .output
+        .iter()
+        .map(|txout| sigops(&txout.script_pubkey, false))
+        .sum::<usize>();
+
+    let mut p2sh = 0;
+    let mut witness = 0;
+    for (txin, prevout) in tx.input.iter().zip(prevouts) {
+        let mut program = prevout.script_pubkey.clone();
+        if program.is_p2sh() {
+            match last_push(&txin.script_sig) {
+                Some(redeem_script) => {
+                    p2sh += sigops(&redeem_script, true);
+                    program = redeem_script;
+                }
+                None => continue,
+            }
+        }
+        if program.is_v0_p2wpkh() {
+            witness += 1;
+        } else if program.is_v0_p2wsh() {
+            if let Some(script) = txin.witness.last() {
+                witness += sigops(&Script::from(script.to_vec()), true);
+            }
+        }
+    }
+    (legacy + p2sh) * 4 + witness
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        blockdata::{
+            locktime::PackedLockTime,
+            script::Builder,
+            transaction::{OutPoint, Sequence, TxIn},
+            witness::Witness,
+        },
+        hashes::Hash,
+        util::amount::Amount,
+        PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash,
+    };
+
+    fn txout(script_pubkey: Script, value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey,
+        }
+    }
+
+    #[test]
+    fn dust_thresholds_match_core() {
+        let cases = [
+            (Script::new_p2pkh(&PubkeyHash::all_zeros()), 546),
+            (Script::new_p2sh(&ScriptHash::all_zeros()), 540),
+            (Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()), 294),
+            (Script::new_v0_p2wsh(&WScriptHash::all_zeros()), 330),
+            (
+                Script::from(hex::decode(format!("5120{}", "00".repeat(32))).unwrap()),
+                330,
+            ),
+            (Script::new_op_return(&[0; 80]), 0),
+        ];
+        for (script, threshold) in cases {
+            assert_eq!(
+                dust_threshold(&txout(script.clone(), 0)),
+                threshold,
+                "{}",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn lists_every_violation() {
+        let p2wsh = Script::new_v0_p2wsh(&WScriptHash::all_zeros());
+        let bare_multisig = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(&[2; 33])
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(is_standard_script(&bare_multisig));
+        let mut witness = vec![vec![0; 81]; MAX_STANDARD_P2WSH_STACK_ITEMS + 1];
+        witness.push(vec![
+            OP_CHECKSIG.to_u8();
+            MAX_STANDARD_P2WSH_SCRIPT_SIZE + 1
+        ]);
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_vec(witness),
+            }],
+            output: vec![
+                txout(Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()), 293),
+                txout(Script::new_op_return(&[0; 81]), 0),
+                txout(Script::new_op_return(&[]), 0),
+                txout(Script::from(vec![OP_CHECKSIG.to_u8()]), 1000),
+                txout(bare_multisig.clone(), 1000),
+            ],
+        };
+        let prevouts = [Prevout {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(1293),
+            script_pubkey: p2wsh,
+        }];
+        let violations = check(&tx, &prevouts).unwrap();
+        assert!(violations.contains(&Violation::Dust {
+            output: 0,
+            value: 293,
+            threshold: 294
+        }));
+        // Any number of OP_RETURN outputs is fine within the byte limit
+        assert!(!violations
+            .iter()
+            .any(|violation| matches!(violation, Violation::DataCarrierSize(_))));
+        assert!(violations.contains(&Violation::NonStandardOutput(3)));
+        assert!(violations.contains(&Violation::BareMultisig(4)));
+        assert!(violations.contains(&Violation::WitnessScriptSize {
+            input: 0,
+            size: MAX_STANDARD_P2WSH_SCRIPT_SIZE + 1
+        }));
+        assert!(violations.contains(&Violation::WitnessStackItems {
+            input: 0,
+            count: MAX_STANDARD_P2WSH_STACK_ITEMS + 1
+        }));
+        assert!(violations.contains(&Violation::WitnessStackItemSize {
+            input: 0,
+            item: 0,
+            size: 81
+        }));
+        // 3601 witness sigops plus four for the bare OP_CHECKSIG output and
+        // 80 for the bare multisig one, at 20 vbytes each, dwarf the weight
+        // for the relay fee
+        assert!(violations.contains(&Violation::MinRelayFee {
+            fee: 0,
+            required: (3601 + 4 + 80) * 20 / 4 * MIN_RELAY_FEE / 1000
+        }));
+    }
+
+    #[test]
+    fn counts_p2sh_sigops_accurately() {
+        let redeem_script = Builder::new()
+            .push_opcode(OP_PUSHNUM_16)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let script_sig = Builder::new()
+            .push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(sigops(&redeem_script, true), 16);
+        assert_eq!(sigops(&redeem_script, false), 20);
+        assert_eq!(last_push(&script_sig), Some(redeem_script));
+        assert!(!is_push_only(&Script::from(vec![OP_CHECKSIG.to_u8()])));
+    }
+
+    #[test]
+    fn sums_data_carrier_bytes() {
+        let tx = |sizes: &[usize]| Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: sizes
+                .iter()
+                .map(|&size| txout(Script::new_op_return(&vec![0; size]), 0))
+                .collect(),
+        };
+        let prevouts = [Prevout {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(100_000),
+            script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+        }];
+        let data_carrier = |sizes| {
+            check(&tx(sizes), &prevouts)
+                .unwrap()
+                .into_iter()
+                .find(|violation| matches!(violation, Violation::DataCarrierSize(_)))
+        };
+        // Each pushes with OP_PUSHDATA2, four bytes of script around the data
+        assert_eq!(data_carrier(&[49_996, 49_996]), None);
+        assert_eq!(
+            data_carrier(&[49_996, 49_997]),
+            Some(Violation::DataCarrierSize(MAX_DATACARRIER_BYTES + 1))
+        );
+    }
+
+    #[test]
+    fn counts_legacy_sigops_of_spent_scripts() {
+        let input = |script_sig| TxIn {
+            script_sig,
+            ..TxIn::default()
+        };
+        let prevout = |script_pubkey| Prevout {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(1000),
+            script_pubkey,
+        };
+        let redeem_script = Builder::new()
+            .push_opcode(OP_PUSHNUM_16)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let p2sh = Script::new_p2sh(&redeem_script.script_hash());
+        let push_redeem = Builder::new()
+            .push_slice(redeem_script.as_bytes())
+            .into_script();
+        let mut not_push_only = push_redeem.clone().into_bytes();
+        not_push_only.insert(0, OP_NOP.to_u8());
+        let bare = Script::from(vec![OP_CHECKSIG.to_u8(); MAX_TX_LEGACY_SIGOPS + 1]);
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![
+                input(push_redeem),
+                input(Script::from(not_push_only)),
+                input(Script::new()),
+            ],
+            output: Vec::new(),
+        };
+        let prevouts = [prevout(p2sh.clone()), prevout(p2sh), prevout(bare)];
+        // The redeem script counts only behind a push-only scriptSig, and
+        // the OP_CHECKMULTISIG it pushes is data, not a sigop
+        assert_eq!(legacy_sigops(&tx, &prevouts[..2]), 16);
+        assert_eq!(legacy_sigops(&tx, &prevouts), 16 + MAX_TX_LEGACY_SIGOPS + 1);
+        assert!(check(&tx, &prevouts)
+            .unwrap()
+            .contains(&Violation::LegacySigops(16 + MAX_TX_LEGACY_SIGOPS + 1)));
+    }
+
+    #[test]
+    fn needs_one_prevout_per_input() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default(); 2],
+            output: Vec::new(),
+        };
+        let prevout = Prevout {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(1000),
+            script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+        };
+        assert_eq!(
+            check(&tx, &[prevout]),
+            Err(PolicyError::Prevouts { have: 1, inputs: 2 })
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, Transaction, TxOut},
+    },
+    consensus::encode,
+    util::amount::Amount,
+    Txid,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// An output being spent, with everything the BIP143 sighash and the
+/// script check need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prevout {
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    pub script_pubkey: Script,
+}
+
+#[derive(Debug)]
+pub enum PrevoutError {
+    /// A `--prevout` value not of the form `txid:vout:amount_sat:script_hex`.
+    Format(String),
+    Json(serde_json::Error),
+    /// Entry `index` of a prevouts JSON array has an unparseable `field`.
+    JsonField {
+        index: usize,
+        field: &'static str,
+        value: String,
+    },
+    Decode(encode::Error),
+    VoutOutOfRange {
+        vout: u32,
+        outputs: usize,
+    },
+    /// The funding transaction has no output paying to our script.
+    NoMatchingOutput(Txid),
+    /// The same output was given twice, by one source or by two.
+    Duplicate(OutPoint),
+    /// The transaction spends an output no prevout was given for.
+    Missing(OutPoint),
+    ScriptMismatch {
+        outpoint: OutPoint,
+        expected: Script,
+        found: Script,
+    },
+}
+
+impl fmt::Display for PrevoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrevoutError::Format(s) => write!(
+                f,
+                "invalid prevout `{}`, expected txid:vout:amount_sat:script_hex",
+                s
+            ),
+            PrevoutError::Json(e) => write!(f, "invalid prevouts JSON: {}", e),
+            PrevoutError::JsonField {
+                index,
+                field,
+                value,
+            } => write!(
+                f,
+                "invalid {} `{}` in prevouts JSON entry {}",
+                field, value, index
+            ),
+            PrevoutError::Decode(e) => write!(f, "invalid funding transaction: {}", e),
+            PrevoutError::VoutOutOfRange { vout, outputs } => write!(
+                f,
+                "output {} does not exist, funding transaction has {}",
+                vout, outputs
+            ),
+            PrevoutError::NoMatchingOutput(txid) => {
+                write!(
+                    f,
+                    "funding transaction {} does not pay to the multisig",
+                    txid
+                )
+            }
+            PrevoutError::Duplicate(outpoint) => {
+                write!(f, "prevout {} is given more than once", outpoint)
+            }
+            PrevoutError::Missing(outpoint) => {
+                write!(f, "no prevout given for input {}", outpoint)
+            }
+            PrevoutError::ScriptMismatch {
+                outpoint,
+                expected,
+                found,
+            } => write!(
+                f,
+                "prevout {} is locked by {:x}, expected {:x}",
+                outpoint, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PrevoutError {}
+
+impl Prevout {
+    /// The output being spent, as the sighash and the interpreter see it.
+    pub fn to_txout(&self) -> TxOut {
+        TxOut {
+            value: self.amount.to_sat(),
+            script_pubkey: self.script_pubkey.clone(),
+        }
+    }
+
+    /// Refuses prevouts we could not produce a valid spend for.
+    pub fn check_script(&self, expected: &Script) -> Result<(), PrevoutError> {
+        if self.script_pubkey == *expected {
+            Ok(())
+        } else {
+            Err(PrevoutError::ScriptMismatch {
+                outpoint: self.outpoint,
+                expected: expected.clone(),
+                found: self.script_pubkey.clone(),
+            })
+        }
+    }
+}
+
+impl FromStr for Prevout {
+    type Err = PrevoutError;
+
+    /// Parses `txid:vout:amount_sat:script_hex`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let format = || PrevoutError::Format(s.to_owned());
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 4 {
+            return Err(format());
+        }
+        Ok(Prevout {
+            outpoint: OutPoint {
+                txid: parts[0].parse().map_err(|_| format())?,
+                vout: parts[1].parse().map_err(|_| format())?,
+            },
+            amount: Amount::from_sat(parts[2].parse().map_err(|_| format())?),
+            script_pubkey: Script::from(hex::decode(parts[3]).map_err(|_| format())?),
+        })
+    }
+}
+
+/// Every output being spent, in input order. BIP341 signatures commit to
+/// all of them, not only their own.
+pub fn spent_outputs(prevouts: &[Prevout]) -> Vec<TxOut> {
+    prevouts.iter().map(Prevout::to_txout).collect()
+}
+
+/// The first outpoint that appears twice in `prevouts`. A transaction
+/// spending an output twice is invalid, and would count its amount twice.
+pub fn first_duplicate(prevouts: &[Prevout]) -> Option<OutPoint> {
+    let mut seen = HashSet::with_capacity(prevouts.len());
+    prevouts
+        .iter()
+        .map(|prevout| prevout.outpoint)
+        .find(|outpoint| !seen.insert(*outpoint))
+}
+
+/// Puts `prevouts`, given in any order, in the order `tx` spends them.
+/// Prevouts the transaction does not spend are dropped.
+pub fn order_for(
+    tx: &Transaction,
+    mut prevouts: Vec<Prevout>,
+) -> Result<Vec<Prevout>, PrevoutError> {
+    if let Some(outpoint) = first_duplicate(&prevouts) {
+        return Err(PrevoutError::Duplicate(outpoint));
+    }
+    tx.input
+        .iter()
+        .map(|input| {
+            let position = prevouts
+                .iter()
+                .position(|prevout| prevout.outpoint == input.previous_output)
+                .ok_or(PrevoutError::Missing(input.previous_output))?;
+            Ok(prevouts.swap_remove(position))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct JsonPrevout {
+    txid: String,
+    vout: u32,
+    /// In satoshis.
+    amount: u64,
+    script_pubkey: String,
+}
+
+/// Parses a JSON array of `{"txid", "vout", "amount", "script_pubkey"}`
+/// objects, amounts in satoshis and scripts in hex.
+pub fn from_json(text: &str) -> Result<Vec<Prevout>, PrevoutError> {
+    let entries: Vec<JsonPrevout> = serde_json::from_str(text).map_err(PrevoutError::Json)?;
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let invalid = |field, value: String| PrevoutError::JsonField {
+                index,
+                field,
+                value,
+            };
+            let txid = entry
+                .txid
+                .parse()
+                .map_err(|_| invalid("txid", entry.txid.clone()))?;
+            let script_pubkey = hex::decode(&entry.script_pubkey)
+                .map_err(|_| invalid("script_pubkey", entry.script_pubkey.clone()))?;
+            Ok(Prevout {
+                outpoint: OutPoint {
+                    txid,
+                    vout: entry.vout,
+                },
+                amount: Amount::from_sat(entry.amount),
+                script_pubkey: Script::from(script_pubkey),
+            })
+        })
+        .collect()
+}
+
+/// Takes output `vout` of a raw funding transaction, or every output paying
+/// to `script_pubkey` when no index is given.
+pub fn from_funding_tx(
+    tx_hex: &str,
+    vout: Option<u32>,
+    script_pubkey: &Script,
+) -> Result<Vec<Prevout>, PrevoutError> {
+    let bytes = hex::decode(tx_hex.trim()).map_err(|_| PrevoutError::Format(tx_hex.to_owned()))?;
+    let tx: Transaction = encode::deserialize(&bytes).map_err(PrevoutError::Decode)?;
+    let txid = tx.txid();
+    let prevout = |vout: u32, out: &TxOut| Prevout {
+        outpoint: OutPoint { txid, vout },
+        amount: Amount::from_sat(out.value),
+        script_pubkey: out.script_pubkey.clone(),
+    };
+
+    match vout {
+        Some(vout) => {
+            let out = tx
+                .output
+                .get(vout as usize)
+                .ok_or(PrevoutError::VoutOutOfRange {
+                    vout,
+                    outputs: tx.output.len(),
+                })?;
+            Ok(vec![prevout(vout, out)])
+        }
+        None => {
+            let matching: Vec<Prevout> = tx
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(_, out)| out.script_pubkey == *script_pubkey)
+                .map(|(i, out)| prevout(i as u32, out))
+                .collect();
+            if matching.is_empty() {
+                return Err(PrevoutError::NoMatchingOutput(txid));
+            }
+            Ok(matching)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{blockdata::transaction::TxIn, PackedLockTime};
+
+    const TXID: &str = "6ca6cd4d1c9f3d6a4f1fd0dcd8ad5e4c2a58b7e56b9b2e4cb1b61b5a1c7a3a2f";
+    const SCRIPT: &str = "a914043f512301b66ffa8d73e71907e2b0b80989521587";
+
+    fn script(hex: &str) -> Script {
+        Script::from(hex::decode(hex).unwrap())
+    }
+
+    #[test]
+    fn parses_flag() {
+        let prevout: Prevout = format!("{}:3:100000:{}", TXID, SCRIPT).parse().unwrap();
+        assert_eq!(prevout.outpoint.txid, TXID.parse().unwrap());
+        assert_eq!(prevout.outpoint.vout, 3);
+        assert_eq!(prevout.amount, Amount::from_sat(100_000));
+        assert_eq!(prevout.script_pubkey, script(SCRIPT));
+
+        for bad in [
+            format!("{}:3:100000", TXID),
+            format!("{}:3:100000:{}:extra", TXID, SCRIPT),
+            format!("{}:3:100000:{}", &TXID[1..], SCRIPT),
+            format!("{}:-1:100000:{}", TXID, SCRIPT),
+            format!("{}:4294967296:100000:{}", TXID, SCRIPT),
+            format!("{}:3:18446744073709551616:{}", TXID, SCRIPT),
+            format!("{}:3:1.5:{}", TXID, SCRIPT),
+            format!("{}:3:100000:{}0", TXID, SCRIPT),
+            format!("{}:3:100000:zz", TXID),
+        ] {
+            assert!(
+                matches!(bad.parse::<Prevout>(), Err(PrevoutError::Format(s)) if s == bad),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn loads_json() {
+        let json = format!(
+            r#"[{{"txid": "{0}", "vout": 0, "amount": 1000, "script_pubkey": "{1}"}},
+                {{"txid": "{0}", "vout": 1, "amount": 2000, "script_pubkey": "{1}"}}]"#,
+            TXID, SCRIPT
+        );
+        let prevouts = from_json(&json).unwrap();
+        assert_eq!(prevouts.len(), 2);
+        assert_eq!(prevouts[1].outpoint.vout, 1);
+        assert_eq!(prevouts[1].amount, Amount::from_sat(2000));
+
+        let missing = format!(r#"[{{"txid": "{}", "vout": 0, "amount": 1000}}]"#, TXID);
+        assert!(matches!(from_json(&missing), Err(PrevoutError::Json(_))));
+        let negative = format!(
+            r#"[{{"txid": "{}", "vout": 0, "amount": -1, "script_pubkey": "{}"}}]"#,
+            TXID, SCRIPT
+        );
+        assert!(matches!(from_json(&negative), Err(PrevoutError::Json(_))));
+        let bad_script = format!(
+            r#"[{{"txid": "{}", "vout": 0, "amount": 1, "script_pubkey": "xyz"}}]"#,
+            TXID
+        );
+        assert!(matches!(
+            from_json(&bad_script),
+            Err(PrevoutError::JsonField {
+                index: 0,
+                field: "script_pubkey",
+                value,
+            }) if value == "xyz"
+        ));
+        let bad_txid = format!(
+            r#"[{{"txid": "{0}", "vout": 0, "amount": 1, "script_pubkey": "{1}"}},
+                {{"txid": "{0}0", "vout": 0, "amount": 1, "script_pubkey": "{1}"}}]"#,
+            TXID, SCRIPT
+        );
+        assert!(matches!(
+            from_json(&bad_txid),
+            Err(PrevoutError::JsonField {
+                index: 1,
+                field: "txid",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn selects_funding_outputs() {
+        let ours = script(SCRIPT);
+        let other = script("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let output = |value, script_pubkey: &Script| TxOut {
+            value,
+            script_pubkey: script_pubkey.clone(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                output(1000, &ours),
+                output(2000, &other),
+                output(3000, &ours),
+            ],
+        };
+        let tx_hex = encode::serialize_hex(&tx);
+
+        let all = from_funding_tx(&tx_hex, None, &ours).unwrap();
+        assert_eq!(
+            all.iter().map(|p| p.outpoint.vout).collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert!(all.iter().all(|p| p.outpoint.txid == tx.txid()));
+        assert_eq!(all[1].amount, Amount::from_sat(3000));
+
+        // An explicit index is taken as is, and checked later
+        let one = from_funding_tx(&tx_hex, Some(1), &ours).unwrap();
+        assert_eq!(one[0].script_pubkey, other);
+        assert!(matches!(
+            from_funding_tx(&tx_hex, Some(3), &ours),
+            Err(PrevoutError::VoutOutOfRange {
+                vout: 3,
+                outputs: 3
+            })
+        ));
+        assert!(matches!(
+            from_funding_tx(&tx_hex, None, &script("51")),
+            Err(PrevoutError::NoMatchingOutput(txid)) if txid == tx.txid()
+        ));
+        assert!(matches!(
+            from_funding_tx(&tx_hex[..20], None, &ours),
+            Err(PrevoutError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn check_script_reports_mismatch() {
+        let prevout: Prevout = format!("{}:0:1000:{}", TXID, SCRIPT).parse().unwrap();
+        assert!(prevout.check_script(&script(SCRIPT)).is_ok());
+        let expected = script("51");
+        match prevout.check_script(&expected) {
+            Err(PrevoutError::ScriptMismatch {
+                outpoint,
+                expected: e,
+                found,
+            }) => {
+                assert_eq!(outpoint, prevout.outpoint);
+                assert_eq!(e, expected);
+                assert_eq!(found, prevout.script_pubkey);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn finds_duplicate_outpoints() {
+        let prevout = |vout| {
+            format!("{}:{}:1000:{}", TXID, vout, SCRIPT)
+                .parse()
+                .unwrap()
+        };
+        let prevouts: Vec<Prevout> = vec![prevout(0), prevout(1), prevout(2)];
+        assert_eq!(first_duplicate(&prevouts), None);
+
+        // The same output again, even at another amount
+        let mut again = prevouts.clone();
+        again.push(Prevout {
+            amount: Amount::from_sat(5000),
+            ..prevouts[1].clone()
+        });
+        assert_eq!(first_duplicate(&again), Some(prevouts[1].outpoint));
+    }
+
+    #[test]
+    fn orders_prevouts_by_input() {
+        let prevout = |vout| -> Prevout {
+            format!("{}:{}:1000:{}", TXID, vout, SCRIPT)
+                .parse()
+                .unwrap()
+        };
+        let input = |vout| TxIn {
+            previous_output: prevout(vout).outpoint,
+            ..TxIn::default()
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![input(2), input(0), input(1)],
+            output: Vec::new(),
+        };
+        let ordered = order_for(&tx, vec![prevout(0), prevout(1), prevout(2)]).unwrap();
+        assert_eq!(ordered, [prevout(2), prevout(0), prevout(1)]);
+
+        assert!(matches!(
+            order_for(&tx, vec![prevout(0), prevout(2)]),
+            Err(PrevoutError::Missing(outpoint)) if outpoint == prevout(1).outpoint
+        ));
+        let extra = order_for(&tx, vec![prevout(3), prevout(1), prevout(2), prevout(0)]);
+        assert_eq!(extra.unwrap(), ordered);
+        assert!(matches!(
+            order_for(&tx, vec![prevout(0), prevout(1), prevout(1)]),
+            Err(PrevoutError::Duplicate(outpoint)) if outpoint == prevout(1).outpoint
+        ));
+    }
+}
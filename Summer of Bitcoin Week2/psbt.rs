@@ -0,0 +1,558 @@
+use crate::builder::{split_anyonecanpay, Wrapping};
+use crate::finalize::{self, FinalizeError};
+use crate::multisig::Multisig;
+use crate::prevout::Prevout;
+use crate::signature;
+use bitcoin::{
+    blockdata::{
+        script::{Builder, Script},
+        transaction::{Transaction, TxIn, TxOut},
+        witness::Witness,
+    },
+    secp256k1::{Message, Secp256k1, Signing},
+    util::{
+        amount::Amount,
+        bip32::{DerivationPath, Fingerprint, KeySource},
+        psbt::{self, PartiallySignedTransaction as Psbt},
+        sighash::{self, EcdsaSighashType, SighashCache},
+    },
+    EcdsaSig, PrivateKey, PublicKey,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum PsbtError {
+    Psbt(psbt::Error),
+    NoPsbts,
+    /// Legacy P2SH inputs need their whole previous transaction in a PSBT.
+    Legacy,
+    /// A key origin not of the form `[fingerprint/path]pubkey`.
+    Origin(String),
+    /// None of the inputs has a witness script with this key.
+    NothingToSign(PublicKey),
+    MissingWitnessUtxo(usize),
+    MissingWitnessScript(usize),
+    /// The witness UTXO is not locked by the P2WSH, or the P2SH of the
+    /// redeem script, of the input's witness script.
+    ScriptMismatch(usize),
+    NonStandardSighash(usize),
+    Sighash(sighash::Error),
+    Finalize {
+        index: usize,
+        error: FinalizeError,
+    },
+    NotFinalized(usize),
+    /// A signature on this input commits to every input.
+    InputsCommitted {
+        index: usize,
+        sighash: EcdsaSighashType,
+    },
+    /// A signature on this input commits to the outputs.
+    OutputsCommitted {
+        index: usize,
+        sighash: EcdsaSighashType,
+    },
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsbtError::Psbt(e) => write!(f, "PSBT: {}", e),
+            PsbtError::NoPsbts => write!(f, "no PSBTs given"),
+            PsbtError::Legacy => write!(
+                f,
+                "legacy P2SH spends need the full previous transactions; sign them directly"
+            ),
+            PsbtError::Origin(s) => write!(
+                f,
+                "invalid key origin `{}`, expected [fingerprint/path]pubkey",
+                s
+            ),
+            PsbtError::NothingToSign(key) => {
+                write!(f, "no input has a witness script with key {}", key)
+            }
+            PsbtError::MissingWitnessUtxo(i) => write!(f, "input {} has no witness UTXO", i),
+            PsbtError::MissingWitnessScript(i) => {
+                write!(f, "input {} has no witness script", i)
+            }
+            PsbtError::ScriptMismatch(i) => write!(
+                f,
+                "input {} spends an output not locked by its witness script",
+                i
+            ),
+            PsbtError::NonStandardSighash(i) => {
+                write!(f, "input {} asks for a non-standard sighash type", i)
+            }
+            PsbtError::Sighash(e) => write!(f, "sighash: {}", e),
+            PsbtError::Finalize { index, error } => write!(f, "input {}: {}", index, error),
+            PsbtError::NotFinalized(i) => write!(f, "input {} is not finalized", i),
+            PsbtError::InputsCommitted { index, sighash } => write!(
+                f,
+                "input {} has a {} signature, which forbids adding inputs",
+                index, sighash
+            ),
+            PsbtError::OutputsCommitted { index, sighash } => write!(
+                f,
+                "input {} has a {} signature, which forbids adding outputs",
+                index, sighash
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PsbtError {}
+
+impl From<psbt::Error> for PsbtError {
+    fn from(e: psbt::Error) -> Self {
+        PsbtError::Psbt(e)
+    }
+}
+
+impl From<sighash::Error> for PsbtError {
+    fn from(e: sighash::Error) -> Self {
+        PsbtError::Sighash(e)
+    }
+}
+
+/// Where a cosigner's key comes from, written `[fingerprint/path]pubkey` as
+/// in output descriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOrigin {
+    pub key: PublicKey,
+    pub source: KeySource,
+}
+
+impl FromStr for KeyOrigin {
+    type Err = PsbtError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PsbtError::Origin(s.to_owned());
+        let (source, key) = split_origin(s).ok_or_else(invalid)?;
+        Ok(KeyOrigin {
+            key: PublicKey::from_str(key).map_err(|_| invalid())?,
+            source,
+        })
+    }
+}
+
+impl fmt::Display for KeyOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_origin(f, &self.source)?;
+        write!(f, "{}", self.key)
+    }
+}
+
+/// Splits `[fingerprint/path]key` into the key source and the key text.
+pub fn split_origin(s: &str) -> Option<(KeySource, &str)> {
+    let (origin, key) = s.strip_prefix('[')?.split_once(']')?;
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    let path = if path.is_empty() {
+        DerivationPath::master()
+    } else {
+        DerivationPath::from_str(&format!("m/{}", path)).ok()?
+    };
+    Some(((Fingerprint::from_str(fingerprint).ok()?, path), key))
+}
+
+/// Writes the `[fingerprint/path]` prefix of a key with a known origin.
+pub fn fmt_origin(f: &mut fmt::Formatter, source: &KeySource) -> fmt::Result {
+    write!(f, "[{}", source.0)?;
+    for child in source.1.as_ref() {
+        write!(f, "/{}", child)?;
+    }
+    write!(f, "]")
+}
+
+/// Turns an unsigned segwit spend of `multisig` into a PSBT carrying
+/// everything a cosigner needs: the prevout, the scripts and the key origins
+/// we know. The change output, if it goes back to the multisig, gets the
+/// same.
+pub fn create(
+    tx: &Transaction,
+    prevouts: &[Prevout],
+    multisig: &Multisig,
+    wrapping: Wrapping,
+    origins: &[KeyOrigin],
+    change: Option<usize>,
+    sighash_types: &[EcdsaSighashType],
+) -> Result<Psbt, PsbtError> {
+    let (redeem_script, own_script) = match wrapping {
+        Wrapping::P2shP2wsh => {
+            let redeem_script = multisig.redeem_script();
+            let own_script = Script::new_p2sh(&redeem_script.script_hash());
+            (Some(redeem_script), own_script)
+        }
+        Wrapping::P2wsh => (None, multisig.redeem_script()),
+        Wrapping::P2sh => return Err(PsbtError::Legacy),
+    };
+    let mut unsigned = tx.clone();
+    for input in &mut unsigned.input {
+        input.script_sig = Script::new();
+        input.witness.clear();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned)?;
+
+    let derivations: BTreeMap<_, _> = origins
+        .iter()
+        .filter(|origin| multisig.keys().contains(&origin.key))
+        .map(|origin| (origin.key.inner, origin.source.clone()))
+        .collect();
+    for ((input, prevout), &ty) in psbt.inputs.iter_mut().zip(prevouts).zip(sighash_types) {
+        // Signers default to ALL, so only record anything else
+        if ty != EcdsaSighashType::All {
+            input.sighash_type = Some(ty.into());
+        }
+        input.witness_utxo = Some(prevout.to_txout());
+        input.redeem_script = redeem_script.clone();
+        input.witness_script = Some(multisig.witness_script().clone());
+        input.bip32_derivation = derivations.clone();
+    }
+
+    if let Some(index) = change {
+        if tx.output[index].script_pubkey == own_script {
+            let output = &mut psbt.outputs[index];
+            output.redeem_script = redeem_script;
+            output.witness_script = Some(multisig.witness_script().clone());
+            output.bip32_derivation = derivations;
+        }
+    }
+    Ok(psbt)
+}
+
+/// Adds a partial signature from `key` to every input whose witness script
+/// includes it, returning how many inputs were signed.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    psbt: &mut Psbt,
+    key: &PrivateKey,
+) -> Result<usize, PsbtError> {
+    let public_key = key.public_key(secp);
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut signatures = Vec::new();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let witness_script = match &input.witness_script {
+            Some(script) => script,
+            None => continue,
+        };
+        match Multisig::from_script(witness_script) {
+            Ok(multisig) if multisig.keys().contains(&public_key) => {}
+            _ => continue,
+        }
+        let utxo = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(PsbtError::MissingWitnessUtxo(index))?;
+        // The amount we sign for is only that of the output we think we
+        // spend, so make sure the scripts lead to it.
+        let p2wsh = witness_script.to_v0_p2wsh();
+        let locked = match &input.redeem_script {
+            Some(redeem_script) => {
+                *redeem_script == p2wsh
+                    && utxo.script_pubkey == Script::new_p2sh(&redeem_script.script_hash())
+            }
+            None => utxo.script_pubkey == p2wsh,
+        };
+        if !locked {
+            return Err(PsbtError::ScriptMismatch(index));
+        }
+        let amount = utxo.value;
+        let hash_ty = match input.sighash_type {
+            Some(ty) => ty
+                .ecdsa_hash_ty()
+                .map_err(|_| PsbtError::NonStandardSighash(index))?,
+            None => EcdsaSighashType::All,
+        };
+
+        let sighash = cache.segwit_signature_hash(index, witness_script, amount, hash_ty)?;
+        let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        let sig = signature::sign(secp, &message, &key.inner);
+        signatures.push((index, EcdsaSig { sig, hash_ty }));
+    }
+    if signatures.is_empty() {
+        return Err(PsbtError::NothingToSign(public_key));
+    }
+
+    let signed = signatures.len();
+    for (index, signature) in signatures {
+        psbt.inputs[index]
+            .partial_sigs
+            .insert(public_key, signature);
+    }
+    Ok(signed)
+}
+
+/// Merges the partial signatures and other fields of several PSBTs for the
+/// same transaction.
+pub fn combine(psbts: Vec<Psbt>) -> Result<Psbt, PsbtError> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = match psbts.next() {
+        Some(psbt) => psbt,
+        None => return Err(PsbtError::NoPsbts),
+    };
+    for psbt in psbts {
+        combined.combine(psbt)?;
+    }
+    Ok(combined)
+}
+
+/// The sighash type of every signature already in the PSBT, partial or in a
+/// final witness, with the index of its input.
+fn signature_types(psbt: &Psbt) -> Vec<(usize, EcdsaSighashType)> {
+    let mut types = Vec::new();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        types.extend(input.partial_sigs.values().map(|sig| (index, sig.hash_ty)));
+        if let Some(witness) = &input.final_script_witness {
+            types.extend(
+                witness
+                    .iter()
+                    .filter_map(|item| EcdsaSig::from_slice(item).ok())
+                    .map(|sig| (index, sig.hash_ty)),
+            );
+        }
+    }
+    types
+}
+
+/// Adds someone else's input to a PSBT that may already carry signatures,
+/// as in a collaborative construction. Only signatures made with
+/// ANYONECANPAY stay valid when the input set changes.
+pub fn add_input(psbt: &mut Psbt, txin: TxIn, input: psbt::Input) -> Result<(), PsbtError> {
+    for (index, sighash) in signature_types(psbt) {
+        if !split_anyonecanpay(sighash).1 {
+            return Err(PsbtError::InputsCommitted { index, sighash });
+        }
+    }
+    psbt.unsigned_tx.input.push(TxIn {
+        script_sig: Script::new(),
+        witness: Witness::new(),
+        ..txin
+    });
+    psbt.inputs.push(input);
+    Ok(())
+}
+
+/// Appends an output to a PSBT that may already carry signatures. NONE
+/// signatures ignore the outputs and SINGLE ones only cover the output at
+/// their own index, so both survive unless the new output lands there.
+pub fn add_output(psbt: &mut Psbt, txout: TxOut, output: psbt::Output) -> Result<(), PsbtError> {
+    let outputs = psbt.unsigned_tx.output.len();
+    for (index, sighash) in signature_types(psbt) {
+        let still_valid = match split_anyonecanpay(sighash).0 {
+            EcdsaSighashType::None => true,
+            EcdsaSighashType::Single => index != outputs,
+            _ => false,
+        };
+        if !still_valid {
+            return Err(PsbtError::OutputsCommitted { index, sighash });
+        }
+    }
+    psbt.unsigned_tx.output.push(txout);
+    psbt.outputs.push(output);
+    Ok(())
+}
+
+/// Builds the final scriptSig and witness of every input from its partial
+/// signatures, then drops the fields BIP174 says a finalizer removes.
+pub fn finalize(psbt: &mut Psbt) -> Result<(), PsbtError> {
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+        let witness_script = input
+            .witness_script
+            .clone()
+            .ok_or(PsbtError::MissingWitnessScript(index))?;
+        let amount = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(PsbtError::MissingWitnessUtxo(index))?
+            .value;
+        let finalize_error = |error| PsbtError::Finalize { index, error };
+        let multisig =
+            Multisig::from_script(&witness_script).map_err(|e| finalize_error(e.into()))?;
+
+        // Only the first `threshold` signatures in key order go in the witness
+        let signatures: Vec<EcdsaSig> = multisig
+            .keys()
+            .iter()
+            .filter_map(|key| input.partial_sigs.get(key).copied())
+            .take(multisig.threshold())
+            .collect();
+        let witness = finalize::multisig_witness(&witness_script, &signatures, |hash_ty| {
+            let sighash = cache
+                .segwit_signature_hash(index, &witness_script, amount, hash_ty)
+                .expect("sighash for an existing input");
+            Message::from_slice(&sighash[..]).expect("sighash is 32 bytes")
+        })
+        .map_err(finalize_error)?;
+
+        input.final_script_sig = input.redeem_script.as_ref().map(|redeem_script| {
+            Builder::new()
+                .push_slice(redeem_script.as_bytes())
+                .into_script()
+        });
+        input.final_script_witness = Some(witness);
+        input.partial_sigs.clear();
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        input.bip32_derivation.clear();
+    }
+    Ok(())
+}
+
+/// Extracts the signed transaction of a finalized PSBT, with the outputs it
+/// spends for verification.
+pub fn extract(psbt: Psbt) -> Result<(Transaction, Vec<Prevout>), PsbtError> {
+    let mut prevouts = Vec::with_capacity(psbt.inputs.len());
+    for (index, (input, txin)) in psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate() {
+        if input.final_script_witness.is_none() && input.final_script_sig.is_none() {
+            return Err(PsbtError::NotFinalized(index));
+        }
+        let utxo = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(PsbtError::MissingWitnessUtxo(index))?;
+        prevouts.push(Prevout {
+            outpoint: txin.previous_output,
+            amount: Amount::from_sat(utxo.value),
+            script_pubkey: utxo.script_pubkey.clone(),
+        });
+    }
+    Ok((psbt.extract_tx(), prevouts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, key};
+    use crate::multisig::KeyOrdering;
+    use crate::verify::{self, Flags};
+    use bitcoin::{
+        blockdata::{locktime::PackedLockTime, transaction::OutPoint},
+        hashes::Hash,
+        network::constants::Network,
+        Txid, WPubkeyHash,
+    };
+
+    /// An unsigned PSBT spending two outputs of a 2-of-3 under `wrapping`.
+    fn unsigned(wrapping: Wrapping) -> (Psbt, Multisig) {
+        let secp = Secp256k1::new();
+        let public_keys: Vec<_> = (1..=3).map(|i| key(i).public_key(&secp)).collect();
+        let multisig = Multisig::new(&public_keys, 2, KeyOrdering::SortedMulti).unwrap();
+        let script_pubkey = wrapping
+            .address(&multisig, Network::Bitcoin)
+            .unwrap()
+            .script_pubkey();
+        let prevouts = fixture::prevouts(&script_pubkey, &[50_000, 50_000]);
+        let unsigned = fixture::build(
+            &wrapping.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts,
+            60_000,
+        );
+        let psbt = create(
+            &unsigned.tx,
+            &prevouts,
+            &multisig,
+            wrapping,
+            &[],
+            unsigned.change,
+            &[EcdsaSighashType::All; 2],
+        )
+        .unwrap();
+        (psbt, multisig)
+    }
+
+    #[test]
+    fn round_trip_verifies() {
+        let secp = Secp256k1::new();
+        for wrapping in [Wrapping::P2shP2wsh, Wrapping::P2wsh] {
+            let (psbt, _) = unsigned(wrapping);
+            // Two cosigners sign their own copies
+            let mut first = psbt.clone();
+            assert_eq!(sign(&secp, &mut first, &key(1)).unwrap(), 2);
+            let mut second = psbt;
+            assert_eq!(sign(&secp, &mut second, &key(3)).unwrap(), 2);
+
+            let mut combined = combine(vec![first, second]).unwrap();
+            assert_eq!(combined.inputs[0].partial_sigs.len(), 2);
+            finalize(&mut combined).unwrap();
+            let (tx, prevouts) = extract(combined).unwrap();
+            for result in verify::verify(&secp, &tx, &prevouts, Flags::Standard) {
+                assert!(result.is_valid(), "{}: {}", wrapping, result);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_to_finalize_or_extract_short_of_threshold() {
+        let secp = Secp256k1::new();
+        let (mut psbt, _) = unsigned(Wrapping::P2wsh);
+        assert!(matches!(
+            extract(psbt.clone()),
+            Err(PsbtError::NotFinalized(0))
+        ));
+        sign(&secp, &mut psbt, &key(2)).unwrap();
+        assert!(matches!(
+            finalize(&mut psbt),
+            Err(PsbtError::Finalize { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn refuses_to_sign_for_another_script() {
+        let secp = Secp256k1::new();
+        let (mut psbt, _) = unsigned(Wrapping::P2wsh);
+        psbt.inputs[1].witness_utxo.as_mut().unwrap().script_pubkey =
+            Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros());
+        assert!(matches!(
+            sign(&secp, &mut psbt, &key(1)),
+            Err(PsbtError::ScriptMismatch(1))
+        ));
+
+        let (mut psbt, multisig) = unsigned(Wrapping::P2shP2wsh);
+        psbt.inputs[0].redeem_script = Some(multisig.witness_script().clone());
+        assert!(matches!(
+            sign(&secp, &mut psbt, &key(1)),
+            Err(PsbtError::ScriptMismatch(0))
+        ));
+    }
+
+    #[test]
+    fn all_signatures_block_new_inputs_and_outputs() {
+        let secp = Secp256k1::new();
+        let (mut psbt, _) = unsigned(Wrapping::P2wsh);
+        sign(&secp, &mut psbt, &key(1)).unwrap();
+        let txin = TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_slice(&[8; 32]).unwrap(),
+                vout: 0,
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            add_input(&mut psbt, txin, psbt::Input::default()),
+            Err(PsbtError::InputsCommitted {
+                index: 0,
+                sighash: EcdsaSighashType::All
+            })
+        ));
+        let txout = TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+        };
+        assert!(matches!(
+            add_output(&mut psbt, txout, psbt::Output::default()),
+            Err(PsbtError::OutputsCommitted {
+                index: 0,
+                sighash: EcdsaSighashType::All
+            })
+        ));
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+        assert_eq!(psbt.inputs.len(), 2);
+    }
+}
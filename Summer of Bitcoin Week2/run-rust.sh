@@ -1 +1,17 @@
-cargo run
\ No newline at end of file
+# Spends 100000 sats at 0000...0000:0 locked by the 2-of-2 P2SH-P2WSH script
+# back to the same address, with no fee. The two cosigner keys are the
+# assignment's public test keys; they are sealed into throwaway keystores
+# under the test passphrase "test" and read back from there, the way real
+# keys would be.
+set -e
+dir=$(mktemp -d)
+trap 'rm -rf "$dir"' EXIT
+printf 'test\n' > "$dir/passphrase"
+echo KyABXNtAZeRnWwmiUSMT1ebGErQBqWKq3UuaAm5MbW8eAazmG829 |
+    cargo run -q -- keystore create --out "$dir/cosigner1.json" --passphrase-file "$dir/passphrase"
+echo Kyv8VuX6BFytbLSuGbc9DJXcbJSdjGbd1Ptz8osc5QmSPacaNvcg |
+    cargo run -q -- keystore create --out "$dir/cosigner2.json" --passphrase-file "$dir/passphrase"
+cargo run -- build --sorted --keystore "$dir/cosigner1.json" --keystore "$dir/cosigner2.json" \
+    --passphrase-file "$dir/passphrase" \
+    --prevout 0000000000000000000000000000000000000000000000000000000000000000:0:100000:a914043f512301b66ffa8d73e71907e2b0b80989521587 \
+    --to 325UUecEQuyrTd28Xs2hvAxdAjHM7XzqVF:100000 --fee-rate 0
@@ -0,0 +1,441 @@
+use crate::builder::{self, Wrapping};
+use crate::cli;
+use crate::finalize::{self, FinalizeError};
+use crate::multisig::Multisig;
+use crate::prevout::Prevout;
+use crate::signature;
+use bitcoin::{
+    blockdata::{transaction::Transaction, witness::Witness},
+    secp256k1::{ecdsa::Signature, Secp256k1, Signing, Verification},
+    util::sighash::{self, EcdsaSighashType, SighashCache},
+    EcdsaSig, PrivateKey, PublicKey, Txid,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const SIGNATURE_FILE_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum SigFileError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u8),
+    /// A field that does not parse: the pubkey, a signature or its type, or
+    /// an input listed out of order.
+    Field(&'static str, String),
+    /// The file signs another transaction than the one being combined.
+    WrongTransaction {
+        expected: Txid,
+        found: Txid,
+    },
+    /// Signatures are not given for every input.
+    Inputs {
+        have: usize,
+        inputs: usize,
+    },
+    /// The signer's key is not one of the script's.
+    UnknownKey(PublicKey),
+    DuplicateKey(PublicKey),
+    BadSignature {
+        key: PublicKey,
+        input: usize,
+    },
+    Threshold {
+        have: usize,
+        need: usize,
+    },
+    Sighash(sighash::Error),
+    Finalize(FinalizeError),
+}
+
+impl fmt::Display for SigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SigFileError::Json(e) => write!(f, "invalid signature file: {}", e),
+            SigFileError::UnsupportedVersion(v) => {
+                write!(f, "unsupported signature file version {}", v)
+            }
+            SigFileError::Field(name, value) => {
+                write!(f, "invalid {} `{}` in signature file", name, value)
+            }
+            SigFileError::WrongTransaction { expected, found } => write!(
+                f,
+                "signature file signs transaction {}, expected {}",
+                found, expected
+            ),
+            SigFileError::Inputs { have, inputs } => write!(
+                f,
+                "signature file has {} signature(s) for {} input(s)",
+                have, inputs
+            ),
+            SigFileError::UnknownKey(key) => {
+                write!(f, "signature file key {} is not in the script", key)
+            }
+            SigFileError::DuplicateKey(key) => {
+                write!(f, "more than one signature file from key {}", key)
+            }
+            SigFileError::BadSignature { key, input } => write!(
+                f,
+                "signature from {} on input {} does not verify",
+                key, input
+            ),
+            SigFileError::Threshold { have, need } => {
+                write!(f, "{} of the {} required signers have signed", have, need)
+            }
+            SigFileError::Sighash(e) => write!(f, "sighash: {}", e),
+            SigFileError::Finalize(e) => write!(f, "witness: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SigFileError {}
+
+impl From<serde_json::Error> for SigFileError {
+    fn from(e: serde_json::Error) -> Self {
+        SigFileError::Json(e)
+    }
+}
+
+impl From<sighash::Error> for SigFileError {
+    fn from(e: sighash::Error) -> Self {
+        SigFileError::Sighash(e)
+    }
+}
+
+impl From<FinalizeError> for SigFileError {
+    fn from(e: FinalizeError) -> Self {
+        SigFileError::Finalize(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonSignatureFile {
+    version: u8,
+    txid: String,
+    pubkey: String,
+    signatures: Vec<JsonSignature>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonSignature {
+    input: usize,
+    sighash_type: String,
+    /// DER, without the sighash type byte.
+    signature: String,
+}
+
+/// One cosigner's signatures on every input of an unsigned transaction,
+/// as carried off an air-gapped machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureFile {
+    /// Of the unsigned transaction.
+    pub txid: Txid,
+    pub key: PublicKey,
+    /// In input order.
+    pub signatures: Vec<EcdsaSig>,
+}
+
+impl SignatureFile {
+    pub fn to_json(&self) -> String {
+        let file = JsonSignatureFile {
+            version: SIGNATURE_FILE_VERSION,
+            txid: self.txid.to_string(),
+            pubkey: self.key.to_string(),
+            signatures: self
+                .signatures
+                .iter()
+                .enumerate()
+                .map(|(input, signature)| JsonSignature {
+                    input,
+                    sighash_type: signature.hash_ty.to_string(),
+                    signature: hex::encode(signature.sig.serialize_der()),
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&file).expect("signature file serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SigFileError> {
+        let file: JsonSignatureFile = serde_json::from_str(json)?;
+        if file.version != SIGNATURE_FILE_VERSION {
+            return Err(SigFileError::UnsupportedVersion(file.version));
+        }
+        let field = |name, value: &str| SigFileError::Field(name, value.to_owned());
+        let mut signatures = Vec::with_capacity(file.signatures.len());
+        for (position, entry) in file.signatures.iter().enumerate() {
+            if entry.input != position {
+                return Err(field("input", &entry.input.to_string()));
+            }
+            let der =
+                hex::decode(&entry.signature).map_err(|_| field("signature", &entry.signature))?;
+            signatures.push(EcdsaSig {
+                sig: Signature::from_der(&der).map_err(|_| field("signature", &entry.signature))?,
+                hash_ty: cli::parse_sighash(&entry.sighash_type)
+                    .map_err(|_| field("sighash type", &entry.sighash_type))?,
+            });
+        }
+        Ok(SignatureFile {
+            txid: file.txid.parse().map_err(|_| field("txid", &file.txid))?,
+            key: file
+                .pubkey
+                .parse()
+                .map_err(|_| field("pubkey", &file.pubkey))?,
+            signatures,
+        })
+    }
+
+    /// Checks the file is for `tx`, from one of the script's keys, and that
+    /// every signature verifies against its input's sighash.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        tx: &Transaction,
+        prevouts: &[Prevout],
+        wrapping: Wrapping,
+        multisig: &Multisig,
+    ) -> Result<(), SigFileError> {
+        if self.txid != tx.txid() {
+            return Err(SigFileError::WrongTransaction {
+                expected: tx.txid(),
+                found: self.txid,
+            });
+        }
+        if !multisig.keys().contains(&self.key) {
+            return Err(SigFileError::UnknownKey(self.key));
+        }
+        if self.signatures.len() != tx.input.len() {
+            return Err(SigFileError::Inputs {
+                have: self.signatures.len(),
+                inputs: tx.input.len(),
+            });
+        }
+        let mut cache = SighashCache::new(tx);
+        for (input, (prevout, signature)) in prevouts.iter().zip(&self.signatures).enumerate() {
+            let message = builder::signature_message(
+                &mut cache,
+                tx,
+                wrapping,
+                input,
+                multisig.witness_script(),
+                prevout.amount.to_sat(),
+                signature.hash_ty,
+            )?;
+            if secp
+                .verify_ecdsa(&message, &signature.sig, &self.key.inner)
+                .is_err()
+            {
+                return Err(SigFileError::BadSignature {
+                    key: self.key,
+                    input,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Signs every input of the unsigned `tx` with `key` alone, input `i`
+/// under `sighash_types[i]`.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    prevouts: &[Prevout],
+    wrapping: Wrapping,
+    multisig: &Multisig,
+    key: &PrivateKey,
+    sighash_types: &[EcdsaSighashType],
+) -> Result<SignatureFile, SigFileError> {
+    let public_key = key.public_key(secp);
+    if !multisig.keys().contains(&public_key) {
+        return Err(SigFileError::UnknownKey(public_key));
+    }
+    let mut cache = SighashCache::new(tx);
+    let mut signatures = Vec::with_capacity(prevouts.len());
+    for (index, (prevout, &sighash_type)) in prevouts.iter().zip(sighash_types).enumerate() {
+        let message = builder::signature_message(
+            &mut cache,
+            tx,
+            wrapping,
+            index,
+            multisig.witness_script(),
+            prevout.amount.to_sat(),
+            sighash_type,
+        )?;
+        signatures.push(EcdsaSig {
+            sig: signature::sign(secp, &message, &key.inner),
+            hash_ty: sighash_type,
+        });
+    }
+    Ok(SignatureFile {
+        txid: tx.txid(),
+        key: public_key,
+        signatures,
+    })
+}
+
+/// Verifies every file and, once enough cosigners have signed, sets each
+/// input's witness, or for legacy P2SH its scriptSig, from the first
+/// `threshold` of them in script key order. Returns the keys used.
+pub fn combine<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &mut Transaction,
+    prevouts: &[Prevout],
+    wrapping: Wrapping,
+    multisig: &Multisig,
+    files: &[SignatureFile],
+) -> Result<Vec<PublicKey>, SigFileError> {
+    let mut by_key: Vec<Option<&SignatureFile>> = vec![None; multisig.keys().len()];
+    for file in files {
+        file.verify(secp, tx, prevouts, wrapping, multisig)?;
+        let position = multisig
+            .keys()
+            .iter()
+            .position(|key| *key == file.key)
+            .expect("verified key is in the script");
+        if by_key[position].replace(file).is_some() {
+            return Err(SigFileError::DuplicateKey(file.key));
+        }
+    }
+    let need = multisig.threshold();
+    let signers: Vec<&SignatureFile> = by_key.into_iter().flatten().take(need).collect();
+    if signers.len() < need {
+        return Err(SigFileError::Threshold {
+            have: signers.len(),
+            need,
+        });
+    }
+
+    let script = multisig.witness_script();
+    let mut cache = SighashCache::new(&*tx);
+    let mut signed = Vec::with_capacity(prevouts.len());
+    for (index, prevout) in prevouts.iter().enumerate() {
+        let amount = prevout.amount.to_sat();
+        let by_type = |hash_ty| {
+            builder::signature_message(&mut cache, tx, wrapping, index, script, amount, hash_ty)
+                .expect("sighash for an existing input")
+        };
+        let signatures: Vec<EcdsaSig> = signers.iter().map(|file| file.signatures[index]).collect();
+        if wrapping == Wrapping::P2sh {
+            let script_sig = finalize::multisig_script_sig(script, &signatures, by_type)?;
+            signed.push((script_sig, Witness::new()));
+        } else {
+            let witness = finalize::multisig_witness(script, &signatures, by_type)?;
+            signed.push((tx.input[index].script_sig.clone(), witness));
+        }
+    }
+    for (input, (script_sig, witness)) in tx.input.iter_mut().zip(signed) {
+        input.script_sig = script_sig;
+        input.witness = witness;
+    }
+    Ok(signers.iter().map(|file| file.key).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, key};
+    use crate::multisig::KeyOrdering;
+    use crate::verify::{self, Flags};
+    use bitcoin::{network::constants::Network, PackedLockTime};
+
+    fn setup(wrapping: Wrapping) -> (Vec<PrivateKey>, Multisig, Transaction, Vec<Prevout>) {
+        let secp = Secp256k1::new();
+        let keys = fixture::keys(3);
+        let public_keys: Vec<PublicKey> = keys.iter().map(|key| key.public_key(&secp)).collect();
+        let multisig = Multisig::new(&public_keys, 2, KeyOrdering::SortedMulti).unwrap();
+        let script_pubkey = wrapping
+            .address(&multisig, Network::Bitcoin)
+            .unwrap()
+            .script_pubkey();
+        let prevouts = fixture::prevouts(&script_pubkey, &[50_000, 50_000]);
+        let tx = fixture::build(
+            &wrapping.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts,
+            90_000,
+        )
+        .tx;
+        (keys, multisig, tx, prevouts)
+    }
+
+    #[test]
+    fn combines_signature_files_at_threshold() {
+        let secp = Secp256k1::new();
+        for wrapping in Wrapping::ALL {
+            let (keys, multisig, mut tx, prevouts) = setup(wrapping);
+            let types = [
+                EcdsaSighashType::All,
+                EcdsaSighashType::SinglePlusAnyoneCanPay,
+            ];
+            let files: Vec<SignatureFile> = keys
+                .iter()
+                .map(|key| {
+                    let file =
+                        sign(&secp, &tx, &prevouts, wrapping, &multisig, key, &types).unwrap();
+                    SignatureFile::from_json(&file.to_json()).unwrap()
+                })
+                .collect();
+
+            let mut partial = tx.clone();
+            assert!(matches!(
+                combine(
+                    &secp,
+                    &mut partial,
+                    &prevouts,
+                    wrapping,
+                    &multisig,
+                    &files[2..]
+                ),
+                Err(SigFileError::Threshold { have: 1, need: 2 })
+            ));
+
+            // Any two of three will do; the third is left out
+            combine(&secp, &mut tx, &prevouts, wrapping, &multisig, &files[1..]).unwrap();
+            let results = verify::verify(&secp, &tx, &prevouts, Flags::Standard);
+            assert!(
+                results.iter().all(verify::InputResult::is_valid),
+                "{}",
+                wrapping
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_signature_files() {
+        let secp = Secp256k1::new();
+        let wrapping = Wrapping::P2wsh;
+        let (keys, multisig, mut tx, prevouts) = setup(wrapping);
+        let types = [EcdsaSighashType::All; 2];
+        let file = sign(&secp, &tx, &prevouts, wrapping, &multisig, &keys[0], &types).unwrap();
+
+        let mut swapped = file.clone();
+        swapped.signatures.swap(0, 1);
+        assert!(matches!(
+            swapped.verify(&secp, &tx, &prevouts, wrapping, &multisig),
+            Err(SigFileError::BadSignature { input: 0, .. })
+        ));
+
+        let outsider = key(9);
+        assert!(matches!(
+            sign(&secp, &tx, &prevouts, wrapping, &multisig, &outsider, &types),
+            Err(SigFileError::UnknownKey(_))
+        ));
+
+        assert!(matches!(
+            combine(
+                &secp,
+                &mut tx,
+                &prevouts,
+                wrapping,
+                &multisig,
+                &[file.clone(), file.clone()]
+            ),
+            Err(SigFileError::DuplicateKey(_))
+        ));
+
+        tx.lock_time = PackedLockTime(1);
+        assert!(matches!(
+            file.verify(&secp, &tx, &prevouts, wrapping, &multisig),
+            Err(SigFileError::WrongTransaction { .. })
+        ));
+    }
+}
@@ -0,0 +1,48 @@
+use bitcoin::secp256k1::{ecdsa::Signature, Message, Secp256k1, SecretKey, Signing};
+
+/// Size of every signature `sign` makes, sighash byte included: a DER
+/// encoding with 32-byte R and S and neither padded (70 bytes), plus one.
+pub const SIGNATURE_SIZE: usize = 71;
+
+/// Signs with RFC6979 nonces, grinding like Bitcoin Core's low-R signing:
+/// the first attempt uses no extra entropy, then a little-endian counter in
+/// the first four bytes of it. Unlike Core we also grind away the rare R or
+/// S under 32 bytes, so every signature is exactly `SIGNATURE_SIZE` and a
+/// fee can be set before signing. S is always normalized to the low half.
+pub fn sign<C: Signing>(secp: &Secp256k1<C>, message: &Message, key: &SecretKey) -> Signature {
+    let mut sig = secp.sign_ecdsa(message, key);
+    let mut counter: u32 = 0;
+    let mut extra_entropy = [0u8; 32];
+    loop {
+        sig.normalize_s();
+        if sig.serialize_der().len() == SIGNATURE_SIZE - 1 {
+            return sig;
+        }
+        counter += 1;
+        extra_entropy[..4].copy_from_slice(&counter.to_le_bytes());
+        sig = secp.sign_ecdsa_with_noncedata(message, key, &extra_entropy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::{sha256, Hash};
+
+    #[test]
+    fn signatures_are_fixed_size_and_low_s() {
+        let secp = Secp256k1::new();
+        for i in 0..200u32 {
+            let key = SecretKey::from_slice(&sha256::Hash::hash(&i.to_le_bytes())[..]).unwrap();
+            let digest = sha256::Hash::hash(&(i + 1000).to_le_bytes());
+            let message = Message::from_slice(&digest[..]).unwrap();
+            let sig = sign(&secp, &message, &key);
+            assert_eq!(sig.serialize_der().len(), SIGNATURE_SIZE - 1);
+            let mut normalized = sig;
+            normalized.normalize_s();
+            assert_eq!(normalized, sig, "S is in the high half");
+            secp.verify_ecdsa(&message, &sig, &key.public_key(&secp))
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use crate::builder::InputTemplate;
+use crate::multisig::KeyOrdering;
+use bitcoin::{
+    blockdata::{
+        opcodes,
+        script::{read_scriptint, Builder, Instruction, Script},
+        transaction::{Transaction, TxOut},
+        witness::Witness,
+    },
+    consensus::encode::VarInt,
+    network::constants::Network,
+    secp256k1::{KeyPair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey},
+    util::{
+        schnorr::SchnorrSig,
+        sighash::{self, Prevouts, SchnorrSighashType, SighashCache},
+        taproot::{ControlBlock, LeafVersion, TapLeafHash, TaprootBuilderError, TaprootSpendInfo},
+    },
+    Address, PublicKey,
+};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use std::fmt;
+use std::str::FromStr;
+
+/// BIP341's unspendable internal key: the x coordinate of
+/// `H = lift_x(sha256(G))`, whose discrete log nobody knows, so the output
+/// can only be spent through a script.
+const NUMS_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+/// Policy limit on the keys of a `multi_a` tapscript.
+pub const MAX_PUBKEYS_PER_MULTI_A: usize = 999;
+/// Size of a BIP340 signature with the DEFAULT sighash type, which adds no
+/// sighash byte.
+pub const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// The internal key to use when none is chosen.
+pub fn nums_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&NUMS_KEY).expect("H is on the curve")
+}
+
+/// Parses a key as 32-byte x-only or 33-byte compressed hex.
+pub fn parse_x_only(s: &str) -> Result<XOnlyPublicKey, TaprootError> {
+    let invalid = || TaprootError::Key(s.to_owned());
+    let bytes = hex::decode(s.trim()).map_err(|_| invalid())?;
+    match bytes.len() {
+        32 => XOnlyPublicKey::from_slice(&bytes).map_err(|_| invalid()),
+        33 => PublicKey::from_slice(&bytes)
+            .map(|key| key.inner.x_only_public_key().0)
+            .map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaprootError {
+    /// Not a 32-byte x-only or 33-byte compressed key in hex.
+    Key(String),
+    /// A `--leaf` value not of the form `m:key,key...`.
+    Leaf(String),
+    NoKeys,
+    InvalidThreshold {
+        m: usize,
+        n: usize,
+    },
+    TooManyKeys(usize),
+    DuplicateKey(XOnlyPublicKey),
+    /// The same script in two leaves.
+    DuplicateLeaf(usize),
+    /// The script is not `<key> OP_CHECKSIG (<key> OP_CHECKSIGADD)... <m>
+    /// OP_NUMEQUAL`.
+    NotMultisig,
+    Tree(TaprootBuilderError),
+    NoSuchLeaf(usize),
+    MissingSignatures {
+        have: usize,
+        need: usize,
+    },
+    Sighash(sighash::Error),
+}
+
+impl fmt::Display for TaprootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaprootError::Key(s) => write!(f, "invalid x-only or compressed key `{}`", s),
+            TaprootError::Leaf(s) => {
+                write!(f, "invalid leaf `{}`, expected m:pubkey,pubkey...", s)
+            }
+            TaprootError::NoKeys => write!(f, "at least one public key is required"),
+            TaprootError::InvalidThreshold { m, n } => {
+                write!(f, "threshold {} is not between 1 and {} keys", m, n)
+            }
+            TaprootError::TooManyKeys(n) => write!(
+                f,
+                "{} keys exceed the {}-key tapscript multisig limit",
+                n, MAX_PUBKEYS_PER_MULTI_A
+            ),
+            TaprootError::DuplicateKey(key) => write!(f, "key {} appears more than once", key),
+            TaprootError::DuplicateLeaf(i) => {
+                write!(f, "leaf {} repeats an earlier leaf", i)
+            }
+            TaprootError::NotMultisig => write!(
+                f,
+                "script is not <key> OP_CHECKSIG (<key> OP_CHECKSIGADD)... <m> OP_NUMEQUAL"
+            ),
+            TaprootError::Tree(e) => write!(f, "taproot tree: {}", e),
+            TaprootError::NoSuchLeaf(i) => write!(f, "there is no leaf {}", i),
+            TaprootError::MissingSignatures { have, need } => {
+                write!(f, "{} signatures given, leaf requires {}", have, need)
+            }
+            TaprootError::Sighash(e) => write!(f, "sighash: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TaprootError {}
+
+impl From<TaprootBuilderError> for TaprootError {
+    fn from(e: TaprootBuilderError) -> Self {
+        TaprootError::Tree(e)
+    }
+}
+
+impl From<sighash::Error> for TaprootError {
+    fn from(e: sighash::Error) -> Self {
+        TaprootError::Sighash(e)
+    }
+}
+
+/// A k-of-n tapscript leaf:
+///
+/// ```text
+/// <key1> OP_CHECKSIG <key2> OP_CHECKSIGADD ... <keyn> OP_CHECKSIGADD <k> OP_NUMEQUAL
+/// ```
+///
+/// Every key takes a witness item, a signature or empty, and exactly `k` of
+/// them must be signatures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tapscript {
+    threshold: usize,
+    /// Keys in the order they appear in the script.
+    keys: Vec<XOnlyPublicKey>,
+    script: Script,
+}
+
+impl Tapscript {
+    pub fn new(
+        keys: &[XOnlyPublicKey],
+        threshold: usize,
+        ordering: KeyOrdering,
+    ) -> Result<Self, TaprootError> {
+        let n = keys.len();
+        if n == 0 {
+            return Err(TaprootError::NoKeys);
+        }
+        if n > MAX_PUBKEYS_PER_MULTI_A {
+            return Err(TaprootError::TooManyKeys(n));
+        }
+        if threshold == 0 || threshold > n {
+            return Err(TaprootError::InvalidThreshold { m: threshold, n });
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return Err(TaprootError::DuplicateKey(*key));
+            }
+        }
+
+        let mut keys = keys.to_vec();
+        if ordering == KeyOrdering::SortedMulti {
+            keys.sort_by_key(|key| key.serialize());
+        }
+
+        let mut builder = Builder::new();
+        for (i, key) in keys.iter().enumerate() {
+            builder = builder.push_x_only_key(key).push_opcode(if i == 0 {
+                opcodes::all::OP_CHECKSIG
+            } else {
+                opcodes::all::OP_CHECKSIGADD
+            });
+        }
+        let script = builder
+            .push_int(threshold as i64)
+            .push_opcode(opcodes::all::OP_NUMEQUAL)
+            .into_script();
+
+        Ok(Tapscript {
+            threshold,
+            keys,
+            script,
+        })
+    }
+
+    /// Parses a leaf script of this form, keeping the keys in script order.
+    pub fn from_script(script: &Script) -> Result<Self, TaprootError> {
+        let instructions = script
+            .instructions_minimal()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| TaprootError::NotMultisig)?;
+        let (pairs, tail) = match instructions.len() {
+            len if len >= 4 && len.is_multiple_of(2) => instructions.split_at(len - 2),
+            _ => return Err(TaprootError::NotMultisig),
+        };
+        if tail[1] != Instruction::Op(opcodes::all::OP_NUMEQUAL) {
+            return Err(TaprootError::NotMultisig);
+        }
+        let threshold = match tail[0] {
+            Instruction::Op(op) => {
+                let first = opcodes::all::OP_PUSHNUM_1.to_u8();
+                let last = opcodes::all::OP_PUSHNUM_16.to_u8();
+                if !(first..=last).contains(&op.to_u8()) {
+                    return Err(TaprootError::NotMultisig);
+                }
+                (op.to_u8() - first + 1) as usize
+            }
+            Instruction::PushBytes(bytes) => read_scriptint(bytes)
+                .ok()
+                .and_then(|value| usize::try_from(value).ok())
+                .ok_or(TaprootError::NotMultisig)?,
+        };
+        let keys = pairs
+            .chunks(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let op = if i == 0 {
+                    opcodes::all::OP_CHECKSIG
+                } else {
+                    opcodes::all::OP_CHECKSIGADD
+                };
+                match pair {
+                    [Instruction::PushBytes(bytes), Instruction::Op(found)] if *found == op => {
+                        XOnlyPublicKey::from_slice(bytes).map_err(|_| TaprootError::NotMultisig)
+                    }
+                    _ => Err(TaprootError::NotMultisig),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tapscript = Tapscript::new(&keys, threshold, KeyOrdering::Multi)?;
+        if tapscript.script != *script {
+            return Err(TaprootError::NotMultisig);
+        }
+        Ok(tapscript)
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn keys(&self) -> &[XOnlyPublicKey] {
+        &self.keys
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    pub fn leaf_hash(&self) -> TapLeafHash {
+        TapLeafHash::from_script(&self.script, LeafVersion::TapScript)
+    }
+
+    /// Signs input `index` of the transaction behind `cache` through this
+    /// leaf with every key in `keys` that it has. `prevouts` are the outputs
+    /// spent by all inputs, as BIP341 commits to each of them.
+    pub fn sign<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        cache: &mut SighashCache<&Transaction>,
+        prevouts: &[TxOut],
+        index: usize,
+        keys: &[KeyPair],
+        sighash_type: SchnorrSighashType,
+    ) -> Result<Vec<(XOnlyPublicKey, SchnorrSig)>, TaprootError> {
+        let sighash = cache.taproot_script_spend_signature_hash(
+            index,
+            &Prevouts::All(prevouts),
+            self.leaf_hash(),
+            sighash_type,
+        )?;
+        let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        Ok(keys
+            .iter()
+            .map(|keypair| (keypair.x_only_public_key().0, keypair))
+            .filter(|(key, _)| self.keys.contains(key))
+            .map(|(key, keypair)| {
+                let mut aux_rand = [0u8; 32];
+                OsRng.fill_bytes(&mut aux_rand);
+                let sig = secp.sign_schnorr_with_aux_rand(&message, keypair, &aux_rand);
+                (
+                    key,
+                    SchnorrSig {
+                        sig,
+                        hash_ty: sighash_type,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// The witness items before the script, bottom of the stack first: the
+    /// last key's signature or empty, down to the first key's. `signatures`
+    /// may hold more than `threshold`; only the first `threshold` in key
+    /// order are used, since `OP_NUMEQUAL` wants exactly that many.
+    fn stack(
+        &self,
+        signatures: &[(XOnlyPublicKey, SchnorrSig)],
+    ) -> Result<Vec<Vec<u8>>, TaprootError> {
+        let mut items = Vec::with_capacity(self.keys.len());
+        let mut used = 0;
+        for key in &self.keys {
+            let signature = signatures.iter().find(|(signer, _)| signer == key);
+            match signature {
+                Some((_, signature)) if used < self.threshold => {
+                    items.push(signature.to_vec());
+                    used += 1;
+                }
+                _ => items.push(Vec::new()),
+            }
+        }
+        if used < self.threshold {
+            return Err(TaprootError::MissingSignatures {
+                have: used,
+                need: self.threshold,
+            });
+        }
+        items.reverse();
+        Ok(items)
+    }
+}
+
+impl FromStr for Tapscript {
+    type Err = TaprootError;
+
+    /// Parses `m:key,key...`, keeping the keys in the order given.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TaprootError::Leaf(s.to_owned());
+        let (threshold, keys) = s.split_once(':').ok_or_else(invalid)?;
+        let threshold = threshold.trim().parse().map_err(|_| invalid())?;
+        let keys = keys
+            .split(',')
+            .map(parse_x_only)
+            .collect::<Result<Vec<_>, _>>()?;
+        Tapscript::new(&keys, threshold, KeyOrdering::Multi)
+    }
+}
+
+/// A P2TR output whose script tree holds one or more `Tapscript` leaves,
+/// with a NUMS internal key unless another is chosen.
+#[derive(Debug, Clone)]
+pub struct TaprootMultisig {
+    leaves: Vec<Tapscript>,
+    spend_info: TaprootSpendInfo,
+}
+
+impl TaprootMultisig {
+    /// Puts `leaves` in a balanced tree under `internal_key`, or the NUMS
+    /// key if `None`.
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: Option<XOnlyPublicKey>,
+        leaves: Vec<Tapscript>,
+    ) -> Result<Self, TaprootError> {
+        for (i, leaf) in leaves.iter().enumerate() {
+            if leaves[..i].contains(leaf) {
+                return Err(TaprootError::DuplicateLeaf(i));
+            }
+        }
+        let spend_info = TaprootSpendInfo::with_huffman_tree(
+            secp,
+            internal_key.unwrap_or_else(nums_key),
+            leaves.iter().map(|leaf| (1, leaf.script.clone())),
+        )?;
+        Ok(TaprootMultisig { leaves, spend_info })
+    }
+
+    pub fn leaves(&self) -> &[Tapscript] {
+        &self.leaves
+    }
+
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.spend_info.internal_key()
+    }
+
+    /// Whether the key path is provably unspendable.
+    pub fn is_nums(&self) -> bool {
+        self.internal_key() == nums_key()
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr_tweaked(self.spend_info.output_key(), network)
+    }
+
+    pub fn control_block(&self, leaf: usize) -> Result<ControlBlock, TaprootError> {
+        let leaf = self
+            .leaves
+            .get(leaf)
+            .ok_or(TaprootError::NoSuchLeaf(leaf))?;
+        Ok(self
+            .spend_info
+            .control_block(&(leaf.script.clone(), LeafVersion::TapScript))
+            .expect("every leaf is in the tree"))
+    }
+
+    /// Exact size of the witness spending through `leaf`, with signatures
+    /// of type `sighash_type`.
+    pub fn witness_size(
+        &self,
+        leaf: usize,
+        sighash_type: SchnorrSighashType,
+    ) -> Result<usize, TaprootError> {
+        let control_block = self.control_block(leaf)?.size();
+        let leaf = &self.leaves[leaf];
+        let signature_size = match sighash_type {
+            SchnorrSighashType::Default => SCHNORR_SIGNATURE_SIZE,
+            _ => SCHNORR_SIGNATURE_SIZE + 1,
+        };
+        let script_len = leaf.script.len();
+        Ok(VarInt(leaf.keys.len() as u64 + 2).len()
+            + leaf.keys.len()
+            + leaf.threshold * signature_size
+            + VarInt(script_len as u64).len()
+            + script_len
+            + VarInt(control_block as u64).len()
+            + control_block)
+    }
+
+    /// An input spending through `leaf`, as `builder::build` needs it.
+    pub fn template(
+        &self,
+        leaf: usize,
+        sighash_type: SchnorrSighashType,
+    ) -> Result<InputTemplate, TaprootError> {
+        Ok(InputTemplate::witness_only(
+            self.witness_size(leaf, sighash_type)?,
+        ))
+    }
+
+    /// `<sig or empty>... <script> <control block>`
+    pub fn witness(
+        &self,
+        leaf: usize,
+        signatures: &[(XOnlyPublicKey, SchnorrSig)],
+    ) -> Result<Witness, TaprootError> {
+        let control_block = self.control_block(leaf)?;
+        let leaf = &self.leaves[leaf];
+        let mut witness = Witness::new();
+        for item in leaf.stack(signatures)? {
+            witness.push(item);
+        }
+        witness.push(leaf.script.as_bytes());
+        witness.push(control_block.serialize());
+        Ok(witness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder;
+    use crate::fixture;
+    use crate::prevout;
+    use crate::verify::{self, Flags};
+    use bitcoin::blockdata::locktime::PackedLockTime;
+
+    fn keypair<C: Signing>(secp: &Secp256k1<C>, i: u8) -> KeyPair {
+        KeyPair::from_secret_key(secp, &fixture::secret_key(i))
+    }
+
+    /// Three leaves of different shapes, so the control blocks differ in
+    /// depth: 2-of-3 over keys 1-3, 1-of-1 over key 4 and 2-of-2 over keys
+    /// 4 and 5.
+    fn tree<C: Verification + Signing>(secp: &Secp256k1<C>) -> TaprootMultisig {
+        let key = |i| keypair(secp, i).x_only_public_key().0;
+        let leaves = vec![
+            Tapscript::new(&[key(1), key(2), key(3)], 2, KeyOrdering::Multi).unwrap(),
+            Tapscript::new(&[key(4)], 1, KeyOrdering::Multi).unwrap(),
+            Tapscript::new(&[key(4), key(5)], 2, KeyOrdering::SortedMulti).unwrap(),
+        ];
+        TaprootMultisig::new(secp, None, leaves).unwrap()
+    }
+
+    #[test]
+    fn signed_script_path_spends_verify() {
+        let secp = Secp256k1::new();
+        let multisig = tree(&secp);
+        let keys: Vec<KeyPair> = (1..=5).map(|i| keypair(&secp, i)).collect();
+        let prevouts = fixture::prevout(&multisig.address(Network::Bitcoin).script_pubkey());
+        let spent = prevout::spent_outputs(&prevouts);
+
+        for sighash_type in [SchnorrSighashType::Default, SchnorrSighashType::All] {
+            for (leaf, tapscript) in multisig.leaves().iter().enumerate() {
+                let template = multisig.template(leaf, sighash_type).unwrap();
+                let unsigned = fixture::build(&template, PackedLockTime::ZERO, &prevouts, 40_000);
+                let signatures = tapscript
+                    .sign(
+                        &secp,
+                        &mut SighashCache::new(&unsigned.tx),
+                        &spent,
+                        0,
+                        &keys,
+                        sighash_type,
+                    )
+                    .unwrap();
+                let mut tx = unsigned.tx.clone();
+                tx.input[0].witness = multisig.witness(leaf, &signatures).unwrap();
+
+                let context = format!("leaf {} {}", leaf, sighash_type);
+                assert_eq!(
+                    tx.input[0].witness.serialized_len(),
+                    multisig.witness_size(leaf, sighash_type).unwrap(),
+                    "{}",
+                    context
+                );
+                assert_eq!(
+                    tx.weight(),
+                    builder::signed_weight(&unsigned.tx, &template),
+                    "{}",
+                    context
+                );
+                let results = verify::verify(&secp, &tx, &prevouts, Flags::Standard);
+                assert!(results[0].is_valid(), "{}: {}", context, results[0]);
+
+                let mut items = tx.input[0].witness.to_vec();
+                let signature = items.iter_mut().find(|item| !item.is_empty()).unwrap();
+                signature[0] ^= 1;
+                tx.input[0].witness = Witness::from_vec(items);
+                let results = verify::verify(&secp, &tx, &prevouts, Flags::Standard);
+                assert!(!results[0].is_valid(), "{}", context);
+            }
+        }
+    }
+
+    #[test]
+    fn too_few_signatures_are_refused() {
+        let secp = Secp256k1::new();
+        let multisig = tree(&secp);
+        assert!(matches!(
+            multisig.witness(0, &[]),
+            Err(TaprootError::MissingSignatures { have: 0, need: 2 })
+        ));
+        assert!(matches!(
+            multisig.witness_size(3, SchnorrSighashType::Default),
+            Err(TaprootError::NoSuchLeaf(3))
+        ));
+    }
+}
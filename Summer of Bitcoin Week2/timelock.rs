@@ -0,0 +1,438 @@
+use crate::builder::InputTemplate;
+use crate::finalize::{self, FinalizeError};
+use crate::multisig::{Multisig, MultisigError, MAX_STANDARD_P2WSH_SCRIPT_SIZE};
+use crate::prevout::Prevout;
+use crate::signature::{self, SIGNATURE_SIZE};
+use bitcoin::{
+    blockdata::{
+        locktime::PackedLockTime,
+        opcodes,
+        script::{Builder, Script},
+        transaction::{Sequence, Transaction},
+        witness::Witness,
+    },
+    consensus::encode::VarInt,
+    network::constants::Network,
+    secp256k1::{Message, Secp256k1, Signing},
+    util::sighash::{self, EcdsaSighashType, SighashCache},
+    Address, EcdsaSig, PrivateKey,
+};
+use std::fmt;
+
+/// Lowest `nLockTime` read as a timestamp rather than a height.
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// When the recovery branch opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timelock {
+    /// `OP_CHECKSEQUENCEVERIFY`: this many blocks after the output confirms.
+    Blocks(u16),
+    /// `OP_CHECKLOCKTIMEVERIFY`: from this block height on.
+    Height(u32),
+}
+
+impl Timelock {
+    /// CSV delays of zero blocks and CLTV heights of zero or in the
+    /// timestamp range are not useful locks.
+    pub fn is_valid(self) -> bool {
+        match self {
+            Timelock::Blocks(blocks) => blocks > 0,
+            Timelock::Height(height) => height > 0 && height < LOCKTIME_THRESHOLD,
+        }
+    }
+
+    /// Appends `<value> OP_CHECKSEQUENCEVERIFY|OP_CHECKLOCKTIMEVERIFY OP_DROP`.
+    pub fn push_verify(self, builder: Builder) -> Builder {
+        let (value, verify) = match self {
+            Timelock::Blocks(blocks) => (blocks as i64, opcodes::all::OP_CSV),
+            Timelock::Height(height) => (height as i64, opcodes::all::OP_CLTV),
+        };
+        builder
+            .push_int(value)
+            .push_opcode(verify)
+            .push_opcode(opcodes::all::OP_DROP)
+    }
+
+    /// The `sequence` an input taking the recovery branch needs.
+    pub fn sequence(self) -> Sequence {
+        match self {
+            Timelock::Blocks(blocks) => Sequence::from_height(blocks),
+            // Any non-final sequence makes nLockTime count
+            Timelock::Height(_) => Sequence::ENABLE_LOCKTIME_NO_RBF,
+        }
+    }
+
+    /// The `lock_time` a transaction taking the recovery branch needs.
+    pub fn lock_time(self) -> PackedLockTime {
+        match self {
+            Timelock::Blocks(_) => PackedLockTime::ZERO,
+            Timelock::Height(height) => PackedLockTime(height),
+        }
+    }
+}
+
+impl fmt::Display for Timelock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timelock::Blocks(blocks) => write!(f, "after {} blocks (CSV)", blocks),
+            Timelock::Height(height) => write!(f, "from height {} (CLTV)", height),
+        }
+    }
+}
+
+/// Which side of the `OP_IF` a spend takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    Primary,
+    Recovery,
+}
+
+#[derive(Debug)]
+pub enum TimelockError {
+    Multisig(MultisigError),
+    InvalidTimelock(Timelock),
+    Sighash(sighash::Error),
+    Finalize(FinalizeError),
+}
+
+impl fmt::Display for TimelockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimelockError::Multisig(e) => write!(f, "{}", e),
+            TimelockError::InvalidTimelock(timelock) => {
+                write!(f, "invalid timelock: {}", timelock)
+            }
+            TimelockError::Sighash(e) => write!(f, "sighash: {}", e),
+            TimelockError::Finalize(e) => write!(f, "witness: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TimelockError {}
+
+impl From<MultisigError> for TimelockError {
+    fn from(e: MultisigError) -> Self {
+        TimelockError::Multisig(e)
+    }
+}
+
+impl From<sighash::Error> for TimelockError {
+    fn from(e: sighash::Error) -> Self {
+        TimelockError::Sighash(e)
+    }
+}
+
+impl From<FinalizeError> for TimelockError {
+    fn from(e: FinalizeError) -> Self {
+        TimelockError::Finalize(e)
+    }
+}
+
+/// An m-of-n multisig that a second set of keys can also spend once a
+/// timelock has passed:
+///
+/// ```text
+/// OP_IF
+///     <m> <key>... <n> OP_CHECKMULTISIG
+/// OP_ELSE
+///     <timelock> OP_CHECKSEQUENCEVERIFY|OP_CHECKLOCKTIMEVERIFY OP_DROP
+///     <k> <key>... <j> OP_CHECKMULTISIG    (or <key> OP_CHECKSIG for one key)
+/// OP_ENDIF
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    primary: Multisig,
+    recovery: Multisig,
+    timelock: Timelock,
+    witness_script: Script,
+}
+
+impl RecoveryPolicy {
+    pub fn new(
+        primary: Multisig,
+        recovery: Multisig,
+        timelock: Timelock,
+    ) -> Result<Self, TimelockError> {
+        if !timelock.is_valid() {
+            return Err(TimelockError::InvalidTimelock(timelock));
+        }
+
+        let mut builder = Builder::new().push_opcode(opcodes::all::OP_IF);
+        builder = push_multisig(builder, &primary).push_opcode(opcodes::all::OP_ELSE);
+        builder = timelock.push_verify(builder);
+        builder = if recovery.keys().len() == 1 {
+            builder
+                .push_key(&recovery.keys()[0])
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+        } else {
+            push_multisig(builder, &recovery)
+        };
+        let witness_script = builder.push_opcode(opcodes::all::OP_ENDIF).into_script();
+        if witness_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(MultisigError::ScriptTooLarge(witness_script.len()).into());
+        }
+
+        Ok(RecoveryPolicy {
+            primary,
+            recovery,
+            timelock,
+            witness_script,
+        })
+    }
+
+    pub fn timelock(&self) -> Timelock {
+        self.timelock
+    }
+
+    pub fn witness_script(&self) -> &Script {
+        &self.witness_script
+    }
+
+    /// The keys and threshold that can sign on `branch`.
+    pub fn signers(&self, branch: Branch) -> &Multisig {
+        match branch {
+            Branch::Primary => &self.primary,
+            Branch::Recovery => &self.recovery,
+        }
+    }
+
+    pub fn p2wsh_address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script, network)
+    }
+
+    pub fn p2sh_p2wsh_address(&self, network: Network) -> Address {
+        Address::p2shwsh(&self.witness_script, network)
+    }
+
+    /// Exact size of the signed witness for `branch`.
+    pub fn witness_size(&self, branch: Branch) -> usize {
+        let signers = self.signers(branch);
+        let signatures = signers.threshold();
+        // signatures, the CHECKMULTISIG dummy if any, selector and script
+        let dummy = usize::from(branch == Branch::Primary || signers.keys().len() > 1);
+        let items = signatures + dummy + 2;
+        let selector = usize::from(branch == Branch::Primary);
+        let script_len = self.witness_script.len();
+        VarInt(items as u64).len()
+            + dummy
+            + signatures * (1 + SIGNATURE_SIZE)
+            + 1
+            + selector
+            + VarInt(script_len as u64).len()
+            + script_len
+    }
+
+    /// A P2SH-P2WSH input taking `branch`, with the sequence it needs.
+    pub fn template(&self, branch: Branch) -> InputTemplate {
+        let mut template =
+            InputTemplate::p2sh_wrapped(&self.witness_script, self.witness_size(branch));
+        if branch == Branch::Recovery {
+            template.sequence = self.timelock.sequence();
+        }
+        template
+    }
+
+    /// The `lock_time` a transaction taking `branch` needs.
+    pub fn lock_time(&self, branch: Branch) -> PackedLockTime {
+        match branch {
+            Branch::Primary => PackedLockTime::ZERO,
+            Branch::Recovery => self.timelock.lock_time(),
+        }
+    }
+
+    /// Assembles the witness for `branch` from signatures already in key
+    /// order: the multisig dummy where needed, the signatures, then `1` to
+    /// take the `OP_IF` or an empty push to take the `OP_ELSE`.
+    pub fn witness(&self, branch: Branch, signatures: &[EcdsaSig]) -> Witness {
+        let signers = self.signers(branch);
+        let mut witness = Witness::new();
+        if branch == Branch::Primary || signers.keys().len() > 1 {
+            witness.push([]);
+        }
+        for signature in signatures {
+            witness.push(signature.to_vec());
+        }
+        match branch {
+            Branch::Primary => witness.push([1]),
+            Branch::Recovery => witness.push([]),
+        }
+        witness.push(self.witness_script.as_bytes());
+        witness
+    }
+}
+
+fn push_multisig(builder: Builder, multisig: &Multisig) -> Builder {
+    let mut builder = builder.push_int(multisig.threshold() as i64);
+    for key in multisig.keys() {
+        builder = builder.push_key(key);
+    }
+    builder
+        .push_int(multisig.keys().len() as i64)
+        .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+}
+
+/// Signs every input of `tx` through `branch` of `policy` with `keys`, one
+/// `SighashCache` for all of them. `tx` must already carry the sequence and
+/// lock time from `RecoveryPolicy::template` and `lock_time`.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    tx: &mut Transaction,
+    prevouts: &[Prevout],
+    policy: &RecoveryPolicy,
+    branch: Branch,
+    keys: &[PrivateKey],
+    sighash_types: &[EcdsaSighashType],
+) -> Result<(), TimelockError> {
+    let script = policy.witness_script();
+    let signers = policy.signers(branch);
+    let mut cache = SighashCache::new(&*tx);
+    let mut witnesses = Vec::with_capacity(prevouts.len());
+    for (index, (prevout, &sighash_type)) in prevouts.iter().zip(sighash_types).enumerate() {
+        let amount = prevout.amount.to_sat();
+        let sighash = cache.segwit_signature_hash(index, script, amount, sighash_type)?;
+        let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        let signatures: Vec<EcdsaSig> = keys
+            .iter()
+            .map(|key| EcdsaSig {
+                sig: signature::sign(secp, &message, &key.inner),
+                hash_ty: sighash_type,
+            })
+            .collect();
+
+        let ordered = finalize::order_signatures(signers, &signatures, |hash_ty| {
+            let sighash = cache
+                .segwit_signature_hash(index, script, amount, hash_ty)
+                .expect("sighash for an existing input");
+            Message::from_slice(&sighash[..]).expect("sighash is 32 bytes")
+        })?;
+        witnesses.push(policy.witness(branch, &ordered));
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder;
+    use crate::fixture;
+    use crate::multisig::KeyOrdering;
+    use crate::verify::{self, Flags};
+
+    /// 2-of-3 over keys 1-3, recoverable by `recovery` of keys 4 and 5.
+    fn policy(recovery: usize, timelock: Timelock) -> RecoveryPolicy {
+        let secp = Secp256k1::new();
+        let public_keys: Vec<_> = fixture::keys(5)
+            .iter()
+            .map(|key| key.public_key(&secp))
+            .collect();
+        let primary = Multisig::new(&public_keys[..3], 2, KeyOrdering::SortedMulti).unwrap();
+        let recovery =
+            Multisig::new(&public_keys[3..3 + recovery], recovery, KeyOrdering::Multi).unwrap();
+        RecoveryPolicy::new(primary, recovery, timelock).unwrap()
+    }
+
+    /// Spends one output of `policy` through `branch`.
+    fn spend(policy: &RecoveryPolicy, branch: Branch) -> (Transaction, Vec<Prevout>, usize) {
+        let secp = Secp256k1::new();
+        let prevouts =
+            fixture::prevout(&policy.p2sh_p2wsh_address(Network::Bitcoin).script_pubkey());
+        let template = policy.template(branch);
+        let unsigned = fixture::build(&template, policy.lock_time(branch), &prevouts, 40_000);
+        let mut tx = unsigned.tx.clone();
+        let keys = fixture::keys(5);
+        let signers = match branch {
+            Branch::Primary => &keys[1..3],
+            Branch::Recovery => &keys[3..3 + policy.signers(branch).keys().len()],
+        };
+        sign(
+            &secp,
+            &mut tx,
+            &prevouts,
+            policy,
+            branch,
+            signers,
+            &[EcdsaSighashType::All],
+        )
+        .unwrap();
+        (
+            tx,
+            prevouts,
+            builder::signed_weight(&unsigned.tx, &template),
+        )
+    }
+
+    #[test]
+    fn signed_spends_match_estimates_and_verify() {
+        let secp = Secp256k1::verification_only();
+        for timelock in [Timelock::Blocks(144), Timelock::Height(800_000)] {
+            for recovery in [1, 2] {
+                let policy = policy(recovery, timelock);
+                for branch in [Branch::Primary, Branch::Recovery] {
+                    let (tx, prevouts, weight) = spend(&policy, branch);
+                    let context = format!("{} {}-key recovery {:?}", timelock, recovery, branch);
+                    assert_eq!(
+                        tx.input[0].witness.serialized_len(),
+                        policy.witness_size(branch),
+                        "{}",
+                        context
+                    );
+                    assert_eq!(tx.weight(), weight, "{}", context);
+                    let results = verify::verify(&secp, &tx, &prevouts, Flags::Standard);
+                    assert!(results[0].is_valid(), "{}: {}", context, results[0]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recovery_fails_before_the_timelock() {
+        let secp = Secp256k1::verification_only();
+        let csv = policy(1, Timelock::Blocks(144));
+        let (mut tx, prevouts, _) = spend(&csv, Branch::Recovery);
+        // Changing the sequence invalidates the signature too, but the
+        // interpreter stops at OP_CHECKSEQUENCEVERIFY first.
+        tx.input[0].sequence = Sequence::from_height(143);
+        assert!(!verify::verify(&secp, &tx, &prevouts, Flags::Consensus)[0].is_valid());
+
+        let cltv = policy(1, Timelock::Height(800_000));
+        let (mut tx, prevouts, _) = spend(&cltv, Branch::Recovery);
+        tx.lock_time = PackedLockTime(799_999);
+        assert!(!verify::verify(&secp, &tx, &prevouts, Flags::Consensus)[0].is_valid());
+    }
+
+    #[test]
+    fn csv_sets_sequence_and_cltv_sets_lock_time() {
+        let csv = policy(1, Timelock::Blocks(144));
+        assert_eq!(
+            csv.template(Branch::Recovery).sequence,
+            Sequence::from_height(144)
+        );
+        assert_eq!(csv.lock_time(Branch::Recovery), PackedLockTime::ZERO);
+
+        let cltv = policy(1, Timelock::Height(800_000));
+        let sequence = cltv.template(Branch::Recovery).sequence;
+        assert!(sequence.enables_absolute_lock_time());
+        assert!(!sequence.is_relative_lock_time());
+        assert_eq!(cltv.lock_time(Branch::Recovery), PackedLockTime(800_000));
+
+        for policy in [csv, cltv] {
+            assert_eq!(policy.template(Branch::Primary).sequence, Sequence::MAX);
+            assert_eq!(policy.lock_time(Branch::Primary), PackedLockTime::ZERO);
+        }
+    }
+
+    #[test]
+    fn rejects_useless_timelocks() {
+        for timelock in [
+            Timelock::Blocks(0),
+            Timelock::Height(0),
+            Timelock::Height(LOCKTIME_THRESHOLD),
+        ] {
+            assert!(!timelock.is_valid(), "{}", timelock);
+        }
+        assert!(Timelock::Height(LOCKTIME_THRESHOLD - 1).is_valid());
+    }
+}
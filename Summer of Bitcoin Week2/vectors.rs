@@ -0,0 +1,381 @@
+//! Sighash, signing and verification checked against published vectors:
+//! BIP143, BIP341's wallet vectors, Bitcoin Core's `sighash.json` and
+//! `tx_valid.json`, and golden copies of the `out.txt` Week2 writes.
+
+use crate::builder::{self, Recipient, Wrapping};
+use crate::multisig::{KeyOrdering, Multisig};
+use crate::prevout::Prevout;
+use crate::verify::{self, Flags};
+use bitcoin::{
+    blockdata::{
+        locktime::PackedLockTime,
+        script::Script,
+        transaction::{OutPoint, Transaction, TxOut},
+        witness::Witness,
+    },
+    consensus::encode::{deserialize, serialize_hex},
+    hashes::Hash,
+    network::constants::Network,
+    secp256k1::{KeyPair, Message, Secp256k1, SecretKey, XOnlyPublicKey},
+    util::{
+        amount::Amount,
+        sighash::{EcdsaSighashType, Prevouts, SchnorrSighashType, SighashCache},
+        taproot::{LeafVersion, TapBranchHash, TapLeafHash, TapTweakHash},
+    },
+    Address, PrivateKey, Txid,
+};
+use serde_json::Value;
+use std::str::FromStr;
+
+fn json(text: &str) -> Value {
+    serde_json::from_str(text).expect("test data is JSON")
+}
+
+fn bytes(value: &Value) -> Vec<u8> {
+    hex::decode(value.as_str().expect("hex string")).expect("valid hex")
+}
+
+fn tx(value: &Value) -> Transaction {
+    deserialize(&bytes(value)).expect("valid transaction")
+}
+
+/// Core's test data lists comments as one-element arrays among the cases.
+fn cases(data: &Value) -> impl Iterator<Item = &Vec<Value>> {
+    data.as_array()
+        .expect("array of cases")
+        .iter()
+        .filter_map(Value::as_array)
+        .filter(|case| case.len() > 1)
+}
+
+#[test]
+fn bip143_sighashes() {
+    let data = json(include_str!("test_data/bip143.json"));
+    for case in data.as_array().unwrap() {
+        let tx = tx(&case["tx"]);
+        let script_code = Script::from(bytes(&case["scriptCode"]));
+        let hash_type =
+            EcdsaSighashType::from_standard(case["hashType"].as_u64().unwrap() as u32).unwrap();
+        let mut cache = SighashCache::new(&tx);
+        let message = builder::signature_message(
+            &mut cache,
+            &tx,
+            Wrapping::P2wsh,
+            case["index"].as_u64().unwrap() as usize,
+            &script_code,
+            case["amount"].as_u64().unwrap(),
+            hash_type,
+        )
+        .unwrap();
+        assert_eq!(
+            message[..],
+            bytes(&case["sigHash"])[..],
+            "{}",
+            case["description"]
+        );
+    }
+}
+
+#[test]
+fn core_sighash_json() {
+    let data = json(include_str!("test_data/sighash.json"));
+    for case in cases(&data) {
+        let tx = tx(&case[0]);
+        let script = Script::from(bytes(&case[1]));
+        let index = case[2].as_u64().unwrap() as usize;
+        // Core stores the hash type as a signed 32-bit integer
+        let hash_type = case[3].as_i64().unwrap() as i32 as u32;
+        // and the result in the reversed order it displays hashes in
+        let mut expected = bytes(&case[4]);
+        expected.reverse();
+
+        let cache = SighashCache::new(&tx);
+        let sighash = cache
+            .legacy_signature_hash(index, &script, hash_type)
+            .unwrap();
+        assert_eq!(sighash[..], expected[..], "{}", case[0]);
+
+        // The ones Week2 can sign with go through its own legacy P2SH path,
+        // which refuses the SINGLE bug instead of signing the constant 1
+        if let Ok(hash_type) = EcdsaSighashType::from_standard(hash_type) {
+            let mut cache = SighashCache::new(&tx);
+            let message = builder::signature_message(
+                &mut cache,
+                &tx,
+                Wrapping::P2sh,
+                index,
+                &script,
+                0,
+                hash_type,
+            );
+            if builder::split_anyonecanpay(hash_type).0 == EcdsaSighashType::Single
+                && index >= tx.output.len()
+            {
+                assert!(message.is_err(), "{}", case[0]);
+            } else {
+                assert_eq!(message.unwrap()[..], expected[..], "{}", case[0]);
+            }
+        }
+    }
+}
+
+#[test]
+fn bip341_key_path_spending() {
+    let secp = Secp256k1::new();
+    let data = json(include_str!("test_data/bip341_wallet_vectors.json"));
+    for vector in data["keyPathSpending"].as_array().unwrap() {
+        let given = &vector["given"];
+        let tx = tx(&given["rawUnsignedTx"]);
+        let spent: Vec<TxOut> = given["utxosSpent"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|utxo| TxOut {
+                value: utxo["amountSats"].as_u64().unwrap(),
+                script_pubkey: Script::from(bytes(&utxo["scriptPubKey"])),
+            })
+            .collect();
+
+        let mut cache = SighashCache::new(&tx);
+        for input in vector["inputSpending"].as_array().unwrap() {
+            let index = input["given"]["txinIndex"].as_u64().unwrap() as usize;
+            let hash_type = SchnorrSighashType::from_consensus_u8(
+                input["given"]["hashType"].as_u64().unwrap() as u8,
+            )
+            .unwrap();
+            let sighash = cache
+                .taproot_key_spend_signature_hash(index, &Prevouts::All(&spent), hash_type)
+                .unwrap();
+            assert_eq!(sighash[..], bytes(&input["intermediary"]["sigHash"])[..]);
+
+            let secret_key =
+                SecretKey::from_slice(&bytes(&input["given"]["internalPrivkey"])).unwrap();
+            let merkle_root = input["given"]["merkleRoot"]
+                .as_str()
+                .map(|root| TapBranchHash::from_str(root).unwrap());
+            let keypair = KeyPair::from_secret_key(&secp, &secret_key);
+            let tweak =
+                TapTweakHash::from_key_and_tweak(keypair.x_only_public_key().0, merkle_root);
+            assert_eq!(tweak[..], bytes(&input["intermediary"]["tweak"])[..]);
+            let tweaked = keypair.add_xonly_tweak(&secp, &tweak.to_scalar()).unwrap();
+            assert_eq!(
+                tweaked.secret_bytes()[..],
+                bytes(&input["intermediary"]["tweakedPrivkey"])[..]
+            );
+
+            // The vectors sign with all-zero auxiliary randomness
+            let message = Message::from_slice(&sighash[..]).unwrap();
+            let signature = secp.sign_schnorr_with_aux_rand(&message, &tweaked, &[0; 32]);
+            let mut witness = signature.as_ref().to_vec();
+            if hash_type != SchnorrSighashType::Default {
+                witness.push(hash_type as u8);
+            }
+            assert_eq!(witness, bytes(&input["expected"]["witness"][0]));
+        }
+    }
+}
+
+#[test]
+fn bip341_script_pubkeys() {
+    let secp = Secp256k1::verification_only();
+    let data = json(include_str!("test_data/bip341_wallet_vectors.json"));
+    for vector in data["scriptPubKey"].as_array().unwrap() {
+        let intermediary = &vector["intermediary"];
+        if let Some(leaf_hashes) = intermediary["leafHashes"].as_array() {
+            let mut leaves = Vec::new();
+            collect_leaves(&vector["given"]["scriptTree"], &mut leaves);
+            leaves.sort_by_key(|leaf| leaf["id"].as_u64());
+            for (leaf, expected) in leaves.iter().zip(leaf_hashes) {
+                let version =
+                    LeafVersion::from_consensus(leaf["leafVersion"].as_u64().unwrap() as u8)
+                        .unwrap();
+                let hash = TapLeafHash::from_script(&Script::from(bytes(&leaf["script"])), version);
+                assert_eq!(hash[..], bytes(expected)[..]);
+            }
+        }
+
+        let internal_key =
+            XOnlyPublicKey::from_slice(&bytes(&vector["given"]["internalPubkey"])).unwrap();
+        let merkle_root = intermediary["merkleRoot"]
+            .as_str()
+            .map(|root| TapBranchHash::from_str(root).unwrap());
+        let tweak = TapTweakHash::from_key_and_tweak(internal_key, merkle_root);
+        assert_eq!(tweak[..], bytes(&intermediary["tweak"])[..]);
+
+        let address = Address::p2tr(&secp, internal_key, merkle_root, Network::Bitcoin);
+        assert_eq!(address.to_string(), vector["expected"]["bip350Address"]);
+        assert_eq!(
+            address.script_pubkey().as_bytes(),
+            &bytes(&vector["expected"]["scriptPubKey"])[..]
+        );
+    }
+}
+
+/// The leaves of a BIP341 vector's script tree: a leaf object, or an array
+/// of two subtrees.
+fn collect_leaves<'a>(tree: &'a Value, leaves: &mut Vec<&'a Value>) {
+    match tree {
+        Value::Array(branches) => {
+            for branch in branches {
+                collect_leaves(branch, leaves);
+            }
+        }
+        Value::Object(_) => leaves.push(tree),
+        _ => {}
+    }
+}
+
+/// Core's scriptPubKey notation, limited to the raw `0x` hex tokens the
+/// cases here use.
+fn core_script(notation: &str) -> Script {
+    let mut script = Vec::new();
+    for token in notation.split_whitespace() {
+        let hex = token.strip_prefix("0x").expect("raw hex token");
+        script.extend(hex::decode(hex).unwrap());
+    }
+    Script::from(script)
+}
+
+fn tx_valid_cases() -> Vec<(Transaction, Vec<Prevout>)> {
+    let data = json(include_str!("test_data/tx_valid.json"));
+    cases(&data)
+        .map(|case| {
+            let prevouts = case[0]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|prevout| Prevout {
+                    outpoint: OutPoint {
+                        txid: Txid::from_str(prevout[0].as_str().unwrap()).unwrap(),
+                        vout: prevout[1].as_u64().unwrap() as u32,
+                    },
+                    script_pubkey: core_script(prevout[2].as_str().unwrap()),
+                    amount: Amount::from_sat(prevout[3].as_u64().unwrap()),
+                })
+                .collect();
+            (tx(&case[1]), prevouts)
+        })
+        .collect()
+}
+
+#[test]
+fn core_tx_valid_json() {
+    let secp = Secp256k1::verification_only();
+    for (tx, prevouts) in tx_valid_cases() {
+        for (input, prevout) in tx.input.iter().zip(&prevouts) {
+            assert_eq!(input.previous_output, prevout.outpoint);
+        }
+        for result in verify::verify(&secp, &tx, &prevouts, Flags::Standard) {
+            assert!(result.is_valid(), "{}: {}", tx.txid(), result);
+        }
+    }
+}
+
+#[test]
+fn tx_valid_cases_fail_when_tampered() {
+    let secp = Secp256k1::verification_only();
+    for (tx, prevouts) in tx_valid_cases() {
+        for index in 0..tx.input.len() {
+            // Flip a bit inside the first signature, whichever of the
+            // witness or scriptSig carries it
+            let mut tampered = tx.clone();
+            let input = &mut tampered.input[index];
+            let mut witness = input.witness.to_vec();
+            if let Some(item) = witness.iter_mut().find(|item| item.len() >= 64) {
+                item[10] ^= 1;
+                input.witness = Witness::from_vec(witness);
+            } else {
+                let mut script_sig = input.script_sig.to_bytes();
+                script_sig[10] ^= 1;
+                input.script_sig = Script::from(script_sig);
+            }
+            let results = verify::verify(&secp, &tampered, &prevouts, Flags::Standard);
+            assert!(!results[index].is_valid(), "{} input {}", tx.txid(), index);
+        }
+    }
+}
+
+/// The 2-of-2 key pair Week2 has always signed its example spend with.
+fn week2_keys() -> Vec<PrivateKey> {
+    [
+        "39dc0a9f0b185a2ee56349691f34716e6e0cda06a7f9707742ac113c4e2317bf",
+        "5077ccd9c558b7d04a81920d38aa11b4a9f9de3b23fab45c3ef28039920fdd6d",
+    ]
+    .iter()
+    .map(|key| {
+        PrivateKey::new(
+            SecretKey::from_slice(&hex::decode(key).unwrap()).unwrap(),
+            Network::Bitcoin,
+        )
+    })
+    .collect()
+}
+
+/// Builds and signs the spend `build --sorted --wrapping <wrapping>` makes
+/// of 100000 sats at the zero outpoint, paying `to` with change back to the
+/// multisig.
+fn week2_spend(wrapping: Wrapping, to: &str, fee_rate: f64) -> Transaction {
+    let secp = Secp256k1::new();
+    let keys = week2_keys();
+    let public_keys: Vec<_> = keys.iter().map(|key| key.public_key(&secp)).collect();
+    let multisig = Multisig::new(&public_keys, 2, KeyOrdering::SortedMulti).unwrap();
+    let script_pubkey = wrapping
+        .address(&multisig, Network::Bitcoin)
+        .unwrap()
+        .script_pubkey();
+    let prevouts = [Prevout {
+        outpoint: OutPoint::new(Txid::all_zeros(), 0),
+        amount: Amount::from_sat(100_000),
+        script_pubkey: script_pubkey.clone(),
+    }];
+    let recipients = [Recipient::parse(to, Network::Bitcoin).unwrap()];
+    let unsigned = builder::build(
+        &wrapping.template(&multisig),
+        PackedLockTime::ZERO,
+        &prevouts,
+        &recipients,
+        &script_pubkey,
+        fee_rate,
+    )
+    .unwrap();
+    let mut tx = unsigned.tx;
+    builder::sign(
+        &secp,
+        &mut tx,
+        &prevouts,
+        wrapping,
+        multisig.witness_script(),
+        &keys,
+        &[EcdsaSighashType::All],
+    )
+    .unwrap();
+    tx
+}
+
+#[test]
+fn golden_out_txt() {
+    let cases = [
+        (
+            Wrapping::P2shP2wsh,
+            "325UUecEQuyrTd28Xs2hvAxdAjHM7XzqVF:100000",
+            0.0,
+            include_str!("test_data/out_p2sh_p2wsh.txt"),
+        ),
+        (
+            Wrapping::P2wsh,
+            "bc1qf493rks6gnh7k2yzsf7qhp0meqknpfs0ej43f5pjyfh4g2940jmqknwqr9:99000",
+            1.0,
+            include_str!("test_data/out_p2wsh.txt"),
+        ),
+        (
+            Wrapping::P2sh,
+            "39C5GaHDocqYVgyCq2TrMfknTL45LPPcKF:99000",
+            1.0,
+            include_str!("test_data/out_p2sh.txt"),
+        ),
+    ];
+    for (wrapping, to, fee_rate, golden) in cases {
+        let tx = week2_spend(wrapping, to, fee_rate);
+        assert_eq!(serialize_hex(&tx), golden.trim(), "{}", wrapping);
+    }
+}
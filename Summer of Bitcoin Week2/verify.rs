@@ -0,0 +1,602 @@
+use crate::prevout::{self, Prevout};
+use crate::taproot::Tapscript;
+use bitcoin::{
+    bitcoinconsensus,
+    blockdata::{
+        script,
+        script::Script,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    consensus::encode::serialize,
+    hashes::Hash,
+    secp256k1::{Message, Secp256k1, Verification, XOnlyPublicKey},
+    util::{
+        schnorr::SchnorrSig,
+        sighash::{Annex, Prevouts, SighashCache},
+        taproot::{ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX},
+    },
+    EcdsaSig, PubkeyHash, PublicKey,
+};
+use std::fmt;
+
+/// Script verification flags. libbitcoinconsensus only accepts the consensus
+/// flags, so `Standard` adds Core's signature policy checks on top of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flags {
+    /// P2SH, DERSIG, NULLDUMMY, CHECKLOCKTIMEVERIFY, CHECKSEQUENCEVERIFY and
+    /// WITNESS, as enforced in blocks.
+    Consensus,
+    /// Consensus plus, for relay, LOW_S and the STRICTENC signature
+    /// encoding on every ECDSA signature of a P2PK, P2PKH, P2SH, P2WPKH or
+    /// P2WSH spend, and WITNESS_PUBKEYTYPE on the keys of the segwit ones.
+    Standard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    /// libbitcoinconsensus rejected the spend.
+    Consensus(bitcoinconsensus::Error),
+    /// Stack item `item`, counted from the bottom of the scriptSig pushes
+    /// or the witness, is not a strict DER signature with a defined sighash
+    /// type.
+    SigEncoding { item: usize },
+    /// Stack item `item` has an S value in the upper half of the order.
+    HighS { item: usize },
+    /// Stack item `item` verifies against none of the spend's keys.
+    BadSignature { item: usize },
+    /// A segwit v0 spend has a key that is not 33 bytes.
+    UncompressedKey,
+    /// The control block does not commit to the revealed tapscript.
+    ControlBlock,
+    /// A tapscript multisig witness with the wrong number of items, or a
+    /// number of signatures other than the threshold.
+    TapscriptStack,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Consensus(bitcoinconsensus::Error::ERR_SCRIPT) => {
+                write!(f, "script evaluation failed")
+            }
+            ScriptError::Consensus(e) => write!(f, "libbitcoinconsensus: {}", e),
+            ScriptError::SigEncoding { item } => {
+                write!(f, "stack item {} is not a strictly encoded signature", item)
+            }
+            ScriptError::HighS { item } => {
+                write!(f, "stack item {} is a high-S signature", item)
+            }
+            ScriptError::BadSignature { item } => {
+                write!(f, "stack item {} does not verify against any key", item)
+            }
+            ScriptError::UncompressedKey => {
+                write!(f, "segwit spend has an uncompressed key")
+            }
+            ScriptError::ControlBlock => {
+                write!(f, "control block does not commit to the tapscript")
+            }
+            ScriptError::TapscriptStack => write!(
+                f,
+                "tapscript needs one item per key and exactly threshold signatures"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputResult {
+    pub index: usize,
+    pub outpoint: OutPoint,
+    /// Empty when the input verified.
+    pub errors: Vec<ScriptError>,
+}
+
+impl InputResult {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for InputResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input {} ({}): ", self.index, self.outpoint)?;
+        if self.is_valid() {
+            return write!(f, "ok");
+        }
+        write!(f, "FAIL")?;
+        for error in &self.errors {
+            write!(f, "; {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Verifies every input of `tx` against the output it spends. `prevouts`
+/// must be in input order.
+///
+/// # Panics
+///
+/// If there is not exactly one prevout per input.
+pub fn verify<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    prevouts: &[Prevout],
+    flags: Flags,
+) -> Vec<InputResult> {
+    assert_eq!(
+        prevouts.len(),
+        tx.input.len(),
+        "verify needs one prevout per input"
+    );
+    let spending = serialize(tx);
+    let spent = prevout::spent_outputs(prevouts);
+    let mut cache = SighashCache::new(tx);
+    tx.input
+        .iter()
+        .zip(prevouts)
+        .enumerate()
+        .map(|(index, (input, prevout))| {
+            let mut errors = Vec::new();
+            let consensus = prevout.script_pubkey.verify_with_flags(
+                index,
+                prevout.amount,
+                &spending,
+                bitcoinconsensus::VERIFY_ALL,
+            );
+            if let Err(e) = consensus {
+                errors.push(ScriptError::Consensus(match e {
+                    script::Error::BitcoinConsensus(e) => e,
+                    _ => bitcoinconsensus::Error::ERR_SCRIPT,
+                }));
+            }
+            // libbitcoinconsensus does not say why a script failed, so look
+            // at the signatures ourselves.
+            if !errors.is_empty() || flags == Flags::Standard {
+                errors.extend(check_signatures(
+                    secp, &mut cache, input, index, prevout, flags,
+                ));
+            }
+            if prevout.script_pubkey.is_v1_p2tr() {
+                errors.extend(check_taproot_witness(
+                    secp, &mut cache, input, index, &spent,
+                ));
+            }
+            InputResult {
+                index,
+                outpoint: input.previous_output,
+                errors,
+            }
+        })
+        .collect()
+}
+
+/// Where an input's signatures are and what they sign, for the kinds of
+/// non-taproot spend whose layout we know.
+struct Spend {
+    /// The scriptSig pushes or witness items, less any script revealed.
+    items: Vec<Vec<u8>>,
+    /// The script the signatures commit to.
+    script_code: Script,
+    /// Every key a signature may be by: those pushed by the script code, or
+    /// for P2PKH and P2WPKH the one on the stack.
+    keys: Vec<Vec<u8>>,
+    segwit: bool,
+}
+
+impl Spend {
+    fn of(input: &TxIn, prevout: &Prevout) -> Option<Spend> {
+        let mut pushes = pushes(&input.script_sig)?;
+        let mut program = prevout.script_pubkey.clone();
+        if program.is_p2sh() {
+            program = Script::from(pushes.pop()?);
+            if !program.is_witness_program() {
+                return Some(Spend {
+                    keys: script_keys(&program),
+                    items: pushes,
+                    script_code: program,
+                    segwit: false,
+                });
+            }
+        }
+
+        let mut witness = input.witness.to_vec();
+        if program.is_v0_p2wsh() {
+            let script = Script::from(witness.pop()?);
+            Some(Spend {
+                keys: script_keys(&script),
+                items: witness,
+                script_code: script,
+                segwit: true,
+            })
+        } else if program.is_v0_p2wpkh() {
+            let key = witness.get(1)?.clone();
+            Some(Spend {
+                script_code: Script::new_p2pkh(&PubkeyHash::hash(&key)),
+                keys: vec![key],
+                items: witness,
+                segwit: true,
+            })
+        } else if program.is_p2pkh() {
+            Some(Spend {
+                script_code: program,
+                keys: pushes.get(1).cloned().into_iter().collect(),
+                items: pushes,
+                segwit: false,
+            })
+        } else if program.is_p2pk() {
+            Some(Spend {
+                keys: script_keys(&program),
+                script_code: program,
+                items: pushes,
+                segwit: false,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks every ECDSA signature of a P2PK, P2PKH, P2SH, P2WPKH or P2WSH
+/// spend, in the scriptSig or the witness, and under `Flags::Standard` the
+/// keys of segwit ones. A stack item counts as a signature if it is shaped
+/// like one; any other spend is left to libbitcoinconsensus, P2TR ones to
+/// `check_taproot_witness`.
+fn check_signatures<C: Verification>(
+    secp: &Secp256k1<C>,
+    cache: &mut SighashCache<&Transaction>,
+    input: &TxIn,
+    index: usize,
+    prevout: &Prevout,
+    flags: Flags,
+) -> Vec<ScriptError> {
+    let spend = match Spend::of(input, prevout) {
+        Some(spend) => spend,
+        None => return Vec::new(),
+    };
+    if flags == Flags::Standard && spend.segwit && spend.keys.iter().any(|key| key.len() != 33) {
+        return vec![ScriptError::UncompressedKey];
+    }
+    let keys: Vec<PublicKey> = spend
+        .keys
+        .iter()
+        .filter_map(|key| PublicKey::from_slice(key).ok())
+        .collect();
+
+    let mut errors = Vec::new();
+    for (item, bytes) in spend.items.iter().enumerate() {
+        if !is_signature_shaped(bytes) {
+            continue;
+        }
+        let signature = match EcdsaSig::from_slice(bytes) {
+            Ok(signature) => signature,
+            Err(_) => {
+                errors.push(ScriptError::SigEncoding { item });
+                continue;
+            }
+        };
+        if flags == Flags::Standard {
+            let mut normalized = signature.sig;
+            normalized.normalize_s();
+            if normalized != signature.sig {
+                errors.push(ScriptError::HighS { item });
+                continue;
+            }
+        }
+        let sighash = if spend.segwit {
+            cache
+                .segwit_signature_hash(
+                    index,
+                    &spend.script_code,
+                    prevout.amount.to_sat(),
+                    signature.hash_ty,
+                )
+                .map(|sighash| sighash.into_inner())
+        } else {
+            cache
+                .legacy_signature_hash(index, &spend.script_code, signature.hash_ty.to_u32())
+                .map(|sighash| sighash.into_inner())
+        };
+        let message = match sighash {
+            Ok(sighash) => Message::from_slice(&sighash).expect("sighash is 32 bytes"),
+            Err(_) => continue,
+        };
+        if !keys.iter().any(|key| {
+            secp.verify_ecdsa(&message, &signature.sig, &key.inner)
+                .is_ok()
+        }) {
+            errors.push(ScriptError::BadSignature { item });
+        }
+    }
+    errors
+}
+
+/// Whether `bytes` is a DER sequence whose length byte covers all but the
+/// sighash type, as every signature is. Preimages and branch selectors
+/// are almost never shaped so.
+fn is_signature_shaped(bytes: &[u8]) -> bool {
+    (9..=73).contains(&bytes.len()) && bytes[0] == 0x30 && bytes[1] as usize == bytes.len() - 3
+}
+
+/// The data pushed by a push-only script, or `None` if it runs opcodes.
+fn pushes(script: &Script) -> Option<Vec<Vec<u8>>> {
+    script
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(script::Instruction::PushBytes(bytes)) => Some(bytes.to_vec()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The pushes of `script` sized as public keys.
+fn script_keys(script: &Script) -> Vec<Vec<u8>> {
+    script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(script::Instruction::PushBytes(bytes)) if bytes.len() == 33 || bytes.len() == 65 => {
+                Some(bytes.to_vec())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks a P2TR key-path signature, or the signatures of a script-path
+/// spend through a `Tapscript` leaf. libbitcoinconsensus predates taproot and
+/// passes any P2TR spend, so these are the only checks such inputs get.
+/// Leaves of any other kind are not checked.
+fn check_taproot_witness<C: Verification>(
+    secp: &Secp256k1<C>,
+    cache: &mut SighashCache<&Transaction>,
+    input: &TxIn,
+    index: usize,
+    spent: &[TxOut],
+) -> Vec<ScriptError> {
+    let output_key = match XOnlyPublicKey::from_slice(&spent[index].script_pubkey[2..]) {
+        Ok(key) => key,
+        Err(_) => return vec![ScriptError::Consensus(bitcoinconsensus::Error::ERR_SCRIPT)],
+    };
+    let mut items: Vec<&[u8]> = input.witness.iter().collect();
+    let annex = match items[..] {
+        [.., last] if items.len() >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => {
+            items.pop();
+            Annex::new(last).ok()
+        }
+        _ => None,
+    };
+    let prevouts = Prevouts::All(spent);
+    let mut check = |item: usize, bytes: &[u8], key: &XOnlyPublicKey, leaf| {
+        let signature =
+            SchnorrSig::from_slice(bytes).map_err(|_| ScriptError::SigEncoding { item })?;
+        let sighash = cache
+            .taproot_signature_hash(index, &prevouts, annex.clone(), leaf, signature.hash_ty)
+            .map_err(|_| ScriptError::BadSignature { item })?;
+        let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        secp.verify_schnorr(&signature.sig, &message, key)
+            .map_err(|_| ScriptError::BadSignature { item })
+    };
+
+    let (stack, script, control_block) = match items[..] {
+        [signature] => {
+            return check(0, signature, &output_key, None)
+                .err()
+                .into_iter()
+                .collect()
+        }
+        [ref stack @ .., script, control_block] => (stack, script, control_block),
+        _ => return vec![ScriptError::Consensus(bitcoinconsensus::Error::ERR_SCRIPT)],
+    };
+    let script = Script::from(script.to_vec());
+    match ControlBlock::from_slice(control_block) {
+        Ok(control_block) if control_block.verify_taproot_commitment(secp, output_key, &script) => {
+            if control_block.leaf_version != LeafVersion::TapScript {
+                return Vec::new();
+            }
+        }
+        _ => return vec![ScriptError::ControlBlock],
+    }
+    let tapscript = match Tapscript::from_script(&script) {
+        Ok(tapscript) => tapscript,
+        Err(_) => return Vec::new(),
+    };
+    if stack.len() != tapscript.keys().len() {
+        return vec![ScriptError::TapscriptStack];
+    }
+
+    // The first key's item is on top of the stack, so last in the witness
+    let leaf = Some((
+        TapLeafHash::from_script(&script, LeafVersion::TapScript),
+        u32::MAX,
+    ));
+    let mut errors = Vec::new();
+    let mut signatures = 0;
+    for (item, (bytes, key)) in stack.iter().zip(tapscript.keys().iter().rev()).enumerate() {
+        if bytes.is_empty() {
+            continue;
+        }
+        signatures += 1;
+        if let Err(e) = check(item, bytes, key, leaf) {
+            errors.push(e);
+        }
+    }
+    if signatures != tapscript.threshold() {
+        errors.push(ScriptError::TapscriptStack);
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{self, Wrapping};
+    use crate::fixture::{self, key};
+    use crate::multisig::{KeyOrdering, Multisig};
+    use crate::signature;
+    use bitcoin::{
+        blockdata::{locktime::PackedLockTime, opcodes, script::Builder, witness::Witness},
+        network::constants::Network,
+        secp256k1::{ecdsa::Signature, SecretKey},
+        util::sighash::EcdsaSighashType,
+        WPubkeyHash,
+    };
+
+    /// A 2-of-3 spend signed by the first two keys.
+    fn signed(wrapping: Wrapping) -> (Transaction, Vec<Prevout>) {
+        let secp = Secp256k1::new();
+        let keys = fixture::keys(3);
+        let public_keys: Vec<_> = keys.iter().map(|key| key.public_key(&secp)).collect();
+        let multisig = Multisig::new(&public_keys, 2, KeyOrdering::Multi).unwrap();
+        let address = wrapping.address(&multisig, Network::Bitcoin).unwrap();
+        let prevouts = fixture::prevout(&address.script_pubkey());
+        let mut tx = fixture::build(
+            &wrapping.template(&multisig),
+            PackedLockTime::ZERO,
+            &prevouts,
+            40_000,
+        )
+        .tx;
+        builder::sign(
+            &secp,
+            &mut tx,
+            &prevouts,
+            wrapping,
+            multisig.witness_script(),
+            &keys[..2],
+            &[EcdsaSighashType::All],
+        )
+        .unwrap();
+        (tx, prevouts)
+    }
+
+    /// Replaces stack item `item` of input 0, wherever the spend keeps it.
+    fn replace_item(tx: &mut Transaction, item: usize, f: impl Fn(&[u8]) -> Vec<u8>) {
+        let input = &mut tx.input[0];
+        if input.witness.is_empty() {
+            let mut pushes = pushes(&input.script_sig).unwrap();
+            pushes[item] = f(&pushes[item]);
+            input.script_sig = pushes
+                .iter()
+                .fold(Builder::new(), |builder, push| builder.push_slice(push))
+                .into_script();
+        } else {
+            let mut items = input.witness.to_vec();
+            items[item] = f(&items[item]);
+            input.witness = Witness::from_vec(items);
+        }
+    }
+
+    /// The same signature with S negated, still valid to consensus.
+    fn high_s(bytes: &[u8]) -> Vec<u8> {
+        let signature = EcdsaSig::from_slice(bytes).unwrap();
+        let mut compact = signature.sig.serialize_compact();
+        let s = SecretKey::from_slice(&compact[32..]).unwrap().negate();
+        compact[32..].copy_from_slice(&s.secret_bytes());
+        EcdsaSig {
+            sig: Signature::from_compact(&compact).unwrap(),
+            hash_ty: signature.hash_ty,
+        }
+        .to_vec()
+    }
+
+    fn errors(tx: &Transaction, prevouts: &[Prevout], flags: Flags) -> Vec<ScriptError> {
+        let secp = Secp256k1::verification_only();
+        verify(&secp, tx, prevouts, flags).remove(0).errors
+    }
+
+    #[test]
+    fn signed_spends_verify() {
+        for wrapping in Wrapping::ALL {
+            let (tx, prevouts) = signed(wrapping);
+            assert_eq!(errors(&tx, &prevouts, Flags::Standard), [], "{}", wrapping);
+        }
+    }
+
+    #[test]
+    fn high_s_is_policy_only() {
+        // Legacy P2SH keeps its signatures in the scriptSig
+        for wrapping in Wrapping::ALL {
+            let (mut tx, prevouts) = signed(wrapping);
+            replace_item(&mut tx, 1, high_s);
+            assert_eq!(errors(&tx, &prevouts, Flags::Consensus), [], "{}", wrapping);
+            assert_eq!(
+                errors(&tx, &prevouts, Flags::Standard),
+                [ScriptError::HighS { item: 1 }],
+                "{}",
+                wrapping
+            );
+        }
+    }
+
+    #[test]
+    fn bad_signature_is_located() {
+        for wrapping in Wrapping::ALL {
+            let (mut tx, prevouts) = signed(wrapping);
+            // Relabelled as SIGHASH_NONE, it signs the wrong message
+            replace_item(&mut tx, 2, |bytes| {
+                let mut bytes = bytes.to_vec();
+                *bytes.last_mut().unwrap() = EcdsaSighashType::None as u8;
+                bytes
+            });
+            let errors = errors(&tx, &prevouts, Flags::Consensus);
+            assert!(
+                matches!(
+                    errors[..],
+                    [
+                        ScriptError::Consensus(_),
+                        ScriptError::BadSignature { item: 2 }
+                    ]
+                ),
+                "{}: {:?}",
+                wrapping,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn uncompressed_segwit_key_is_non_standard() {
+        let secp = Secp256k1::new();
+        let mut public_key = key(1).public_key(&secp);
+        public_key.compressed = false;
+        let script = Builder::new()
+            .push_key(&public_key)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let prevouts = fixture::prevout(&script.to_v0_p2wsh());
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevouts[0].outpoint,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 40_000,
+                script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+            }],
+        };
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(0, &script, 50_000, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let signature = EcdsaSig::sighash_all(signature::sign(&secp, &message, &key(1).inner));
+        tx.input[0].witness = Witness::from_vec(vec![signature.to_vec(), script.to_bytes()]);
+
+        assert_eq!(errors(&tx, &prevouts, Flags::Consensus), []);
+        assert_eq!(
+            errors(&tx, &prevouts, Flags::Standard),
+            [ScriptError::UncompressedKey]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "one prevout per input")]
+    fn needs_one_prevout_per_input() {
+        let secp = Secp256k1::verification_only();
+        let (tx, mut prevouts) = signed(Wrapping::P2wsh);
+        prevouts.push(prevouts[0].clone());
+        verify(&secp, &tx, &prevouts, Flags::Standard);
+    }
+}